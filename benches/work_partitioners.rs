@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::SeqNo;
+use atlas_core::request_pre_processing::work_dividers::{WDConsistentHash, WDHashMixing, WDLoadAware, WDRoundRobin};
+use atlas_core::request_pre_processing::WorkPartitioner;
+
+const WORKER_COUNT: usize = 8;
+
+/// Clients are usually given clustered ids (for example, 1000 to 1999), with one session each
+fn clustered_sessions() -> Vec<(NodeId, SeqNo)> {
+    (1000..2000u32)
+        .map(|client| (NodeId::from(client), SeqNo::from(0u32)))
+        .collect()
+}
+
+/// Clients with many sessions each
+fn multi_session_clients() -> Vec<(NodeId, SeqNo)> {
+    (1000..1032u32)
+        .flat_map(|client| (0..32u32).map(move |session| (NodeId::from(client), SeqNo::from(session))))
+        .collect()
+}
+
+/// The ratio between the most and the least loaded worker
+fn skew<WP>(partitioner: &WP, sessions: &[(NodeId, SeqNo)], worker_count: usize) -> f64 where WP: WorkPartitioner<()> {
+    let mut load = vec![0usize; worker_count];
+
+    for (client, session) in sessions {
+        load[partitioner.get_worker_for_raw(*client, *session, worker_count)] += 1;
+    }
+
+    let max = *load.iter().max().unwrap() as f64;
+    let min = *load.iter().min().unwrap() as f64;
+
+    if min == 0.0 { f64::INFINITY } else { max / min }
+}
+
+/// The fraction of sessions which change worker when going from `from` to `to` workers
+fn moved_on_resize<WP>(partitioner: &WP, sessions: &[(NodeId, SeqNo)], from: usize, to: usize) -> f64 where WP: WorkPartitioner<()> {
+    let moved = sessions.iter()
+        .filter(|(client, session)| partitioner.get_worker_for_raw(*client, *session, from) != partitioner.get_worker_for_raw(*client, *session, to))
+        .count();
+
+    moved as f64 / sessions.len() as f64
+}
+
+/// Each measurement gets a fresh partitioner, since stateful partitioners remember their placements
+fn report<WP>(name: &str, partitioner: fn() -> WP) where WP: WorkPartitioner<()> {
+    println!("{}: skew (clustered) {:.2}, skew (multi session) {:.2}, moved on resize {} -> {} {:.2}",
+             name,
+             skew(&partitioner(), &clustered_sessions(), WORKER_COUNT),
+             skew(&partitioner(), &multi_session_clients(), WORKER_COUNT),
+             WORKER_COUNT, WORKER_COUNT + 1,
+             moved_on_resize(&partitioner(), &clustered_sessions(), WORKER_COUNT, WORKER_COUNT + 1));
+}
+
+fn bench_partitioner<WP>(c: &mut Criterion, name: &str, partitioner: fn() -> WP) where WP: WorkPartitioner<()> {
+    report(name, partitioner);
+
+    let wp = partitioner();
+
+    let sessions = clustered_sessions();
+
+    c.bench_function(&format!("partition {}", name), |b| {
+        b.iter(|| {
+            for (client, session) in &sessions {
+                black_box(wp.get_worker_for_raw(*client, *session, WORKER_COUNT));
+            }
+        })
+    });
+}
+
+fn work_partitioners(c: &mut Criterion) {
+    bench_partitioner(c, "round robin", || WDRoundRobin);
+    bench_partitioner(c, "hash mixing", || WDHashMixing);
+    bench_partitioner(c, "consistent hash", || WDConsistentHash);
+    bench_partitioner(c, "load aware", WDLoadAware::default);
+}
+
+criterion_group!(benches, work_partitioners);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+fn main() {
+    #[cfg(feature = "serialize_capnp")]
+    {
+        println!("cargo:rerun-if-changed=schemas/messages.capnp");
+
+        capnpc::CompilerCommand::new()
+            .src_prefix("schemas")
+            .default_parent_module(vec!["serialize".into(), "capnp".into()])
+            .file("schemas/messages.capnp")
+            .run()
+            .expect("Failed to compile the capnp schemas");
+    }
+}
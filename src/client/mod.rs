@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+use std::iter;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+
+use log::{debug, error, warn};
+
+use atlas_common::channel::{ChannelSyncRx, ChannelSyncTx, new_bounded_sync, TryRecvError};
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::{Orderable, SeqNo};
+use atlas_communication::protocol_node::{NodeIncomingRqHandler, ProtocolNetworkNode};
+use atlas_execution::serialize::ApplicationData;
+
+use crate::messages::{RejectionReason, ReplyMessage, RequestMessage, RequestRejectedMessage, SystemMessage};
+use crate::reconfiguration_protocol::QuorumUpdateMessage;
+use crate::serialize::ClientServiceMsg;
+use crate::smr::exec::unordered::{UnorderedQuorumOutcome, UnorderedReplyQuorum};
+
+const CLIENT_RCV_TIMEOUT: Option<Duration> = Some(Duration::from_millis(1));
+const QUORUM_UPDATE_QUEUE_SIZE: usize = 128;
+const CLIENT_RECEIVER_THREAD_NAME: &str = "CLIENT-RECEIVER";
+
+/// The replicas never accept operation zero of a session, so the operations of a session start at one
+const FIRST_OPERATION_ID: u32 = 1;
+
+/// The configuration of a client session
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// The id of this client's session
+    pub session_id: SeqNo,
+    /// How long we wait for a reply quorum before retransmitting the request
+    pub request_timeout: Duration,
+    /// How many times a request is retransmitted before we give up on it
+    pub max_retransmissions: usize,
+    /// How many operations of this session can be outstanding at the same time.
+    /// This must not be larger than the session window of the replicas' pre processor
+    pub max_outstanding: usize,
+}
+
+/// A client session, which issues requests to the replicas and waits for a quorum of replies.
+///
+/// Requests are numbered sequentially within the session, and up to [ClientConfig::max_outstanding]
+/// of them can be in flight at once (see [Client::invoke_pipelined]). Ordered requests are accepted once
+/// f + 1 replicas reply with the same result. Unordered requests need 2f + 1 replicas which executed
+/// over the same state, and are resubmitted as ordered requests when the replicas diverge.
+pub struct Client<D, NT> where D: ApplicationData {
+    config: ClientConfig,
+    node: Arc<NT>,
+    next_operation: Mutex<SeqNo>,
+    /// The amount of operations that are currently outstanding
+    outstanding: Mutex<usize>,
+    shared: Arc<ClientShared<D>>,
+}
+
+/// The state shared between the client and its receiving thread
+struct ClientShared<D> where D: ApplicationData {
+    /// The current members of the quorum
+    quorum: RwLock<Vec<NodeId>>,
+    /// The replica we believe to be the leader, to which requests are sent first
+    leader_hint: RwLock<Option<NodeId>>,
+    /// The requests that are waiting for a reply quorum, by session and operation id
+    pending: Mutex<HashMap<(SeqNo, SeqNo), PendingRequest<D>>>,
+}
+
+/// How the replies to a request are being collected
+enum ReplyCollector<D> where D: ApplicationData {
+    Ordered(OrderedReplyQuorum<D>),
+    Unordered(UnorderedReplyQuorum<D>),
+}
+
+struct PendingRequest<D> where D: ApplicationData {
+    collector: ReplyCollector<D>,
+    rejected_by: Vec<NodeId>,
+    responder: ChannelSyncTx<ClientResponse<D::Reply>>,
+}
+
+/// The result of a request, as seen by the receiving thread
+enum ClientResponse<R> {
+    Reply(ReplyMessage<R>),
+    Rejected(RequestRejectedMessage),
+    FallbackToOrdered,
+}
+
+/// Collects the replies to an ordered request, accepting the reply
+/// once `quorum` replicas replied with the same (serialized) payload
+struct OrderedReplyQuorum<D> where D: ApplicationData {
+    quorum: usize,
+    responded: Vec<NodeId>,
+    votes: Vec<(Vec<u8>, usize, ReplyMessage<D::Reply>)>,
+}
+
+impl<D, NT> Client<D, NT>
+    where D: ApplicationData + 'static,
+          NT: ProtocolNetworkNode<ClientServiceMsg<D>> + 'static {
+    /// Create a new client session over the given network node, with the initial members of the quorum.
+    ///
+    /// Returns the client along with the channel that should be handed to the reconfiguration protocol
+    /// (as a [crate::reconfiguration_protocol::ReconfigurableNodeTypes::ClientNode]) so the client
+    /// keeps track of the quorum members
+    pub fn new(config: ClientConfig, node: Arc<NT>, quorum: Vec<NodeId>) -> (Self, ChannelSyncTx<QuorumUpdateMessage>) {
+        let (quorum_tx, quorum_rx) = new_bounded_sync(QUORUM_UPDATE_QUEUE_SIZE);
+
+        let shared = Arc::new(ClientShared {
+            quorum: RwLock::new(quorum),
+            leader_hint: RwLock::new(None),
+            pending: Mutex::new(HashMap::new()),
+        });
+
+        let weak_shared = Arc::downgrade(&shared);
+        let receiving_node = node.clone();
+
+        std::thread::Builder::new()
+            .name(format!("{}-{:?}", CLIENT_RECEIVER_THREAD_NAME, config.session_id))
+            .spawn(move || {
+                run_client_receiver(weak_shared, receiving_node, quorum_rx);
+            }).expect("Failed to launch client receiver thread");
+
+        let client = Self {
+            config,
+            node,
+            next_operation: Mutex::new(SeqNo::from(FIRST_OPERATION_ID)),
+            outstanding: Mutex::new(0),
+            shared,
+        };
+
+        (client, quorum_tx)
+    }
+
+    pub fn session_id(&self) -> SeqNo {
+        self.config.session_id
+    }
+
+    /// The current members of the quorum, as far as this client knows
+    pub fn quorum_members(&self) -> Vec<NodeId> {
+        self.shared.quorum.read().unwrap().clone()
+    }
+
+    /// Hint the client about the current leader, so requests are first sent only to it.
+    /// Retransmissions are always sent to every member of the quorum
+    pub fn set_leader_hint(&self, leader: Option<NodeId>) {
+        *self.shared.leader_hint.write().unwrap() = leader;
+    }
+
+    /// Submit an ordered request and wait for f + 1 matching replies.
+    /// Fails if there are already [ClientConfig::max_outstanding] outstanding operations
+    pub fn invoke(&self, operation: D::Request) -> Result<D::Reply> where D::Request: Clone {
+        self.invoke_pipelined(operation)?.wait()
+    }
+
+    /// Submit an ordered request without waiting for its reply, so many operations of this session
+    /// can be outstanding at the same time.
+    /// Fails (without sending anything) if there are already [ClientConfig::max_outstanding] outstanding operations,
+    /// in which case some of the pending replies must be waited for (or dropped) first
+    pub fn invoke_pipelined(&self, operation: D::Request) -> Result<PendingReply<'_, D, NT>> where D::Request: Clone {
+        let mut pending = self.invoke_batch(vec![operation])?;
+
+        Ok(pending.pop().unwrap())
+    }
+
+    /// Submit a batch of ordered requests, which are coalesced into as few network sends as possible.
+    /// Returns the pending replies in the same order as the operations.
+    /// Fails (without sending anything) if the batch does not fit in the window of outstanding operations
+    pub fn invoke_batch(&self, operations: Vec<D::Request>) -> Result<Vec<PendingReply<'_, D, NT>>> where D::Request: Clone {
+        self.acquire_outstanding_slots(operations.len())?;
+
+        let quorum = max_faults(self.quorum_members().len()) + 1;
+
+        let pending: Vec<PendingReply<'_, D, NT>> = operations.into_iter().map(|operation| {
+            let request = RequestMessage::new(self.session_id(), self.next_operation_id(), operation);
+
+            self.register(request, ReplyCollector::Ordered(OrderedReplyQuorum::new(quorum)), false)
+        }).collect();
+
+        let leader = *self.shared.leader_hint.read().unwrap();
+
+        let targets = match leader {
+            Some(leader) => vec![leader],
+            None => self.quorum_members(),
+        };
+
+        self.transmit(pending.iter().map(|pending| &pending.request), &targets, false);
+
+        Ok(pending)
+    }
+
+    /// Submit a read only request, which is executed without being ordered, and wait for 2f + 1
+    /// replicas to reply the same over the same state. If the replicas diverge, the request is
+    /// submitted again as an ordered request
+    pub fn invoke_unordered(&self, operation: D::Request) -> Result<D::Reply> where D::Request: Clone {
+        let replica_count = self.quorum_members().len();
+        let quorum = 2 * max_faults(replica_count) + 1;
+
+        self.acquire_outstanding_slots(1)?;
+
+        let request = RequestMessage::new(self.session_id(), self.next_operation_id(), operation.clone());
+
+        let collector = ReplyCollector::Unordered(UnorderedReplyQuorum::new(quorum, replica_count));
+
+        let pending = self.register(request, collector, true);
+
+        self.transmit(iter::once(&pending.request), &self.quorum_members(), true);
+
+        let response = pending.wait_for_response();
+
+        // Release the outstanding slot before we (possibly) resubmit the operation
+        drop(pending);
+
+        match response? {
+            ClientResponse::Reply(reply) => Ok(reply.into_inner().2),
+            ClientResponse::Rejected(rejected) => Err(Error::simple_with_msg(ErrorKind::Communication,
+                                                                            rejection_message(&rejected))),
+            ClientResponse::FallbackToOrdered => {
+                debug!("Unordered request of session {:?} diverged, falling back to ordered execution", self.session_id());
+
+                self.invoke(operation)
+            }
+        }
+    }
+
+    fn next_operation_id(&self) -> SeqNo {
+        let mut next_operation = self.next_operation.lock().unwrap();
+
+        let operation_id = *next_operation;
+
+        *next_operation = operation_id.next();
+
+        operation_id
+    }
+
+    /// Take the given amount of outstanding slots. Each of them is released when its [PendingReply] is dropped
+    fn acquire_outstanding_slots(&self, count: usize) -> Result<()> {
+        let max_outstanding = self.config.max_outstanding.max(1);
+
+        let mut outstanding = self.outstanding.lock().unwrap();
+
+        if *outstanding + count > max_outstanding {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "The window of outstanding operations of the session is full"));
+        }
+
+        *outstanding += count;
+
+        Ok(())
+    }
+
+    /// Register a request as pending, so the receiving thread can deliver its replies
+    fn register(&self, request: RequestMessage<D::Request>, collector: ReplyCollector<D>, unordered: bool) -> PendingReply<'_, D, NT> {
+        let (tx, rx) = new_bounded_sync(1);
+
+        self.shared.pending.lock().unwrap().insert((request.session_id(), request.sequence_number()), PendingRequest {
+            collector,
+            rejected_by: Vec::new(),
+            responder: tx,
+        });
+
+        PendingReply {
+            client: self,
+            request,
+            rx,
+            unordered,
+        }
+    }
+
+    /// Send the requests to the given targets, only flushing the connection after the last request
+    fn transmit<'a>(&self, requests: impl Iterator<Item=&'a RequestMessage<D::Request>> + Clone, targets: &Vec<NodeId>, unordered: bool)
+        where D::Request: Clone + 'a {
+        for target in targets {
+            let mut requests = requests.clone().peekable();
+
+            while let Some(request) = requests.next() {
+                let message = if unordered {
+                    SystemMessage::UnorderedRequest(request.clone())
+                } else {
+                    SystemMessage::OrderedRequest(request.clone())
+                };
+
+                let flush = requests.peek().is_none();
+
+                if let Err(err) = self.node.send(message, *target, flush) {
+                    error!("Failed to send request {:?} of session {:?} to {:?}: {:?}", request.sequence_number(), self.session_id(), target, err);
+                }
+            }
+        }
+    }
+}
+
+impl<D, NT> Client<D, NT> where D: ApplicationData {
+    fn release_outstanding_slot(&self) {
+        *self.outstanding.lock().unwrap() -= 1;
+    }
+}
+
+/// A request that has been sent to the replicas and is waiting for its reply quorum.
+/// Dropping it abandons the request
+pub struct PendingReply<'a, D, NT> where D: ApplicationData {
+    client: &'a Client<D, NT>,
+    request: RequestMessage<D::Request>,
+    rx: ChannelSyncRx<ClientResponse<D::Reply>>,
+    unordered: bool,
+}
+
+impl<'a, D, NT> PendingReply<'a, D, NT>
+    where D: ApplicationData + 'static,
+          NT: ProtocolNetworkNode<ClientServiceMsg<D>> + 'static {
+    pub fn operation_id(&self) -> SeqNo {
+        self.request.sequence_number()
+    }
+
+    /// Wait for the reply quorum, retransmitting the request when it times out
+    pub fn wait(self) -> Result<D::Reply> where D::Request: Clone {
+        match self.wait_for_response()? {
+            ClientResponse::Reply(reply) => Ok(reply.into_inner().2),
+            ClientResponse::Rejected(rejected) => Err(Error::simple_with_msg(ErrorKind::Communication,
+                                                                            rejection_message(&rejected))),
+            ClientResponse::FallbackToOrdered => unreachable!("Ordered requests never fall back"),
+        }
+    }
+
+    fn wait_for_response(&self) -> Result<ClientResponse<D::Reply>> where D::Request: Clone {
+        let config = &self.client.config;
+
+        for attempt in 0..=config.max_retransmissions {
+            match self.rx.recv_timeout(config.request_timeout) {
+                Ok(response) => return Ok(response),
+                Err(TryRecvError::Timeout) if attempt < config.max_retransmissions => {
+                    warn!("Request {:?} of session {:?} timed out, retransmitting to the whole quorum (attempt {})",
+                        self.operation_id(), self.client.session_id(), attempt + 1);
+
+                    let members = self.client.quorum_members();
+
+                    // The quorum may have changed since the request was sent, so the replies
+                    // it needs must follow the current view before we wait for them again
+                    self.client.shared.refresh_reply_quorum(&(self.request.session_id(), self.request.sequence_number()), members.len());
+
+                    self.client.transmit(iter::once(&self.request), &members, self.unordered);
+                }
+                Err(TryRecvError::Timeout) => break,
+                Err(_) => {
+                    return Err(Error::simple_with_msg(ErrorKind::Communication, "Client receiver has disconnected"));
+                }
+            }
+        }
+
+        Err(Error::simple_with_msg(ErrorKind::Communication, "Failed to collect a reply quorum for the request"))
+    }
+}
+
+impl<'a, D, NT> Drop for PendingReply<'a, D, NT> where D: ApplicationData {
+    fn drop(&mut self) {
+        self.client.shared.pending.lock().unwrap().remove(&(self.request.session_id(), self.request.sequence_number()));
+
+        self.client.release_outstanding_slot();
+    }
+}
+
+impl<D> ClientShared<D> where D: ApplicationData {
+    /// Replace the members of the quorum, recomputing the reply quorum of every pending request
+    fn update_quorum(&self, members: Vec<NodeId>) {
+        let replica_count = members.len();
+
+        *self.quorum.write().unwrap() = members;
+
+        let keys: Vec<(SeqNo, SeqNo)> = self.pending.lock().unwrap().keys().cloned().collect();
+
+        for key in keys {
+            self.refresh_reply_quorum(&key, replica_count);
+        }
+    }
+
+    /// Recompute the amount of replies the given request needs from the size of the current quorum,
+    /// delivering its response if the replies it already collected are now enough
+    fn refresh_reply_quorum(&self, key: &(SeqNo, SeqNo), replica_count: usize) {
+        let mut pending = self.pending.lock().unwrap();
+
+        let response = match pending.get_mut(key) {
+            Some(request) => request.collector.update_quorum(replica_count),
+            None => return,
+        };
+
+        if let Some(response) = response {
+            if let Some(request) = pending.remove(key) {
+                let _ = request.responder.send(response);
+            }
+        }
+    }
+
+    fn handle_reply(&self, from: NodeId, reply: ReplyMessage<D::Reply>) {
+        if !self.quorum.read().unwrap().contains(&from) {
+            return;
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+
+        let operation_id = reply.sequence_number();
+        let key = (reply.session_id(), operation_id);
+
+        let request = match pending.get_mut(&key) {
+            Some(request) => request,
+            None => return,
+        };
+
+        let response = match &mut request.collector {
+            ReplyCollector::Ordered(collector) => {
+                collector.register_reply(from, reply).map(|reply| reply.map(ClientResponse::Reply))
+            }
+            ReplyCollector::Unordered(collector) => {
+                collector.register_reply(from, reply).map(|outcome| match outcome {
+                    UnorderedQuorumOutcome::Pending => None,
+                    UnorderedQuorumOutcome::Accepted(reply) => Some(ClientResponse::Reply(reply)),
+                    UnorderedQuorumOutcome::FallbackToOrdered => Some(ClientResponse::FallbackToOrdered),
+                })
+            }
+        };
+
+        match response {
+            Ok(Some(response)) => {
+                if let Some(request) = pending.remove(&key) {
+                    let _ = request.responder.send(response);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("Failed to process reply from {:?} to operation {:?}: {:?}", from, operation_id, err);
+            }
+        }
+    }
+
+    /// A request is only considered rejected when f + 1 replicas reject it,
+    /// since a single faulty replica could be lying
+    fn handle_rejection(&self, from: NodeId, rejected: RequestRejectedMessage) {
+        let quorum_size = {
+            let quorum = self.quorum.read().unwrap();
+
+            if !quorum.contains(&from) {
+                return;
+            }
+
+            quorum.len()
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+
+        let key = (rejected.session_id(), rejected.sequence_number());
+
+        let reached = match pending.get_mut(&key) {
+            Some(request) if !request.rejected_by.contains(&from) => {
+                request.rejected_by.push(from);
+
+                request.rejected_by.len() > max_faults(quorum_size)
+            }
+            _ => false,
+        };
+
+        if reached {
+            if let Some(request) = pending.remove(&key) {
+                let _ = request.responder.send(ClientResponse::Rejected(rejected));
+            }
+        }
+    }
+}
+
+impl<D> ReplyCollector<D> where D: ApplicationData {
+    /// Follow a quorum of the given size, returning the response if the collected replies already reach it
+    fn update_quorum(&mut self, replica_count: usize) -> Option<ClientResponse<D::Reply>> {
+        match self {
+            ReplyCollector::Ordered(collector) => {
+                collector.update_quorum(max_faults(replica_count) + 1).map(ClientResponse::Reply)
+            }
+            ReplyCollector::Unordered(collector) => {
+                match collector.update_quorum(2 * max_faults(replica_count) + 1, replica_count) {
+                    UnorderedQuorumOutcome::Pending => None,
+                    UnorderedQuorumOutcome::Accepted(reply) => Some(ClientResponse::Reply(reply)),
+                    UnorderedQuorumOutcome::FallbackToOrdered => Some(ClientResponse::FallbackToOrdered),
+                }
+            }
+        }
+    }
+}
+
+impl<D> OrderedReplyQuorum<D> where D: ApplicationData {
+    fn new(quorum: usize) -> Self {
+        Self {
+            quorum,
+            responded: Vec::new(),
+            votes: Vec::new(),
+        }
+    }
+
+    fn update_quorum(&mut self, quorum: usize) -> Option<ReplyMessage<D::Reply>> {
+        self.quorum = quorum;
+
+        self.votes.iter()
+            .find(|(_, count, _)| *count >= self.quorum)
+            .map(|(_, _, reply)| reply.clone())
+    }
+
+    fn register_reply(&mut self, from: NodeId, reply: ReplyMessage<D::Reply>) -> Result<Option<ReplyMessage<D::Reply>>> {
+        if self.responded.contains(&from) {
+            return Ok(None);
+        }
+
+        self.responded.push(from);
+
+        let mut payload = Vec::new();
+
+        D::serialize_reply(&mut payload, reply.payload())?;
+
+        let count = match self.votes.iter_mut().find(|(voted, _, _)| *voted == payload) {
+            Some((_, count, _)) => {
+                *count += 1;
+
+                *count
+            }
+            None => {
+                self.votes.push((payload, 1, reply.clone()));
+
+                1
+            }
+        };
+
+        if count >= self.quorum {
+            Ok(Some(reply))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn run_client_receiver<D, NT>(shared: Weak<ClientShared<D>>, node: Arc<NT>, quorum_updates: ChannelSyncRx<QuorumUpdateMessage>)
+    where D: ApplicationData + 'static,
+          NT: ProtocolNetworkNode<ClientServiceMsg<D>> + 'static {
+    loop {
+        // The client has been dropped, so there is no one left to deliver replies to
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => break,
+        };
+
+        while let Ok(update) = quorum_updates.try_recv() {
+            match update {
+                QuorumUpdateMessage::UpdatedQuorumView(members) => {
+                    debug!("Client quorum view updated to {:?}", members);
+
+                    shared.update_quorum(members);
+                }
+            }
+        }
+
+        let message = match node.node_incoming_rq_handling().receive_from_replicas(CLIENT_RCV_TIMEOUT) {
+            Ok(Some(message)) => message,
+            Ok(None) => continue,
+            Err(err) => {
+                error!("Failed to receive messages from the replicas: {:?}", err);
+
+                continue;
+            }
+        };
+
+        let (header, message) = message.into_inner();
+
+        match message {
+            SystemMessage::OrderedReply(reply) | SystemMessage::UnorderedReply(reply) => {
+                shared.handle_reply(header.from(), reply);
+            }
+            SystemMessage::RequestRejected(rejected) => {
+                shared.handle_rejection(header.from(), rejected);
+            }
+            _ => {
+                warn!("Client received an unexpected message from {:?}", header.from());
+            }
+        }
+    }
+}
+
+/// The maximum amount of faulty replicas tolerated by a quorum of the given size
+#[inline]
+fn max_faults(quorum_size: usize) -> usize {
+    quorum_size.saturating_sub(1) / 3
+}
+
+fn rejection_message(rejected: &RequestRejectedMessage) -> &'static str {
+    match rejected.reason() {
+        RejectionReason::Overloaded => "The request was rejected since the replicas are overloaded",
+        RejectionReason::Shed => "The request was shed by the replicas",
+        RejectionReason::Invalid => "The request was refused as invalid by the replicas",
+    }
+}
@@ -15,3 +15,4 @@ pub mod persistent_log;
 pub mod reconfiguration_protocol;
 pub mod log_transfer;
 pub mod smr;
+pub mod client;
@@ -8,7 +8,7 @@ use atlas_communication::protocol_node::ProtocolNetworkNode;
 use atlas_smr_application::serialize::ApplicationData;
 
 use crate::log_transfer::networking::serialize::LogTransferMessage;
-use crate::messages::LogTransfer;
+use crate::messages::{ClientRqInfo, LogTransfer, StoredRequestMessage};
 use crate::ordering_protocol::OrderingProtocol;
 use crate::ordering_protocol::stateful_order_protocol::StatefulOrderProtocol;
 use crate::persistent_log::{PersistentDecisionLog, StatefulOrderingProtocolLog};
@@ -27,8 +27,29 @@ pub enum LTResult<D: ApplicationData> {
     /// The log transfer protocol has finished and the ordering protocol should now
     /// be proceeded. The requests contained are requests that must be executed by the application
     /// in order to reach the state that corresponds to the decision log
-    /// FirstSeq and LastSeq of the installed log downloaded from other replicas and the requests that should be executed
-    LTPFinished(SeqNo, SeqNo, Vec<D::Request>),
+    /// FirstSeq and LastSeq of the installed log downloaded from other replicas and the requests that should be executed.
+    /// The client request information of the installed log is also provided, so it can be delivered to the request
+    /// pre processor as decided, which will prevent the requests from being proposed again.
+    /// This should be built with [LTResult::finished]
+    LTPFinished(SeqNo, SeqNo, Vec<D::Request>, Vec<ClientRqInfo>),
+}
+
+impl<D: ApplicationData> LTResult<D> {
+    /// The log transfer protocol has finished, having installed the given requests
+    /// (as returned by [DecisionLog::install_log] or [StatefulOrderProtocol::install_state])
+    pub fn finished(first: SeqNo, last: SeqNo, installed: Vec<StoredRequestMessage<D::Request>>) -> Self {
+        let rq_infos = installed.iter().map(ClientRqInfo::from).collect();
+
+        let mut requests = Vec::with_capacity(installed.len());
+
+        for request in installed {
+            let (_, message) = request.into_inner();
+
+            requests.push(message.into_inner_operation());
+        }
+
+        LTResult::LTPFinished(first, last, requests, rq_infos)
+    }
 }
 
 pub enum LTTimeoutResult {
@@ -117,7 +138,7 @@ impl<D: ApplicationData> Debug for LTResult<D> {
             LTResult::Running => {
                 write!(f, "Running")
             }
-            LTResult::LTPFinished(first, last, _) => {
+            LTResult::LTPFinished(first, last, _, _) => {
                 write!(f, "LTPFinished({:?}, {:?})", first, last)
             }
         }
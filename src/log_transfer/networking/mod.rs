@@ -3,6 +3,8 @@ pub mod serialize;
 
 use std::collections::BTreeMap;
 
+use log::error;
+
 use atlas_common::crypto::hash::Digest;
 use atlas_common::error::*;
 use atlas_common::node_id::NodeId;
@@ -10,13 +12,15 @@ use atlas_communication::FullNetworkNode;
 use atlas_communication::message::{SerializedMessage, StoredMessage, StoredSerializedProtocolMessage};
 use atlas_communication::protocol_node::ProtocolNetworkNode;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
-use atlas_communication::serialize::Serializable;
+use atlas_communication::serialize::{Buf, Serializable};
 use atlas_execution::serialize::ApplicationData;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
 
-use crate::messages::SystemMessage;
+use crate::messages::authenticator::{authenticator_for, MessageClass};
+use crate::messages::{LogTransfer, SystemMessage};
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
-use crate::serialize::Service;
+use crate::serialize::compression::CompressionRequest;
+use crate::serialize::{compress_message, Service};
 use crate::smr::networking::NodeWrap;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 
@@ -54,11 +58,36 @@ pub trait LogTransferSendNode<D, OP, LPM> where LPM: LogTransferMessage<D, OP> {
     #[inline(always)]
     fn broadcast_signed(&self, message: LPM::LogTransferMessage, target: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>>;
 
+    /// Sends a message to a given target, authenticated as dictated by our [AuthenticationPolicy](crate::messages::authenticator::AuthenticationPolicy):
+    /// either signed or carrying an [Authenticator](crate::messages::authenticator::Authenticator) with the MAC for the target.
+    /// Does not block on the message sent. Returns a result that is
+    /// Ok if there is a current connection to the target or err if not. No other checks are made
+    /// on the success of the message dispatch
+    #[inline(always)]
+    fn send_authenticated(&self, message: LPM::LogTransferMessage, target: NodeId, flush: bool) -> Result<()>;
+
+    /// Broadcast a message to all of the given targets, authenticated as dictated by our policy
+    /// (a single authenticator carries the MACs for all of the targets).
+    /// Does not block on the message sent. Returns a result that is
+    /// Ok if there is a current connection to the targets or err if not. No other checks are made
+    /// on the success of the message dispatch
+    #[inline(always)]
+    fn broadcast_authenticated(&self, message: LPM::LogTransferMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>>;
+
     /// Serialize a message to a given target.
     /// Creates the serialized byte buffer along with the header, so we can send it later.
     #[inline(always)]
     fn serialize_digest_message(&self, message: LPM::LogTransferMessage) -> Result<(SerializedMessage<LPM::LogTransferMessage>, Digest)>;
 
+    /// Serialize a message to a given target, compressing its encoding with the given compression
+    /// (usually the one negotiated with the target, see [PeerCompressionTable](crate::serialize::compression::PeerCompressionTable))
+    /// if it is large enough. Meant for the large messages, such as decision logs.
+    /// The returned digest (which the header must be signed over) is the digest of the bytes which are sent,
+    /// so it's the digest of the compressed bytes if the message was compressed, since that is what the receiver's
+    /// network layer checks the payload against
+    #[inline(always)]
+    fn serialize_digest_compressed_message(&self, message: LPM::LogTransferMessage, compression: Option<CompressionRequest>) -> Result<(SerializedMessage<LPM::LogTransferMessage>, Digest)>;
+
     /// Broadcast the serialized messages provided.
     /// Does not block on the message sent. Returns a result that is
     /// Ok if there is a current connection to the targets or err if not. No other checks are made
@@ -98,6 +127,46 @@ impl<NT, D, P, S, L, NI, RM> LogTransferSendNode<D, P, L> for NodeWrap<NT, D, P,
         self.0.broadcast_signed(SystemMessage::from_log_transfer_message(message), targets)
     }
 
+    fn send_authenticated(&self, message: L::LogTransferMessage, target: NodeId, flush: bool) -> Result<()> {
+        let authentication = match authenticator_for(MessageClass::LogTransfer) {
+            Some(authentication) => authentication,
+            None => return self.0.send_signed(SystemMessage::from_log_transfer_message(message), target, flush),
+        };
+
+        let message = LogTransfer::new(message);
+
+        let digest = Service::<D, P, S, L>::log_authentication_digest(self.0.id(), message.wire_version(), message.payload())?;
+
+        let authenticator = authentication.authenticate(&digest, std::iter::once(target))?;
+
+        self.0.send(SystemMessage::LogTransferMessage(message.with_authenticator(Some(authenticator))), target, flush)
+    }
+
+    fn broadcast_authenticated(&self, message: L::LogTransferMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>> {
+        let authentication = match authenticator_for(MessageClass::LogTransfer) {
+            Some(authentication) => authentication,
+            None => return self.0.broadcast_signed(SystemMessage::from_log_transfer_message(message), targets),
+        };
+
+        let targets: Vec<NodeId> = targets.collect();
+
+        let message = LogTransfer::new(message);
+
+        let authenticator = Service::<D, P, S, L>::log_authentication_digest(self.0.id(), message.wire_version(), message.payload())
+            .and_then(|digest| authentication.authenticate(&digest, targets.iter().copied()));
+
+        match authenticator {
+            Ok(authenticator) => {
+                self.0.broadcast(SystemMessage::LogTransferMessage(message.with_authenticator(Some(authenticator))), targets.into_iter())
+            }
+            Err(err) => {
+                error!("Failed to authenticate log transfer message: {:?}", err);
+
+                Err(targets)
+            }
+        }
+    }
+
     /// Why do we do this wrapping/unwrapping? Well, since we want to avoid having to store all of the
     /// generics that are used at the replica level (with all message types), we can't
     /// just return a system message type.
@@ -114,6 +183,25 @@ impl<NT, D, P, S, L, NI, RM> LogTransferSendNode<D, P, L> for NodeWrap<NT, D, P,
         Ok((SerializedMessage::new(message, bytes), digest))
     }
 
+    #[inline(always)]
+    fn serialize_digest_compressed_message(&self, message: L::LogTransferMessage, compression: Option<CompressionRequest>) -> Result<(SerializedMessage<L::LogTransferMessage>, Digest)> {
+        let (message, digest) = self.0.serialize_digest_message(SystemMessage::from_log_transfer_message(message))?;
+
+        let (message, bytes) = message.into_inner();
+
+        let compressed = match compression {
+            Some(compression) => compress_message(&bytes[..], &compression)?,
+            None => None,
+        };
+
+        let (bytes, digest) = match compressed {
+            Some((compressed, compressed_digest)) => (Buf::from(compressed), compressed_digest),
+            None => (bytes, digest),
+        };
+
+        Ok((SerializedMessage::new(message.into_log_transfer_message(), bytes), digest))
+    }
+
     /// Read comment above
     #[inline(always)]
     fn broadcast_serialized(&self, messages: BTreeMap<NodeId, StoredSerializedProtocolMessage<L::LogTransferMessage>>) -> std::result::Result<(), Vec<NodeId>> {
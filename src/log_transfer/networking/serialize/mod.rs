@@ -1,11 +1,15 @@
 use std::sync::Arc;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use atlas_communication::message::Header;
 use atlas_common::error::*;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
 use atlas_smr_application::serialize::ApplicationData;
 use crate::log_transfer::networking::signature_ver::LogTransferVerificationHelper;
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
+#[cfg(feature = "serialize_capnp")]
+use crate::serialize::versioning::WireVersion;
+#[cfg(feature = "serialize_serde")]
+use crate::serialize::versioning::VersionedDeserialize;
 
 /// The abstraction for log transfer protocol messages.
 /// This allows us to have any log transfer protocol work with the same backbone
@@ -14,19 +18,26 @@ pub trait LogTransferMessage<D, OP>: Send + Sync {
     type LogTransferMessage: Send + Clone;
 
     #[cfg(feature = "serialize_serde")]
-    type LogTransferMessage: for<'a> Deserialize<'a> + Serialize + Send + Clone;
+    type LogTransferMessage: VersionedDeserialize + Serialize + Send + Clone;
 
-    /// Verify the message and return the message if it is valid
+    /// Verify the message, without taking ownership of it
     fn verify_log_message<NI, LVH>(network_info: &Arc<NI>,
                                           header: &Header,
-                                          message: Self::LogTransferMessage) -> Result<(bool, Self::LogTransferMessage)>
+                                          message: &Self::LogTransferMessage) -> Result<bool>
         where NI: NetworkInformationProvider,
               LVH: LogTransferVerificationHelper<D, OP, NI>,
               D: ApplicationData, OP: OrderingProtocolMessage<D>;
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(builder: febft_capnp::cst_messages_capnp::cst_message::Builder, msg: &Self::LogTransferMessage) -> Result<()>;
+    fn serialize_capnp(builder: capnp::any_pointer::Builder, msg: &Self::LogTransferMessage) -> Result<()>;
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(reader: febft_capnp::cst_messages_capnp::cst_message::Reader) -> Result<Self::LogTransferMessage>;
-}
\ No newline at end of file
+    fn deserialize_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::LogTransferMessage>;
+
+    /// Decode a message encoded by a peer which is still running the previous wire version.
+    /// See [OrderingProtocolMessage::deserialize_previous_capnp](crate::ordering_protocol::networking::serialize::OrderingProtocolMessage::deserialize_previous_capnp)
+    #[cfg(feature = "serialize_capnp")]
+    fn deserialize_previous_capnp(_version: WireVersion, _reader: capnp::any_pointer::Reader) -> Result<Self::LogTransferMessage> {
+        Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "No decoder for the log transfer messages of the previous wire version"))
+    }
+}
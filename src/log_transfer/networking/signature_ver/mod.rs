@@ -1,9 +1,13 @@
+use atlas_common::crypto::hash::Digest;
+use atlas_common::error::*;
+use atlas_communication::message::Header;
 use atlas_communication::FullNetworkNode;
 use atlas_communication::message_signing::NetworkMessageSignatureVerifier;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
 use atlas_communication::serialize::Serializable;
 use atlas_smr_application::serialize::ApplicationData;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
+use crate::messages::authenticator::{Authenticator, MacKeyProvider};
 use crate::messages::signature_ver::SigVerifier;
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
 use crate::ordering_protocol::networking::signature_ver::OrderProtocolSignatureVerificationHelper;
@@ -11,7 +15,14 @@ use crate::serialize::Service;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 
 pub trait LogTransferVerificationHelper<D, OP, NI>: OrderProtocolSignatureVerificationHelper<D, OP, NI>
-    where D: ApplicationData, OP: OrderingProtocolMessage<D>, NI: NetworkInformationProvider {}
+    where D: ApplicationData, OP: OrderingProtocolMessage<D>, NI: NetworkInformationProvider {
+    /// helper to verify log transfer messages sent in the [AuthenticationMode::Authenticator](crate::messages::authenticator::AuthenticationMode::Authenticator) mode,
+    /// which carry a vector of MACs (over the given authentication digest) instead of a signature
+    fn verify_log_authenticator<K>(keys: &K, header: &Header, digest: &Digest, authenticator: &Authenticator) -> Result<bool>
+        where K: MacKeyProvider + ?Sized {
+        Ok(authenticator.verify(keys, header.from(), digest))
+    }
+}
 
 impl<SV, NI, D, OP, ST, LT> LogTransferVerificationHelper<D, OP, NI> for SigVerifier<SV, NI, D, OP, ST, LT>
     where D: ApplicationData + 'static,
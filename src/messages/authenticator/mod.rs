@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac as _};
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use atlas_common::crypto::hash::{Context, Digest};
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_execution::serialize::ApplicationData;
+
+use crate::messages::SystemMessage;
+use crate::serialize::versioning::WireVersion;
+
+/// The length of each MAC, in bytes
+pub const MAC_LENGTH: usize = 32;
+
+/// The length of the symmetric keys shared by each pair of nodes, in bytes
+pub const MAC_KEY_LENGTH: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+static AUTHENTICATION: OnceLock<Authentication> = OnceLock::new();
+
+/// How messages are authenticated
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthenticationMode {
+    /// The message is signed by the sender, so anyone can verify it and it can be forwarded
+    Signature,
+    /// The message carries an [Authenticator], with a MAC for each of its destinations.
+    /// Much cheaper than signatures, but only the destinations can verify it, so it can't be
+    /// forwarded or used as a proof
+    Authenticator,
+}
+
+/// The classes of messages which can be assigned an [AuthenticationMode]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageClass {
+    Request,
+    Reply,
+    OrderProtocol,
+    StateTransfer,
+    LogTransfer,
+    /// Messages forwarded by other replicas. These are verified with the signatures of their original senders
+    Forwarded,
+    /// Messages about the connection between the nodes, such as the wire handshake
+    Control,
+}
+
+const MESSAGE_CLASS_COUNT: usize = 7;
+
+/// The authentication mode used for each class of messages.
+/// By default, every message is signed
+#[derive(Clone, Debug)]
+pub struct AuthenticationPolicy {
+    modes: [AuthenticationMode; MESSAGE_CLASS_COUNT],
+}
+
+/// A symmetric key shared by a pair of nodes
+#[derive(Clone)]
+pub struct MacKey([u8; MAC_KEY_LENGTH]);
+
+/// The MAC of a message, computed with the key shared by the sender and one of the destinations
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Mac([u8; MAC_LENGTH]);
+
+/// An authenticator vector: the MACs of a message for each of its destinations.
+///
+/// Each MAC covers the sender, its destination and the sequence number of the authenticator, along with
+/// the digest of the payload, so a MAC can't be moved to another destination and every authenticator
+/// produced by a node is unique (so the receivers can tell replayed messages apart by their sequence number)
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct Authenticator {
+    seq: u64,
+    macs: Vec<(NodeId, Mac)>,
+}
+
+/// Provides the symmetric keys we share with each of the other nodes
+pub trait MacKeyProvider: Send + Sync {
+    /// Our own id
+    fn own_id(&self) -> NodeId;
+
+    /// The key we share with the given node
+    fn key_for(&self, node: NodeId) -> Option<&MacKey>;
+}
+
+/// The keys and the policy used to authenticate the messages exchanged by this node
+pub struct Authentication {
+    keys: Box<dyn MacKeyProvider>,
+    policy: AuthenticationPolicy,
+    /// The sequence number of the next authenticator we produce
+    next_seq: AtomicU64,
+}
+
+/// A [MacKeyProvider] with a fixed set of pairwise keys
+pub struct PairwiseKeys {
+    own_id: NodeId,
+    keys: BTreeMap<NodeId, MacKey>,
+}
+
+impl MessageClass {
+    fn index(&self) -> usize {
+        match self {
+            MessageClass::Request => 0,
+            MessageClass::Reply => 1,
+            MessageClass::OrderProtocol => 2,
+            MessageClass::StateTransfer => 3,
+            MessageClass::LogTransfer => 4,
+            MessageClass::Forwarded => 5,
+            MessageClass::Control => 6,
+        }
+    }
+
+    /// Can messages of this class carry an [Authenticator]
+    pub fn supports_authenticator(&self) -> bool {
+        matches!(self, MessageClass::OrderProtocol | MessageClass::StateTransfer | MessageClass::LogTransfer)
+    }
+
+    /// The class of the given message
+    pub fn of<D, P, ST, LT>(message: &SystemMessage<D, P, ST, LT>) -> Self where D: ApplicationData {
+        match message {
+            SystemMessage::OrderedRequest(_) | SystemMessage::UnorderedRequest(_) => MessageClass::Request,
+            SystemMessage::OrderedReply(_) | SystemMessage::UnorderedReply(_) | SystemMessage::RequestRejected(_) => MessageClass::Reply,
+            SystemMessage::ProtocolMessage(_) => MessageClass::OrderProtocol,
+            SystemMessage::StateTransferMessage(_) => MessageClass::StateTransfer,
+            SystemMessage::LogTransferMessage(_) => MessageClass::LogTransfer,
+            SystemMessage::ForwardedRequestMessage(_) | SystemMessage::ForwardedProtocolMessage(_) => MessageClass::Forwarded,
+            SystemMessage::WireHandshake(_) => MessageClass::Control,
+        }
+    }
+}
+
+impl Default for AuthenticationPolicy {
+    fn default() -> Self {
+        Self {
+            modes: [AuthenticationMode::Signature; MESSAGE_CLASS_COUNT],
+        }
+    }
+}
+
+impl AuthenticationPolicy {
+    /// Use the given mode for the given class of messages.
+    /// Only the order protocol, state transfer and log transfer messages carry an authenticator.
+    /// Forwarded messages in particular must always be signed, since the forwarder can't produce
+    /// MACs on behalf of the original sender
+    pub fn with_mode(mut self, class: MessageClass, mode: AuthenticationMode) -> Result<Self> {
+        if mode == AuthenticationMode::Authenticator && !class.supports_authenticator() {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "This class of messages must be signed"));
+        }
+
+        self.modes[class.index()] = mode;
+
+        Ok(self)
+    }
+
+    pub fn mode_for(&self, class: MessageClass) -> AuthenticationMode {
+        self.modes[class.index()]
+    }
+
+    /// The mode for the given message
+    pub fn mode_for_message<D, P, ST, LT>(&self, message: &SystemMessage<D, P, ST, LT>) -> AuthenticationMode where D: ApplicationData {
+        self.mode_for(MessageClass::of(message))
+    }
+
+    /// Does any class of messages use authenticators
+    pub fn uses_authenticators(&self) -> bool {
+        self.modes.iter().any(|mode| *mode == AuthenticationMode::Authenticator)
+    }
+}
+
+impl MacKey {
+    pub fn from_bytes(bytes: [u8; MAC_KEY_LENGTH]) -> Self {
+        Self(bytes)
+    }
+
+    fn hmac(&self, digest: &Digest) -> HmacSha256 {
+        let mut hmac = HmacSha256::new_from_slice(&self.0)
+            .expect("HMAC accepts keys of any length");
+
+        hmac.update(digest.as_ref());
+
+        hmac
+    }
+
+    /// Compute the MAC of the given message digest with this key (HMAC-SHA256)
+    pub fn mac(&self, digest: &Digest) -> Mac {
+        Mac(self.hmac(digest).finalize().into_bytes().into())
+    }
+
+    /// Verify the MAC of the given message digest. The comparison is done in constant time,
+    /// so it doesn't leak how much of the MAC was correct
+    pub fn verify(&self, digest: &Digest, mac: &Mac) -> bool {
+        self.hmac(digest).verify_slice(&mac.0).is_ok()
+    }
+}
+
+impl Mac {
+    pub fn from_bytes(bytes: [u8; MAC_LENGTH]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; MAC_LENGTH] {
+        &self.0
+    }
+}
+
+impl Authenticator {
+    pub fn from_macs(seq: u64, macs: Vec<(NodeId, Mac)>) -> Self {
+        Self { seq, macs }
+    }
+
+    /// Produce the authenticator with the given sequence number of a message (identified by its digest)
+    /// for the given destinations
+    pub fn generate<K>(keys: &K, seq: u64, digest: &Digest, targets: impl Iterator<Item=NodeId>) -> Result<Self> where K: MacKeyProvider + ?Sized {
+        let from = keys.own_id();
+
+        let macs = targets.map(|target| {
+            keys.key_for(target)
+                .map(|key| (target, key.mac(&mac_digest(from, target, seq, digest))))
+                .ok_or_else(|| Error::simple_with_msg(ErrorKind::Communication, "No MAC key shared with the target"))
+        }).collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { seq, macs })
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn macs(&self) -> &Vec<(NodeId, Mac)> {
+        &self.macs
+    }
+
+    /// Verify the MAC meant for us, in a message sent by the given node.
+    /// Returns false if there is no MAC for us in the vector
+    pub fn verify<K>(&self, keys: &K, from: NodeId, digest: &Digest) -> bool where K: MacKeyProvider + ?Sized {
+        let own_id = keys.own_id();
+
+        let key = match keys.key_for(from) {
+            Some(key) => key,
+            None => return false,
+        };
+
+        self.macs.iter()
+            .find(|(node, _)| *node == own_id)
+            .map(|(_, mac)| key.verify(&mac_digest(from, own_id, self.seq, digest), mac))
+            .unwrap_or(false)
+    }
+}
+
+impl PairwiseKeys {
+    pub fn new(own_id: NodeId, keys: BTreeMap<NodeId, MacKey>) -> Self {
+        Self { own_id, keys }
+    }
+}
+
+impl MacKeyProvider for PairwiseKeys {
+    fn own_id(&self) -> NodeId {
+        self.own_id
+    }
+
+    fn key_for(&self, node: NodeId) -> Option<&MacKey> {
+        self.keys.get(&node)
+    }
+}
+
+impl Authentication {
+    pub fn keys(&self) -> &dyn MacKeyProvider {
+        self.keys.as_ref()
+    }
+
+    pub fn policy(&self) -> &AuthenticationPolicy {
+        &self.policy
+    }
+
+    /// Produce the authenticator of a message (identified by its digest) for the given destinations,
+    /// with the next sequence number of this node
+    pub fn authenticate(&self, digest: &Digest, targets: impl Iterator<Item=NodeId>) -> Result<Authenticator> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+
+        Authenticator::generate(self.keys(), seq, digest, targets)
+    }
+}
+
+/// Set up the keys and the policy used to authenticate our messages.
+/// Until this is done, every message is signed.
+///
+/// Authenticators are computed over the payload encoded on its own, which the serde backend can only
+/// do with the `serialize_postcard` feature, so a policy with authenticators is refused without it
+pub fn init_authentication<K>(keys: K, policy: AuthenticationPolicy) -> Result<()> where K: MacKeyProvider + 'static {
+    if policy.uses_authenticators() && !crate::serialize::can_encode_payloads() {
+        return Err(Error::simple_with_msg(ErrorKind::Communication, "Authenticators require the serialize_capnp or serialize_postcard features"));
+    }
+
+    // Start from the current time, so the sequence numbers keep increasing across restarts
+    let first_seq = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+
+    AUTHENTICATION.set(Authentication { keys: Box::new(keys), policy, next_seq: AtomicU64::new(first_seq) })
+        .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Authentication has already been initialized"))
+}
+
+/// The authentication to use for the given class of messages, if they are to be authenticated
+/// with an [Authenticator]. None if they must be signed
+pub fn authenticator_for(class: MessageClass) -> Option<&'static Authentication> {
+    AUTHENTICATION.get()
+        .filter(|authentication| authentication.policy.mode_for(class) == AuthenticationMode::Authenticator)
+}
+
+/// The digest covered by the MAC for one of the destinations of a message: the digest of the message
+/// bound to its sender, that destination and the sequence number of the authenticator
+fn mac_digest(from: NodeId, to: NodeId, seq: u64, digest: &Digest) -> Digest {
+    let from: u64 = from.into();
+    let to: u64 = to.into();
+
+    let mut context = Context::new();
+
+    context.update(&from.to_le_bytes());
+    context.update(&to.to_le_bytes());
+    context.update(&seq.to_le_bytes());
+    context.update(digest.as_ref());
+
+    context.finish()
+}
+
+/// The digest covered by the MACs of a message sent by the given node, whose payload
+/// (encoded on its own, since the authenticator is carried in the same message) is given
+pub fn authentication_digest(from: NodeId, version: WireVersion, encoded_payload: &[u8]) -> Digest {
+    let from: u64 = from.into();
+
+    let mut context = Context::new();
+
+    context.update(&from.to_le_bytes());
+    context.update(&version.version().to_le_bytes());
+    context.update(encoded_payload);
+
+    context.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(own_id: u32, peers: &[u32]) -> PairwiseKeys {
+        // Every pair shares the same key, so only the binding of the MACs tells the destinations apart
+        let keys = peers.iter()
+            .map(|peer| (NodeId::from(*peer), MacKey::from_bytes([0x42; MAC_KEY_LENGTH])))
+            .collect();
+
+        PairwiseKeys::new(NodeId::from(own_id), keys)
+    }
+
+    fn digest() -> Digest {
+        Digest::from_bytes(&[0x11; Digest::LENGTH]).unwrap()
+    }
+
+    #[test]
+    fn each_destination_verifies_its_own_mac() {
+        let authenticator = Authenticator::generate(&keys(0, &[1, 2]), 7, &digest(), [1u32, 2].into_iter().map(NodeId::from)).unwrap();
+
+        assert!(authenticator.verify(&keys(1, &[0]), NodeId::from(0u32), &digest()));
+        assert!(authenticator.verify(&keys(2, &[0]), NodeId::from(0u32), &digest()));
+        assert!(!authenticator.verify(&keys(3, &[0]), NodeId::from(0u32), &digest()));
+    }
+
+    #[test]
+    fn mac_is_bound_to_its_destination() {
+        let authenticator = Authenticator::generate(&keys(0, &[1]), 7, &digest(), std::iter::once(NodeId::from(1u32))).unwrap();
+
+        let (_, mac) = authenticator.macs()[0];
+
+        let redirected = Authenticator::from_macs(7, vec![(NodeId::from(2u32), mac)]);
+
+        assert!(!redirected.verify(&keys(2, &[0]), NodeId::from(0u32), &digest()));
+    }
+
+    #[test]
+    fn mac_is_bound_to_the_sequence_number_and_sender() {
+        let authenticator = Authenticator::generate(&keys(0, &[1]), 7, &digest(), std::iter::once(NodeId::from(1u32))).unwrap();
+
+        let resequenced = Authenticator::from_macs(8, authenticator.macs().clone());
+
+        assert!(!resequenced.verify(&keys(1, &[0]), NodeId::from(0u32), &digest()));
+        assert!(!authenticator.verify(&keys(1, &[0, 2]), NodeId::from(2u32), &digest()));
+    }
+}
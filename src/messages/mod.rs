@@ -1,9 +1,15 @@
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::sync::Arc;
 
 #[cfg(feature = "serialize_serde")]
-use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serialize_serde")]
+use serde::de::{Error as DeserializeError, IgnoredAny, MapAccess, SeqAccess, Visitor};
 
 use atlas_common::crypto::hash::Digest;
 use atlas_common::error::*;
@@ -13,9 +19,19 @@ use atlas_communication::message::StoredMessage;
 use atlas_communication::protocol_node::ProtocolNetworkNode;
 use atlas_execution::serialize::ApplicationData;
 
+use crate::messages::authenticator::Authenticator;
+use crate::messages::payload::{OperationDecoder, RequestPayload};
+#[cfg(feature = "serialize_serde")]
+use crate::serialize::compression;
+use crate::serialize::compression::CompressionSet;
+use crate::serialize::versioning::{CURRENT_WIRE_VERSION, WireHandshakeMessage, WireVersion};
+#[cfg(feature = "serialize_serde")]
+use crate::serialize::versioning::{VersionedDeserialize, VersionedPayload};
 use crate::timeouts::TimedOut;
 
 pub mod signature_ver;
+pub mod authenticator;
+pub mod payload;
 
 /// The `Message` type encompasses all the messages traded between different
 /// asynchronous tasks in the system.
@@ -53,13 +69,21 @@ pub enum SystemMessage<D: ApplicationData, P, ST, LT> {
     ///Requests forwarded from other peers
     ForwardedRequestMessage(ForwardedRequestsMessage<D::Request>),
     ///A message related to the protocol
+    #[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "P: VersionedDeserialize")))]
     ProtocolMessage(Protocol<P>),
     ///A protocol message that has been forwarded by another peer
+    #[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "P: VersionedDeserialize")))]
     ForwardedProtocolMessage(ForwardedProtocolMessage<P>),
     ///A state transfer protocol message
+    #[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "ST: VersionedDeserialize")))]
     StateTransferMessage(StateTransfer<ST>),
     ///A Log trasnfer protocol message
+    #[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "LT: VersionedDeserialize")))]
     LogTransferMessage(LogTransfer<LT>),
+    ///A notification that a request was rejected before being ordered
+    RequestRejected(RequestRejectedMessage),
+    ///The wire capabilities of a node, exchanged when connecting to agree on the wire version
+    WireHandshake(WireHandshakeMessage),
 }
 
 impl<D, P, ST, LT> SystemMessage<D, P, ST, LT> where D: ApplicationData {
@@ -139,6 +163,12 @@ impl<D, P, ST, LT> Clone for SystemMessage<D, P, ST, LT> where D: ApplicationDat
             SystemMessage::LogTransferMessage(log_transfer) => {
                 SystemMessage::LogTransferMessage(log_transfer.clone())
             }
+            SystemMessage::RequestRejected(rejection) => {
+                SystemMessage::RequestRejected(rejection.clone())
+            }
+            SystemMessage::WireHandshake(handshake) => {
+                SystemMessage::WireHandshake(handshake.clone())
+            }
         }
     }
 }
@@ -173,6 +203,12 @@ impl<D, P, ST, LT> Debug for SystemMessage<D, P, ST, LT> where D: ApplicationDat
             SystemMessage::LogTransferMessage(_) => {
                 write!(f, "Log transfer message")
             }
+            SystemMessage::RequestRejected(rejection) => {
+                write!(f, "Request rejected {:?}", rejection.reason())
+            }
+            SystemMessage::WireHandshake(handshake) => {
+                write!(f, "Wire handshake {:?}", handshake.capabilities())
+            }
         }
     }
 }
@@ -194,12 +230,16 @@ pub type StoredRequestMessage<O> = StoredMessage<RequestMessage<O>>;
 ///
 /// The `O` type argument symbolizes the client operation to be performed
 /// over the replicated state.
+///
+/// Requests received from the network keep the operation in its serialized form
+/// (see [RequestPayload]), so cloning them is cheap.
 #[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "O: for<'a> Deserialize<'a>")))]
 #[derive(Clone)]
 pub struct RequestMessage<O> {
     session_id: SeqNo,
     operation_id: SeqNo,
-    operation: O,
+    operation: RequestPayload<O>,
 }
 
 /// Represents a reply to a client.
@@ -211,6 +251,9 @@ pub struct ReplyMessage<P> {
     session_id: SeqNo,
     operation_id: SeqNo,
     payload: P,
+    /// For unordered replies, the sequence number of the last decision applied to
+    /// the state the request was executed over
+    executed_seq: Option<SeqNo>,
 }
 
 impl<O> Orderable for RequestMessage<O> {
@@ -222,21 +265,41 @@ impl<O> Orderable for RequestMessage<O> {
 impl<O> RequestMessage<O> {
     /// Creates a new `RequestMessage`.
     pub fn new(sess: SeqNo, id: SeqNo, operation: O) -> Self {
-        Self { operation, operation_id: id, session_id: sess }
+        Self { operation: RequestPayload::new(operation), operation_id: id, session_id: sess }
     }
 
-    /// Returns a reference to the operation of type `O`.
+    /// Creates a new `RequestMessage` whose operation is only deserialized when it is first accessed.
+    pub fn from_serialized(sess: SeqNo, id: SeqNo, operation: Arc<[u8]>, decode: OperationDecoder<O>) -> Self {
+        Self { operation: RequestPayload::from_serialized(operation, decode), operation_id: id, session_id: sess }
+    }
+
+    /// Returns a reference to the operation of type `O`, deserializing it if needed.
+    ///
+    /// Requests received from the network are only accepted if their operation can be decoded
+    /// (see [OrderProtocolSignatureVerificationHelper::verify_request_message](crate::ordering_protocol::networking::signature_ver::OrderProtocolSignatureVerificationHelper::verify_request_message)),
+    /// so this only panics for requests which were not verified (see [RequestPayload::operation]).
     pub fn operation(&self) -> &O {
-        &self.operation
+        self.operation.operation()
+    }
+
+    /// Returns a reference to the operation, deserializing it if needed, or the error
+    /// if it can't be decoded
+    pub fn try_operation(&self) -> Result<&O> {
+        self.operation.try_operation()
+    }
+
+    /// The serialized operation, if this request was received from the network
+    pub fn serialized_operation(&self) -> Option<&Arc<[u8]>> {
+        self.operation.serialized()
     }
 
     pub fn session_id(&self) -> SeqNo {
         self.session_id
     }
 
-    /// Unwraps this `RequestMessage`.
+    /// Unwraps this `RequestMessage`, deserializing the operation if needed.
     pub fn into_inner_operation(self) -> O {
-        self.operation
+        self.operation.into_operation()
     }
 }
 
@@ -249,7 +312,19 @@ impl<P> Orderable for ReplyMessage<P> {
 impl<P> ReplyMessage<P> {
     /// Creates a new `ReplyMessage`.
     pub fn new(sess: SeqNo, id: SeqNo, payload: P) -> Self {
-        Self { payload, operation_id: id, session_id: sess }
+        Self { payload, operation_id: id, session_id: sess, executed_seq: None }
+    }
+
+    /// Creates a new unordered `ReplyMessage`, executed over the state
+    /// resulting from the decision `executed_seq`
+    pub fn new_unordered(sess: SeqNo, id: SeqNo, executed_seq: SeqNo, payload: P) -> Self {
+        Self { payload, operation_id: id, session_id: sess, executed_seq: Some(executed_seq) }
+    }
+
+    /// The sequence number of the last decision applied to the state
+    /// this (unordered) request was executed over
+    pub fn executed_seq(&self) -> Option<SeqNo> {
+        self.executed_seq
     }
 
     /// Returns a reference to the payload of type `P`.
@@ -267,17 +342,82 @@ impl<P> ReplyMessage<P> {
     }
 }
 
+/// The reason why a replica has rejected a given request
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RejectionReason {
+    /// The replica did not have space for the request, which was never processed.
+    /// The client can retry the same operation later on
+    Overloaded,
+    /// The request was evicted from the pending requests to make room for newer ones.
+    /// It will not be proposed by this replica again, but it might have already been proposed
+    Shed,
+    /// The request is malformed or was refused by the application's validation, so it will never be ordered.
+    /// Retrying the same request will not succeed
+    Invalid,
+}
+
+/// Represents the rejection of a request by a replica, before it was ordered.
 #[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
 #[derive(Clone)]
+pub struct RequestRejectedMessage {
+    session_id: SeqNo,
+    operation_id: SeqNo,
+    reason: RejectionReason,
+}
+
+impl Orderable for RequestRejectedMessage {
+    fn sequence_number(&self) -> SeqNo {
+        self.operation_id
+    }
+}
+
+impl RequestRejectedMessage {
+    /// Creates a new `RequestRejectedMessage`.
+    pub fn new(sess: SeqNo, id: SeqNo, reason: RejectionReason) -> Self {
+        Self { session_id: sess, operation_id: id, reason }
+    }
+
+    pub fn session_id(&self) -> SeqNo {
+        self.session_id
+    }
+
+    pub fn reason(&self) -> RejectionReason {
+        self.reason
+    }
+}
+
+/// With serde, the wire version is decoded before the payload, which is then decoded
+/// with the decoder of that version (see [VersionedDeserialize]).
+#[cfg_attr(feature = "serialize_serde", derive(Serialize))]
+#[derive(Clone)]
 pub struct Protocol<P> {
+    /// The wire version the payload was encoded with
+    version: WireVersion,
+    /// The MACs of the message, if it's authenticated with an [Authenticator] instead of signed
+    authenticator: Option<Authenticator>,
     payload: P,
 }
 
 impl<P> Protocol<P> {
     pub fn new(payload: P) -> Self {
-        Self { payload }
+        Self::with_version(payload, CURRENT_WIRE_VERSION)
+    }
+
+    /// A message whose payload was decoded from the given wire version.
+    /// This only labels the payload: messages are always encoded with the current version
+    pub(crate) fn with_version(payload: P, version: WireVersion) -> Self {
+        Self { version, authenticator: None, payload }
     }
 
+    pub(crate) fn with_authenticator(self, authenticator: Option<Authenticator>) -> Self {
+        Self { authenticator, ..self }
+    }
+
+    pub fn wire_version(&self) -> WireVersion { self.version }
+
+    pub fn authenticator(&self) -> Option<&Authenticator> { self.authenticator.as_ref() }
+
     pub fn payload(&self) -> &P { &self.payload }
 
     pub fn into_inner(self) -> P {
@@ -285,6 +425,63 @@ impl<P> Protocol<P> {
     }
 }
 
+#[cfg(feature = "serialize_serde")]
+impl<'de, P> Deserialize<'de> for Protocol<P> where P: VersionedDeserialize {
+    fn deserialize<DE>(deserializer: DE) -> std::result::Result<Self, DE::Error> where DE: Deserializer<'de> {
+        deserializer.deserialize_struct("Protocol", &["version", "authenticator", "payload"], ProtocolVisitor(PhantomData))
+    }
+}
+
+#[cfg(feature = "serialize_serde")]
+struct ProtocolVisitor<P>(PhantomData<fn() -> P>);
+
+#[cfg(feature = "serialize_serde")]
+impl<'de, P> Visitor<'de> for ProtocolVisitor<P> where P: VersionedDeserialize {
+    type Value = Protocol<P>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "a protocol message")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+        let version: WireVersion = seq.next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+
+        let authenticator: Option<Authenticator> = seq.next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+
+        let payload = seq.next_element_seed(VersionedPayload::<P>::new(version))?
+            .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+
+        Ok(Protocol::with_version(payload, version).with_authenticator(authenticator))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error> where A: MapAccess<'de> {
+        let mut version = None;
+        let mut authenticator = None;
+        let mut payload = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => version = Some(map.next_value::<WireVersion>()?),
+                "authenticator" => authenticator = Some(map.next_value::<Option<Authenticator>>()?),
+                "payload" => {
+                    // The payload can only be decoded once we know which version it was encoded with
+                    let version = version.ok_or_else(|| A::Error::custom("The wire version must precede the payload"))?;
+
+                    payload = Some(map.next_value_seed(VersionedPayload::<P>::new(version))?);
+                }
+                _ => { map.next_value::<IgnoredAny>()?; }
+            }
+        }
+
+        let version = version.ok_or_else(|| A::Error::missing_field("version"))?;
+        let payload = payload.ok_or_else(|| A::Error::missing_field("payload"))?;
+
+        Ok(Protocol::with_version(payload, version).with_authenticator(authenticator.flatten()))
+    }
+}
+
 impl<P> Deref for Protocol<P> {
     type Target = P;
 
@@ -296,17 +493,46 @@ impl<P> Deref for Protocol<P> {
 ///
 /// State transfer messages
 ///
-#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+///
+/// The encoding of the message can be compressed on the wire
+/// (see [CompressedPayload](crate::serialize::compression::CompressedPayload)), which is transparent to the protocols.
 #[derive(Clone)]
 pub struct StateTransfer<P> {
+    /// The wire version the payload was encoded with
+    version: WireVersion,
+    /// The compression algorithms accepted by the sender of the message
+    accepts: CompressionSet,
+    /// The MACs of the message, if it's authenticated with an [Authenticator] instead of signed
+    authenticator: Option<Authenticator>,
     payload: P,
 }
 
 impl<P> StateTransfer<P> {
     pub fn new(payload: P) -> Self {
-        Self { payload }
+        Self::with_version(payload, CURRENT_WIRE_VERSION)
+    }
+
+    /// A message whose payload was decoded from the given wire version.
+    /// This only labels the payload: messages are always encoded with the current version
+    pub(crate) fn with_version(payload: P, version: WireVersion) -> Self {
+        Self { version, accepts: CompressionSet::local(), authenticator: None, payload }
+    }
+
+    pub(crate) fn with_accepted_compression(self, accepts: CompressionSet) -> Self {
+        Self { accepts, ..self }
     }
 
+    pub(crate) fn with_authenticator(self, authenticator: Option<Authenticator>) -> Self {
+        Self { authenticator, ..self }
+    }
+
+    pub fn wire_version(&self) -> WireVersion { self.version }
+
+    /// The compression algorithms accepted by the sender of this message
+    pub fn accepted_compression(&self) -> CompressionSet { self.accepts }
+
+    pub fn authenticator(&self) -> Option<&Authenticator> { self.authenticator.as_ref() }
+
     pub fn payload(&self) -> &P { &self.payload }
 
     pub fn into_inner(self) -> P {
@@ -314,6 +540,24 @@ impl<P> StateTransfer<P> {
     }
 }
 
+#[cfg(feature = "serialize_serde")]
+impl<P> Serialize for StateTransfer<P> where P: Serialize {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        compression::serialize_transfer(serializer, self.version, self.accepts, self.authenticator.as_ref(), &self.payload)
+    }
+}
+
+#[cfg(feature = "serialize_serde")]
+impl<'de, P> Deserialize<'de> for StateTransfer<P> where P: VersionedDeserialize {
+    fn deserialize<DE>(deserializer: DE) -> std::result::Result<Self, DE::Error> where DE: Deserializer<'de> {
+        let (version, accepts, authenticator, payload) = compression::deserialize_transfer(deserializer)?;
+
+        Ok(Self::with_version(payload, version)
+            .with_accepted_compression(accepts)
+            .with_authenticator(authenticator))
+    }
+}
+
 impl<P> Deref for StateTransfer<P> {
     type Target = P;
 
@@ -325,17 +569,46 @@ impl<P> Deref for StateTransfer<P> {
 ///
 /// Log transfer messages
 ///
-#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+///
+/// The encoding of the message can be compressed on the wire
+/// (see [CompressedPayload](crate::serialize::compression::CompressedPayload)), which is transparent to the protocols.
 #[derive(Clone)]
 pub struct LogTransfer<P> {
+    /// The wire version the payload was encoded with
+    version: WireVersion,
+    /// The compression algorithms accepted by the sender of the message
+    accepts: CompressionSet,
+    /// The MACs of the message, if it's authenticated with an [Authenticator] instead of signed
+    authenticator: Option<Authenticator>,
     payload: P,
 }
 
 impl<P> LogTransfer<P> {
     pub fn new(payload: P) -> Self {
-        Self { payload }
+        Self::with_version(payload, CURRENT_WIRE_VERSION)
+    }
+
+    /// A message whose payload was decoded from the given wire version.
+    /// This only labels the payload: messages are always encoded with the current version
+    pub(crate) fn with_version(payload: P, version: WireVersion) -> Self {
+        Self { version, accepts: CompressionSet::local(), authenticator: None, payload }
     }
 
+    pub(crate) fn with_accepted_compression(self, accepts: CompressionSet) -> Self {
+        Self { accepts, ..self }
+    }
+
+    pub(crate) fn with_authenticator(self, authenticator: Option<Authenticator>) -> Self {
+        Self { authenticator, ..self }
+    }
+
+    pub fn wire_version(&self) -> WireVersion { self.version }
+
+    /// The compression algorithms accepted by the sender of this message
+    pub fn accepted_compression(&self) -> CompressionSet { self.accepts }
+
+    pub fn authenticator(&self) -> Option<&Authenticator> { self.authenticator.as_ref() }
+
     pub fn payload(&self) -> &P { &self.payload }
 
     pub fn into_inner(self) -> P {
@@ -343,6 +616,24 @@ impl<P> LogTransfer<P> {
     }
 }
 
+#[cfg(feature = "serialize_serde")]
+impl<P> Serialize for LogTransfer<P> where P: Serialize {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        compression::serialize_transfer(serializer, self.version, self.accepts, self.authenticator.as_ref(), &self.payload)
+    }
+}
+
+#[cfg(feature = "serialize_serde")]
+impl<'de, P> Deserialize<'de> for LogTransfer<P> where P: VersionedDeserialize {
+    fn deserialize<DE>(deserializer: DE) -> std::result::Result<Self, DE::Error> where DE: Deserializer<'de> {
+        let (version, accepts, authenticator, payload) = compression::deserialize_transfer(deserializer)?;
+
+        Ok(Self::with_version(payload, version)
+            .with_accepted_compression(accepts)
+            .with_authenticator(authenticator))
+    }
+}
+
 impl<P> Deref for LogTransfer<P> {
     type Target = P;
 
@@ -356,6 +647,7 @@ impl<P> Deref for LogTransfer<P> {
 /// A message containing a number of forwarded requests
 ///
 #[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "O: for<'a> Deserialize<'a>")))]
 #[derive(Clone)]
 pub struct ForwardedRequestsMessage<O> {
     inner: Vec<StoredRequestMessage<O>>,
@@ -379,6 +671,7 @@ impl<O> ForwardedRequestsMessage<O> {
 
 /// A message containing a single forwarded consensus message
 #[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize_serde", serde(bound(deserialize = "P: VersionedDeserialize")))]
 #[derive(Clone)]
 pub struct ForwardedProtocolMessage<P> where {
     message: StoredMessage<Protocol<P>>,
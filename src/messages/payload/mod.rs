@@ -0,0 +1,206 @@
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "serialize_postcard")]
+use std::fmt::Formatter;
+
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serialize_postcard")]
+use serde::de::{SeqAccess, Visitor};
+
+use atlas_common::error::*;
+use atlas_execution::serialize::ApplicationData;
+
+/// Decodes a serialized operation
+pub type OperationDecoder<O> = fn(&[u8]) -> Result<O>;
+
+/// The operation of a client request.
+///
+/// Requests received from the network keep their serialized form in a reference counted buffer,
+/// and are only deserialized when the operation is first accessed. Every clone of such a payload
+/// (into the pending requests, the batches, when forwarding them, etc.) shares both the serialized
+/// form and the decoded operation, so cloning is only a pointer copy, the operation is decoded at most once
+/// and re-serializing the request reuses the same bytes.
+///
+/// Operations created locally are held as they are, and cloned along with the payload.
+pub struct RequestPayload<O>(PayloadInner<O>);
+
+enum PayloadInner<O> {
+    /// An operation created locally (or decoded along with the rest of the message)
+    Decoded(O),
+    /// An operation received in its serialized form, shared by every clone of the payload
+    Serialized(Arc<SerializedOperation<O>>),
+}
+
+struct SerializedOperation<O> {
+    bytes: Arc<[u8]>,
+    decode: OperationDecoder<O>,
+    /// The deserialized operation, decoded on first access
+    operation: OnceLock<O>,
+}
+
+impl<O> RequestPayload<O> {
+    pub fn new(operation: O) -> Self {
+        Self(PayloadInner::Decoded(operation))
+    }
+
+    /// A payload backed by its serialized form, which is only decoded when the operation is accessed.
+    /// With serde, the bytes are sent as they are, so they must be the postcard encoding of the operation
+    pub fn from_serialized(bytes: Arc<[u8]>, decode: OperationDecoder<O>) -> Self {
+        Self(PayloadInner::Serialized(Arc::new(SerializedOperation { bytes, decode, operation: OnceLock::new() })))
+    }
+
+    /// The serialized operation, if this payload was received from the network
+    pub fn serialized(&self) -> Option<&Arc<[u8]>> {
+        match &self.0 {
+            PayloadInner::Decoded(_) => None,
+            PayloadInner::Serialized(serialized) => Some(&serialized.bytes),
+        }
+    }
+
+    /// Is the operation already deserialized
+    pub fn is_decoded(&self) -> bool {
+        match &self.0 {
+            PayloadInner::Decoded(_) => true,
+            PayloadInner::Serialized(serialized) => serialized.operation.get().is_some(),
+        }
+    }
+
+    /// The operation, deserializing it if this is the first access
+    pub fn try_operation(&self) -> Result<&O> {
+        match &self.0 {
+            PayloadInner::Decoded(operation) => Ok(operation),
+            PayloadInner::Serialized(serialized) => {
+                if let Some(operation) = serialized.operation.get() {
+                    return Ok(operation);
+                }
+
+                let decoded = (serialized.decode)(&serialized.bytes[..])?;
+
+                Ok(serialized.operation.get_or_init(|| decoded))
+            }
+        }
+    }
+
+    /// The operation, deserializing it if this is the first access.
+    ///
+    /// # Panics
+    /// If the operation can't be decoded. Requests received from the network are only accepted once their
+    /// operation was decoded (see [OrderProtocolSignatureVerificationHelper::verify_request_message](crate::ordering_protocol::networking::signature_ver::OrderProtocolSignatureVerificationHelper::verify_request_message)),
+    /// so this can only happen with requests which were not verified
+    pub fn operation(&self) -> &O {
+        self.try_operation().expect("The operation of a verified request can always be decoded")
+    }
+
+    /// Unwrap the operation, deserializing it if it was not yet accessed.
+    ///
+    /// If the serialized form is still shared with other requests, a copy of the operation is decoded from it,
+    /// so the operation never has to be cloned.
+    ///
+    /// # Panics
+    /// As [Self::operation], if the operation can't be decoded
+    pub fn into_operation(self) -> O {
+        let serialized = match self.0 {
+            PayloadInner::Decoded(operation) => return operation,
+            PayloadInner::Serialized(serialized) => serialized,
+        };
+
+        match Arc::try_unwrap(serialized) {
+            Ok(SerializedOperation { bytes, decode, operation }) => operation.into_inner()
+                .unwrap_or_else(|| decode(&bytes[..]).expect("The operation of a verified request can always be decoded")),
+            Err(shared) => (shared.decode)(&shared.bytes[..])
+                .expect("The operation of a verified request can always be decoded"),
+        }
+    }
+}
+
+/// The [OperationDecoder] for the requests of the given application
+pub fn decode_request<D>(bytes: &[u8]) -> Result<D::Request> where D: ApplicationData {
+    D::deserialize_request(bytes)
+}
+
+/// The [OperationDecoder] of the operations received with serde, which are encoded with postcard
+#[cfg(feature = "serialize_postcard")]
+fn decode_postcard<O>(bytes: &[u8]) -> Result<O> where O: for<'a> Deserialize<'a> {
+    ::postcard::from_bytes(bytes).wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to decode the request operation")
+}
+
+impl<O> Clone for RequestPayload<O> where O: Clone {
+    fn clone(&self) -> Self {
+        match &self.0 {
+            PayloadInner::Decoded(operation) => Self(PayloadInner::Decoded(operation.clone())),
+            PayloadInner::Serialized(serialized) => Self(PayloadInner::Serialized(serialized.clone())),
+        }
+    }
+}
+
+/// With postcard, the operation is carried as a byte string holding its own encoding, so received operations
+/// are only decoded when accessed, and are forwarded with the same bytes they were received with.
+///
+/// Otherwise, there is no encoding for the operation on its own (as with authenticators and compression),
+/// so it is encoded along with the message, and decoded with it.
+#[cfg(feature = "serialize_serde")]
+impl<O> Serialize for RequestPayload<O> where O: Serialize {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        #[cfg(feature = "serialize_postcard")]
+        return match &self.0 {
+            PayloadInner::Serialized(serialized) => serializer.serialize_bytes(&serialized.bytes),
+            PayloadInner::Decoded(operation) => {
+                let bytes = ::postcard::to_stdvec(operation)
+                    .map_err(|err| serde::ser::Error::custom(format!("Failed to encode the request operation: {:?}", err)))?;
+
+                serializer.serialize_bytes(&bytes)
+            }
+        };
+
+        #[cfg(not(feature = "serialize_postcard"))]
+        {
+            let operation = self.try_operation()
+                .map_err(|err| serde::ser::Error::custom(format!("Failed to decode the request operation: {:?}", err)))?;
+
+            operation.serialize(serializer)
+        }
+    }
+}
+
+#[cfg(feature = "serialize_serde")]
+impl<'de, O> Deserialize<'de> for RequestPayload<O> where O: for<'a> Deserialize<'a> {
+    fn deserialize<DE>(deserializer: DE) -> std::result::Result<Self, DE::Error> where DE: Deserializer<'de> {
+        #[cfg(feature = "serialize_postcard")]
+        return deserializer.deserialize_byte_buf(OperationBytesVisitor)
+            .map(|bytes| Self::from_serialized(Arc::from(bytes), decode_postcard::<O>));
+
+        #[cfg(not(feature = "serialize_postcard"))]
+        O::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Reads the byte string of a serialized operation
+#[cfg(feature = "serialize_postcard")]
+struct OperationBytesVisitor;
+
+#[cfg(feature = "serialize_postcard")]
+impl<'de> Visitor<'de> for OperationBytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "the bytes of a request operation")
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> std::result::Result<Self::Value, E> where E: serde::de::Error {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> std::result::Result<Self::Value, E> where E: serde::de::Error {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(bytes)
+    }
+}
@@ -1,3 +1,36 @@
 use std::marker::PhantomData;
+use std::ops::Deref;
 
-pub struct SigVerifier<SV, NI, D, OP, ST, LT>(PhantomData<(SV, NI, D, OP, LT, ST)>);
\ No newline at end of file
+pub mod parallel_requests;
+
+pub struct SigVerifier<SV, NI, D, OP, ST, LT>(PhantomData<(SV, NI, D, OP, LT, ST)>);
+
+/// A message that has passed verification.
+///
+/// It can only be produced by the verification itself (see [crate::serialize::Service::verify_system_message]),
+/// so the protocols receiving it know that its signatures (including the ones of the messages
+/// it carries) have already been checked and don't have to verify it again.
+#[derive(Clone)]
+pub struct Verified<M>(M);
+
+impl<M> Verified<M> {
+    pub(crate) fn new(message: M) -> Self {
+        Self(message)
+    }
+
+    pub fn message(&self) -> &M {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+impl<M> Deref for Verified<M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossbeam::channel::Sender;
+use log::error;
+
+use atlas_common::error::*;
+
+/// The amount of requests verified by each job of the verification pool.
+/// Sets smaller than this are verified inline, since the hand off would cost more than the verification
+pub const REQUEST_VERIFICATION_CHUNK: usize = 32;
+
+const VERIFICATION_THREAD_NAME: &str = "REQUEST-VERIFIER";
+
+static VERIFIER: OnceLock<ParallelRequestVerifier> = OnceLock::new();
+
+type VerificationJob = Box<dyn FnOnce() + Send>;
+
+/// A pool of threads which verifies the client signatures of many requests at once,
+/// such as the ones contained in forwarded request messages.
+///
+/// Each request is still verified on its own (with its own signature check), the pool only spreads
+/// the requests over its threads. There is no batch signature verification.
+pub struct ParallelRequestVerifier {
+    job_tx: Sender<VerificationJob>,
+    thread_count: usize,
+}
+
+/// Initialize the parallel request verifier with the given amount of threads.
+/// When not initialized, it is started with one thread per available core on first use
+pub fn init_parallel_request_verifier(thread_count: usize) -> Result<()> {
+    VERIFIER.set(ParallelRequestVerifier::new(thread_count))
+        .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "The parallel request verifier has already been initialized"))
+}
+
+/// The parallel request verifier
+pub fn parallel_request_verifier() -> &'static ParallelRequestVerifier {
+    VERIFIER.get_or_init(|| {
+        let thread_count = std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1);
+
+        ParallelRequestVerifier::new(thread_count)
+    })
+}
+
+impl ParallelRequestVerifier {
+    fn new(thread_count: usize) -> Self {
+        let thread_count = thread_count.max(1);
+
+        let (job_tx, job_rx) = crossbeam::channel::unbounded::<VerificationJob>();
+
+        for thread in 0..thread_count {
+            let job_rx = job_rx.clone();
+
+            std::thread::Builder::new()
+                .name(format!("{}-{}", VERIFICATION_THREAD_NAME, thread))
+                .spawn(move || {
+                    while let Ok(job) = job_rx.recv() {
+                        job();
+                    }
+                }).expect("Failed to launch request verification thread");
+        }
+
+        Self {
+            job_tx,
+            thread_count,
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    /// Verify each of the given items with the given verifier, splitting them into chunks of
+    /// [REQUEST_VERIFICATION_CHUNK] which are verified in parallel.
+    ///
+    /// Returns whether every item is valid, along with the items (in their original order).
+    /// The items are shared with the pool threads, which verify them by reference.
+    /// As soon as an invalid item is found, the chunks that have not yet been started are skipped,
+    /// since the whole set will be rejected anyway.
+    ///
+    /// The verifier is a function pointer (and not a closure), so it does not carry the
+    /// (possibly non 'static) signature verifier types along to the pool threads.
+    pub fn verify_each<NI, T>(&self, network_info: &Arc<NI>, items: Vec<T>, verifier: fn(&Arc<NI>, &T) -> Result<bool>) -> Result<(bool, Vec<T>)>
+        where NI: Send + Sync + 'static,
+              T: Send + Sync + 'static {
+        if items.len() <= REQUEST_VERIFICATION_CHUNK || self.thread_count == 1 {
+            let valid = verify_chunk(network_info, &items, verifier, &AtomicBool::new(false))?;
+
+            return Ok((valid, items));
+        }
+
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let chunk_count = (items.len() + REQUEST_VERIFICATION_CHUNK - 1) / REQUEST_VERIFICATION_CHUNK;
+
+        let items = Arc::new(items);
+
+        let (result_tx, result_rx) = crossbeam::channel::bounded(chunk_count);
+
+        for chunk in 0..chunk_count {
+            let start = chunk * REQUEST_VERIFICATION_CHUNK;
+            let end = (start + REQUEST_VERIFICATION_CHUNK).min(items.len());
+
+            let items = items.clone();
+            let network_info = network_info.clone();
+            let failed = failed.clone();
+            let result_tx = result_tx.clone();
+
+            let job: VerificationJob = Box::new(move || {
+                let result = verify_chunk(&network_info, &items[start..end], verifier, &failed);
+
+                if !matches!(result, Ok(true)) {
+                    failed.store(true, Ordering::Relaxed);
+                }
+
+                // Release our reference to the items before reporting, so they can be handed back once every chunk is done
+                drop(items);
+
+                let _ = result_tx.send(result);
+            });
+
+            if let Err(_) = self.job_tx.send(job) {
+                error!("Request verification pool is disconnected");
+
+                return Err(Error::simple_with_msg(ErrorKind::Communication, "Request verification pool is disconnected"));
+            }
+        }
+
+        let mut valid = true;
+
+        for _ in 0..chunk_count {
+            let result = result_rx.recv()
+                .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Request verification job was dropped"))?;
+
+            valid &= result?;
+        }
+
+        let items = Arc::try_unwrap(items)
+            .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Verified requests are still shared with the verification pool"))?;
+
+        Ok((valid, items))
+    }
+}
+
+fn verify_chunk<NI, T>(network_info: &Arc<NI>, chunk: &[T], verifier: fn(&Arc<NI>, &T) -> Result<bool>, failed: &AtomicBool) -> Result<bool> {
+    for item in chunk {
+        // Another chunk has already failed, so there is no point in continuing
+        if failed.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        if !verifier(network_info, item)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
@@ -49,6 +49,27 @@ pub const RQ_PP_CLONE_PENDING_TIME_ID: usize = 023;
 pub const RQ_PP_COLLECT_PENDING_TIME: &str = "RQ_COLLECT_PENDING_TIME";
 pub const RQ_PP_COLLECT_PENDING_TIME_ID: usize = 024;
 
+pub const RQ_PP_REJECTED_RQS: &str = "RQ_PRE_PROCESSING_REJECTED_RQS";
+pub const RQ_PP_REJECTED_RQS_ID: usize = 025;
+
+pub const RQ_PP_SHED_RQS: &str = "RQ_PRE_PROCESSING_SHED_RQS";
+pub const RQ_PP_SHED_RQS_ID: usize = 026;
+
+pub const RQ_PP_BLOCKED_INTAKE: &str = "RQ_PRE_PROCESSING_BLOCKED_INTAKE";
+pub const RQ_PP_BLOCKED_INTAKE_ID: usize = 027;
+
+pub const RQ_PP_BATCH_FORMATION_TIME: &str = "RQ_PRE_PROCESSING_BATCH_FORMATION_TIME";
+pub const RQ_PP_BATCH_FORMATION_TIME_ID: usize = 028;
+
+pub const RQ_PP_BATCH_SIZE: &str = "RQ_PRE_PROCESSING_BATCH_SIZE";
+pub const RQ_PP_BATCH_SIZE_ID: usize = 029;
+
+pub const RQ_PP_CACHED_REPLIES: &str = "RQ_PRE_PROCESSING_CACHED_REPLIES";
+pub const RQ_PP_CACHED_REPLIES_ID: usize = 032;
+
+pub const RQ_PP_INVALID_RQS: &str = "RQ_PRE_PROCESSING_INVALID_RQS";
+pub const RQ_PP_INVALID_RQS_ID: usize = 033;
+
 // Timeout metrics
 
 pub const TIMEOUT_MESSAGE_PROCESSING: &str = "TIMEOUT_MESSAGE_PROCESSING";
@@ -57,6 +78,36 @@ pub const TIMEOUT_MESSAGE_PROCESSING_ID: usize = 030;
 pub const TIMEOUT_MESSAGES_PROCESSED: &str = "TIMEOUT_MESSAGES_PROCESSED";
 pub const TIMEOUT_MESSAGES_PROCESSED_ID: usize = 031;
 
+// Request forwarding metrics
+
+pub const RQ_FWD_FORWARDED_RQS: &str = "RQ_FORWARDING_FORWARDED_RQS";
+pub const RQ_FWD_FORWARDED_RQS_ID: usize = 034;
+
+pub const RQ_FWD_SUPPRESSED_RQS: &str = "RQ_FORWARDING_SUPPRESSED_RQS";
+pub const RQ_FWD_SUPPRESSED_RQS_ID: usize = 035;
+
+pub const RQ_FWD_OVER_BUDGET_RQS: &str = "RQ_FORWARDING_OVER_BUDGET_RQS";
+pub const RQ_FWD_OVER_BUDGET_RQS_ID: usize = 036;
+
+// Message verification metrics
+
+pub const MSG_VERIFICATION_TIME: &str = "MSG_VERIFICATION_TIME";
+pub const MSG_VERIFICATION_TIME_ID: usize = 037;
+
+pub const MSG_VERIFICATION_CACHE_HITS: &str = "MSG_VERIFICATION_CACHE_HITS";
+pub const MSG_VERIFICATION_CACHE_HITS_ID: usize = 038;
+
+pub const MSG_VERIFICATION_REJECTED: &str = "MSG_VERIFICATION_REJECTED";
+pub const MSG_VERIFICATION_REJECTED_ID: usize = 039;
+
+// State transfer and log transfer compression metrics
+
+pub const TRANSFER_COMPRESSED: &str = "TRANSFER_COMPRESSED_PAYLOADS";
+pub const TRANSFER_COMPRESSED_ID: usize = 040;
+
+pub const TRANSFER_BYTES_SAVED: &str = "TRANSFER_COMPRESSION_BYTES_SAVED";
+pub const TRANSFER_BYTES_SAVED_ID: usize = 041;
+
 pub fn metrics() -> Vec<MetricRegistry> {
     vec![
         (RQ_PP_CLIENT_MSG_ID, RQ_PP_CLIENT_MSG.to_string(), MetricKind::Duration).into(),
@@ -74,7 +125,22 @@ pub fn metrics() -> Vec<MetricRegistry> {
         (RQ_PP_WORKER_STOPPED_TIME_ID, RQ_PP_WORKER_STOPPED_TIME.to_string(), MetricKind::Duration, MetricLevel::Debug).into(),
         (RQ_PP_CLONE_PENDING_TIME_ID, RQ_PP_CLONE_PENDING_TIME.to_string(), MetricKind::Duration, MetricLevel::Debug).into(),
         (RQ_PP_COLLECT_PENDING_TIME_ID, RQ_PP_COLLECT_PENDING_TIME.to_string(), MetricKind::Duration, MetricLevel::Debug).into(),
+        (RQ_PP_REJECTED_RQS_ID, RQ_PP_REJECTED_RQS.to_string(), MetricKind::Counter).into(),
+        (RQ_PP_SHED_RQS_ID, RQ_PP_SHED_RQS.to_string(), MetricKind::Counter).into(),
+        (RQ_PP_BLOCKED_INTAKE_ID, RQ_PP_BLOCKED_INTAKE.to_string(), MetricKind::Counter).into(),
+        (RQ_PP_BATCH_FORMATION_TIME_ID, RQ_PP_BATCH_FORMATION_TIME.to_string(), MetricKind::Duration).into(),
+        (RQ_PP_BATCH_SIZE_ID, RQ_PP_BATCH_SIZE.to_string(), MetricKind::Count).into(),
+        (RQ_PP_CACHED_REPLIES_ID, RQ_PP_CACHED_REPLIES.to_string(), MetricKind::Counter).into(),
+        (RQ_PP_INVALID_RQS_ID, RQ_PP_INVALID_RQS.to_string(), MetricKind::Counter).into(),
         (TIMEOUT_MESSAGE_PROCESSING_ID, TIMEOUT_MESSAGE_PROCESSING.to_string(), MetricKind::Duration, MetricLevel::Debug).into(),
         (TIMEOUT_MESSAGES_PROCESSED_ID, TIMEOUT_MESSAGES_PROCESSED.to_string(), MetricKind::Counter, MetricLevel::Debug).into(),
+        (RQ_FWD_FORWARDED_RQS_ID, RQ_FWD_FORWARDED_RQS.to_string(), MetricKind::Counter).into(),
+        (RQ_FWD_SUPPRESSED_RQS_ID, RQ_FWD_SUPPRESSED_RQS.to_string(), MetricKind::Counter).into(),
+        (RQ_FWD_OVER_BUDGET_RQS_ID, RQ_FWD_OVER_BUDGET_RQS.to_string(), MetricKind::Counter).into(),
+        (MSG_VERIFICATION_TIME_ID, MSG_VERIFICATION_TIME.to_string(), MetricKind::Duration).into(),
+        (MSG_VERIFICATION_CACHE_HITS_ID, MSG_VERIFICATION_CACHE_HITS.to_string(), MetricKind::Counter).into(),
+        (MSG_VERIFICATION_REJECTED_ID, MSG_VERIFICATION_REJECTED.to_string(), MetricKind::Counter).into(),
+        (TRANSFER_COMPRESSED_ID, TRANSFER_COMPRESSED.to_string(), MetricKind::Counter).into(),
+        (TRANSFER_BYTES_SAVED_ID, TRANSFER_BYTES_SAVED.to_string(), MetricKind::Counter).into(),
     ]
 }
\ No newline at end of file
@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use atlas_common::crypto::hash::Digest;
+use atlas_common::node_id::NodeId;
+use atlas_execution::serialize::ApplicationData;
+use atlas_metrics::metrics::metric_increment;
+
+use crate::messages::{ClientRqInfo, ForwardedRequestsMessage, StoredRequestMessage};
+use crate::metric::{RQ_FWD_FORWARDED_RQS_ID, RQ_FWD_OVER_BUDGET_RQS_ID, RQ_FWD_SUPPRESSED_RQS_ID};
+use crate::ordering_protocol::networking::OrderProtocolSendNode;
+use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
+use crate::request_pre_processing::RequestPreProcessor;
+use crate::timeouts::{RqTimeout, TimeoutKind, TimeoutPhase};
+
+/// To whom should the requests that have timed out be forwarded
+#[derive(Clone, Copy, Debug)]
+pub enum ForwardingPolicy {
+    /// Forward only to the current leader
+    ToLeader,
+    /// Forward to every member of the quorum
+    ToQuorum,
+    /// Forward to the current leader until the request has timed out more than
+    /// the given amount of times, after which it's forwarded to the whole quorum
+    LeaderThenQuorum(usize),
+}
+
+/// The configuration of the [RequestForwarder]
+#[derive(Clone, Debug)]
+pub struct ForwardingConfig {
+    pub policy: ForwardingPolicy,
+    /// A request that has been forwarded to a given peer is not forwarded
+    /// to that same peer again until this much time has passed
+    pub suppression_window: Duration,
+    /// The maximum amount of request payload bytes that can be forwarded to each peer
+    /// in a budget period. Requests above the budget are held back until they time out again
+    pub peer_byte_budget: usize,
+    /// The duration of each budget period
+    pub budget_period: Duration,
+}
+
+/// The results of a forwarding round
+#[derive(Default, Debug)]
+pub struct ForwardingReport {
+    /// The amount of requests that were forwarded to each peer
+    pub forwarded: BTreeMap<NodeId, usize>,
+    /// The requests that had already been forwarded recently to the chosen targets
+    pub suppressed: usize,
+    /// The requests that did not fit in the byte budget of the chosen targets
+    pub over_budget: usize,
+    /// The peers we failed to send the forwarded requests to
+    pub failed: Vec<NodeId>,
+}
+
+/// The bytes that have been forwarded to a peer in the current budget period
+struct PeerBudget {
+    period_start: Instant,
+    spent: usize,
+}
+
+/// The requests chosen to be forwarded to a peer in a forwarding round
+struct PlannedForward<O> {
+    requests: Vec<StoredRequestMessage<O>>,
+    digests: Vec<Digest>,
+    bytes: usize,
+}
+
+/// Decides when and to whom the client requests should be forwarded, so ordering protocols
+/// don't have to.
+///
+/// It's driven by the client request timeouts delivered by [crate::timeouts::Timeouts]:
+/// the requests which timed out are fetched from the [RequestPreProcessor] and forwarded
+/// according to the [ForwardingPolicy], skipping the requests that have already been
+/// forwarded to the same peer recently and respecting each peer's byte budget.
+pub struct RequestForwarder {
+    config: ForwardingConfig,
+    /// The peers each request was forwarded to, and when
+    forwarded: HashMap<Digest, Vec<(NodeId, Instant)>>,
+    budgets: BTreeMap<NodeId, PeerBudget>,
+}
+
+impl RequestForwarder {
+    pub fn new(config: ForwardingConfig) -> Self {
+        Self {
+            config,
+            forwarded: Default::default(),
+            budgets: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> &ForwardingConfig {
+        &self.config
+    }
+
+    /// Forward the client requests of the given timeouts.
+    ///
+    /// `leader` is the current leader, if known, and `quorum` the members of the current quorum.
+    /// Timeouts that are not client request timeouts are ignored.
+    pub fn forward_timed_out<D, OPM, NT>(&mut self, node: &NT, pre_processor: &RequestPreProcessor<D::Request>,
+                                         timeouts: &[RqTimeout], leader: Option<NodeId>, quorum: &[NodeId]) -> ForwardingReport
+        where D: ApplicationData + 'static,
+              OPM: OrderingProtocolMessage<D>,
+              NT: OrderProtocolSendNode<D, OPM> {
+        let mut report = ForwardingReport::default();
+
+        let now = Instant::now();
+
+        self.collect_expired(now);
+
+        let mut timeout_counts = HashMap::with_capacity(timeouts.len());
+
+        let client_rqs: Vec<ClientRqInfo> = timeouts.iter().filter_map(|timeout| {
+            match (timeout.timeout_kind(), timeout.timeout_phase()) {
+                (TimeoutKind::ClientRequestTimeout(rq_info), TimeoutPhase::TimedOut(count, _)) => {
+                    timeout_counts.insert(rq_info.digest(), *count);
+
+                    Some(rq_info.clone())
+                }
+                _ => None
+            }
+        }).collect();
+
+        if client_rqs.is_empty() {
+            return report;
+        }
+
+        let requests = pre_processor.clone_pending_rqs(client_rqs);
+
+        let mut per_target: BTreeMap<NodeId, PlannedForward<D::Request>> = BTreeMap::new();
+
+        for request in requests {
+            let digest = request.header().unique_digest();
+            let rq_bytes = request.header().payload_length();
+
+            let timeout_count = timeout_counts.get(&digest).copied().unwrap_or(1);
+
+            let targets = self.targets_for(node.id(), timeout_count, leader, quorum);
+
+            for target in targets {
+                if self.recently_forwarded(&digest, target) {
+                    report.suppressed += 1;
+
+                    continue;
+                }
+
+                let planned = per_target.entry(target).or_insert_with(|| PlannedForward {
+                    requests: Vec::new(),
+                    digests: Vec::new(),
+                    bytes: 0,
+                });
+
+                if !self.fits_budget(target, planned.bytes, rq_bytes, now) {
+                    report.over_budget += 1;
+
+                    continue;
+                }
+
+                planned.requests.push(request.clone());
+                planned.digests.push(digest.clone());
+                planned.bytes += rq_bytes;
+            }
+        }
+
+        for (target, planned) in per_target {
+            let PlannedForward { requests, digests, bytes } = planned;
+
+            let rq_count = requests.len();
+
+            if rq_count == 0 {
+                continue;
+            }
+
+            debug!("{:?} // Forwarding {} requests to {:?}", node.id(), rq_count, target);
+
+            if let Err(failed) = node.forward_requests(ForwardedRequestsMessage::new(requests), std::iter::once(target)) {
+                warn!("{:?} // Failed to forward requests to {:?}", node.id(), failed);
+
+                report.failed.extend(failed);
+
+                continue;
+            }
+
+            // Only what was actually sent counts towards the suppression window and the budget,
+            // so the requests that failed to be sent can be retried on the next timeout
+            self.spend_budget(target, bytes);
+
+            for digest in digests {
+                self.forwarded.entry(digest).or_default().push((target, now));
+            }
+
+            report.forwarded.insert(target, rq_count);
+
+            metric_increment(RQ_FWD_FORWARDED_RQS_ID, Some(rq_count as u64));
+        }
+
+        metric_increment(RQ_FWD_SUPPRESSED_RQS_ID, Some(report.suppressed as u64));
+        metric_increment(RQ_FWD_OVER_BUDGET_RQS_ID, Some(report.over_budget as u64));
+
+        report
+    }
+
+    /// The given requests have been decided, so we no longer have to keep track of them
+    pub fn requests_decided(&mut self, requests: &[ClientRqInfo]) {
+        for request in requests {
+            self.forwarded.remove(&request.digest());
+        }
+    }
+
+    /// The peers a request that has timed out the given amount of times should be forwarded to.
+    /// We never forward to ourselves
+    fn targets_for(&self, our_id: NodeId, timeout_count: usize, leader: Option<NodeId>, quorum: &[NodeId]) -> Vec<NodeId> {
+        let to_leader = match self.config.policy {
+            ForwardingPolicy::ToLeader => true,
+            ForwardingPolicy::ToQuorum => false,
+            ForwardingPolicy::LeaderThenQuorum(escalate_after) => timeout_count <= escalate_after,
+        };
+
+        match leader {
+            // We are the leader ourselves, so the request is already where it should be
+            Some(leader) if to_leader && leader == our_id => Vec::new(),
+            Some(leader) if to_leader => vec![leader],
+            // When the leader is not known, we can only fall back to the quorum
+            _ => quorum.iter().copied().filter(|node| *node != our_id).collect()
+        }
+    }
+
+    fn recently_forwarded(&self, digest: &Digest, target: NodeId) -> bool {
+        self.forwarded.get(digest)
+            .map(|targets| targets.iter().any(|(node, _)| *node == target))
+            .unwrap_or(false)
+    }
+
+    /// Check whether a request fits in the budget of the given peer, on top of the
+    /// bytes already planned to be forwarded to it in this round
+    fn fits_budget(&mut self, target: NodeId, planned_bytes: usize, rq_bytes: usize, now: Instant) -> bool {
+        let budget = self.budgets.entry(target).or_insert_with(|| PeerBudget {
+            period_start: now,
+            spent: 0,
+        });
+
+        if now.duration_since(budget.period_start) >= self.config.budget_period {
+            budget.period_start = now;
+            budget.spent = 0;
+        }
+
+        let spent = budget.spent + planned_bytes;
+
+        // A request larger than the whole budget is still forwarded at the start of a period,
+        // otherwise it would never be forwarded
+        spent == 0 || spent + rq_bytes <= self.config.peer_byte_budget
+    }
+
+    /// Charge the bytes that were forwarded to the given peer to its budget
+    fn spend_budget(&mut self, target: NodeId, bytes: usize) {
+        if let Some(budget) = self.budgets.get_mut(&target) {
+            budget.spent += bytes;
+        }
+    }
+
+    /// Forget the forwards that are older than the suppression window
+    fn collect_expired(&mut self, now: Instant) {
+        let window = self.config.suppression_window;
+
+        self.forwarded.retain(|_, targets| {
+            targets.retain(|(_, forwarded_at)| now.duration_since(*forwarded_at) < window);
+
+            !targets.is_empty()
+        });
+    }
+}
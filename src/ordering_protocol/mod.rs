@@ -12,6 +12,7 @@ use atlas_execution::serialize::ApplicationData;
 
 use crate::messages::{ClientRqInfo, Protocol};
 use crate::ordering_protocol::networking::serialize::{OrderingProtocolMessage, PermissionedOrderingProtocolMessage};
+use crate::ordering_protocol::speculative::{SpeculationOutcome, SpeculativeDecision};
 use crate::persistent_log::OrderingProtocolLog;
 use crate::request_pre_processing::{BatchOutput, RequestPreProcessor};
 use crate::timeouts::{RqTimeout, Timeouts};
@@ -19,6 +20,8 @@ use crate::timeouts::{RqTimeout, Timeouts};
 pub mod reconfigurable_order_protocol;
 pub mod stateful_order_protocol;
 pub mod networking;
+pub mod speculative;
+pub mod forwarding;
 
 pub type View<POP: PermissionedOrderingProtocolMessage> = <POP as PermissionedOrderingProtocolMessage>::ViewInfo;
 
@@ -107,6 +110,11 @@ pub enum OrderProtocolPoll<P, O> {
     Decided(Vec<ProtocolConsensusDecision<O>>),
     QuorumJoined(Option<Vec<ProtocolConsensusDecision<O>>>, NodeId, Vec<NodeId>),
     RePoll,
+    /// Tentative decisions, which can be executed speculatively (only when speculation is enabled,
+    /// see [speculative::SpeculativeOrderingProtocol])
+    Tentative(Vec<SpeculativeDecision<O>>),
+    /// The outcome of previously delivered tentative decisions
+    SpeculationResolved(Vec<SpeculationOutcome>),
 }
 
 /// Result from executing a message in the ordering protocol
@@ -115,6 +123,11 @@ pub enum OrderProtocolExecResult<O> {
     Decided(Vec<ProtocolConsensusDecision<O>>),
     RunCst,
     QuorumJoined(Option<Vec<ProtocolConsensusDecision<O>>>, NodeId, Vec<NodeId>),
+    /// Tentative decisions, which can be executed speculatively (only when speculation is enabled,
+    /// see [speculative::SpeculativeOrderingProtocol])
+    Tentative(Vec<SpeculativeDecision<O>>),
+    /// The outcome of previously delivered tentative decisions
+    SpeculationResolved(Vec<SpeculationOutcome>),
 }
 
 /// Information reported after a logging operation.
@@ -180,6 +193,12 @@ impl<P, O> Debug for OrderProtocolPoll<P, O> where P: Debug {
             OrderProtocolPoll::QuorumJoined(decs, node, quorum) => {
                 write!(f, "{:?} Joined Quorum. Current: {:?}. {} decisions", node, quorum, decs.as_ref().map(|d| d.len()).unwrap_or(0))
             }
+            OrderProtocolPoll::Tentative(decisions) => {
+                write!(f, "{} tentative decisions", decisions.len())
+            }
+            OrderProtocolPoll::SpeculationResolved(outcomes) => {
+                write!(f, "Speculation outcomes {:?}", outcomes)
+            }
         }
     }
 }
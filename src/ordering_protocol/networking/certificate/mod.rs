@@ -0,0 +1,209 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Serialize};
+
+use atlas_common::crypto::hash::{Context, Digest};
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::{Orderable, SeqNo};
+use atlas_communication::reconfiguration_node::NetworkInformationProvider;
+
+use crate::ordering_protocol::networking::serialize::OrderProtocolProof;
+
+/// A scheme to produce compact quorum certificates, by aggregating (or combining, in the
+/// case of threshold signatures) the signature shares of a quorum of replicas over a decision digest.
+///
+/// The digest given to the scheme is always the one produced by [certified_digest], which binds the
+/// sequence number of the decision, so a certificate can't be replayed for another sequence number.
+///
+/// A certificate is verified with a single check, instead of one signature check per replica
+/// as with the proofs made up of individually signed messages.
+pub trait CertificateScheme<NI>: Send + Sync where NI: NetworkInformationProvider {
+    /// The share produced by a single replica
+    #[cfg(feature = "serialize_capnp")]
+    type SignatureShare: Send + Clone;
+
+    #[cfg(feature = "serialize_serde")]
+    type SignatureShare: for<'a> Deserialize<'a> + Serialize + Send + Clone;
+
+    /// The certificate obtained from a quorum of shares
+    #[cfg(feature = "serialize_capnp")]
+    type Certificate: Send + Clone;
+
+    #[cfg(feature = "serialize_serde")]
+    type Certificate: for<'a> Deserialize<'a> + Serialize + Send + Clone;
+
+    /// Produce our own share over the given digest
+    fn create_share(network_info: &Arc<NI>, digest: &Digest) -> Result<Self::SignatureShare>;
+
+    /// Verify the share of a given replica over the given digest
+    fn verify_share(network_info: &Arc<NI>, from: NodeId, digest: &Digest, share: &Self::SignatureShare) -> Result<bool>;
+
+    /// Combine a quorum of (already verified) shares into a certificate
+    fn aggregate(network_info: &Arc<NI>, digest: &Digest, shares: &[(NodeId, Self::SignatureShare)]) -> Result<Self::Certificate>;
+
+    /// Verify a certificate over the given digest, signed by the given replicas
+    fn verify_certificate(network_info: &Arc<NI>, digest: &Digest, signers: &[NodeId], certificate: &Self::Certificate) -> Result<bool>;
+}
+
+/// A certificate that a quorum of replicas agreed on the decision with the given digest,
+/// for the given sequence number.
+///
+/// It can be used directly as (or as part of) the [OrderingProtocolMessage::Proof](crate::ordering_protocol::networking::serialize::OrderingProtocolMessage::Proof)
+/// of a protocol, so the proofs shipped in the log transfer and with the sequence numbers
+/// carry a single certificate instead of a signature per replica.
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct QuorumCertificate<C> {
+    seq: SeqNo,
+    digest: Digest,
+    signers: Vec<NodeId>,
+    certificate: C,
+}
+
+/// Collects the signature shares of the replicas for a given decision,
+/// until there are enough of them to form a [QuorumCertificate]
+pub struct CertificateBuilder<NI, CS> where NI: NetworkInformationProvider, CS: CertificateScheme<NI> {
+    seq: SeqNo,
+    digest: Digest,
+    /// The digest the shares are produced over, see [certified_digest]
+    certified_digest: Digest,
+    quorum_members: Vec<NodeId>,
+    quorum: usize,
+    shares: Vec<(NodeId, CS::SignatureShare)>,
+    _phantom: PhantomData<fn() -> NI>,
+}
+
+/// The digest that is actually signed by the replicas for the decision with the given digest,
+/// at the given sequence number
+pub fn certified_digest(seq: SeqNo, digest: &Digest) -> Digest {
+    let mut context = Context::new();
+
+    context.update(&u32::from(seq).to_le_bytes());
+    context.update(digest.as_ref());
+
+    context.finish()
+}
+
+impl<C> QuorumCertificate<C> {
+    pub fn new(seq: SeqNo, digest: Digest, signers: Vec<NodeId>, certificate: C) -> Self {
+        Self {
+            seq,
+            digest,
+            signers,
+            certificate,
+        }
+    }
+
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    pub fn signers(&self) -> &Vec<NodeId> {
+        &self.signers
+    }
+
+    pub fn certificate(&self) -> &C {
+        &self.certificate
+    }
+
+    pub fn into_inner(self) -> (SeqNo, Digest, Vec<NodeId>, C) {
+        (self.seq, self.digest, self.signers, self.certificate)
+    }
+}
+
+impl<C> Orderable for QuorumCertificate<C> {
+    fn sequence_number(&self) -> SeqNo {
+        self.seq
+    }
+}
+
+impl<C> OrderProtocolProof for QuorumCertificate<C> {}
+
+impl<NI, CS> CertificateBuilder<NI, CS> where NI: NetworkInformationProvider, CS: CertificateScheme<NI> {
+    /// Collect the shares of the given quorum members, until `quorum` of them are collected
+    pub fn new(seq: SeqNo, digest: Digest, quorum_members: Vec<NodeId>, quorum: usize) -> Self {
+        Self {
+            seq,
+            certified_digest: certified_digest(seq, &digest),
+            digest,
+            quorum_members,
+            quorum,
+            shares: Vec::with_capacity(quorum),
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Produce our own share for this decision
+    pub fn create_share(&self, network_info: &Arc<NI>) -> Result<CS::SignatureShare> {
+        CS::create_share(network_info, &self.certified_digest)
+    }
+
+    /// The amount of valid shares collected so far
+    pub fn share_count(&self) -> usize {
+        self.shares.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.shares.len() >= self.quorum
+    }
+
+    /// Register the share of a given replica.
+    /// Returns false if the share is not valid, the replica is not a member of the quorum
+    /// or we already have a share from that replica
+    pub fn register_share(&mut self, network_info: &Arc<NI>, from: NodeId, share: CS::SignatureShare) -> Result<bool> {
+        if !self.quorum_members.contains(&from) || self.shares.iter().any(|(node, _)| *node == from) {
+            return Ok(false);
+        }
+
+        if !CS::verify_share(network_info, from, &self.certified_digest, &share)? {
+            return Ok(false);
+        }
+
+        self.shares.push((from, share));
+
+        Ok(true)
+    }
+
+    /// Build the certificate, if we already have a quorum of shares
+    pub fn build(&self, network_info: &Arc<NI>) -> Result<Option<QuorumCertificate<CS::Certificate>>> {
+        if !self.is_complete() {
+            return Ok(None);
+        }
+
+        let shares = &self.shares[..self.quorum];
+
+        let certificate = CS::aggregate(network_info, &self.certified_digest, shares)?;
+
+        let signers = shares.iter().map(|(node, _)| *node).collect();
+
+        Ok(Some(QuorumCertificate::new(self.seq, self.digest.clone(), signers, certificate)))
+    }
+}
+
+/// Verify a quorum certificate: it must be signed by at least `quorum` distinct members of the
+/// given quorum and the (single) aggregated signature must be valid for the certified digest
+/// at the certificate's sequence number
+pub fn verify_quorum_certificate<NI, CS>(network_info: &Arc<NI>, certificate: &QuorumCertificate<CS::Certificate>,
+                                         quorum_members: &[NodeId], quorum: usize) -> Result<bool>
+    where NI: NetworkInformationProvider, CS: CertificateScheme<NI> {
+    let mut signers = certificate.signers().clone();
+
+    signers.sort();
+    signers.dedup();
+
+    if signers.len() != certificate.signers().len() {
+        // Duplicate signers
+        return Ok(false);
+    }
+
+    if signers.len() < quorum || !signers.iter().all(|signer| quorum_members.contains(signer)) {
+        return Ok(false);
+    }
+
+    let digest = certified_digest(certificate.sequence_number(), certificate.digest());
+
+    CS::verify_certificate(network_info, &digest, certificate.signers(), certificate.certificate())
+}
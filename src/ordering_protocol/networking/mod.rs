@@ -1,7 +1,9 @@
 pub mod signature_ver;
 pub mod serialize;
+pub mod certificate;
 
 use std::collections::BTreeMap;
+use log::error;
 use std::sync::Arc;
 use atlas_common::crypto::hash::Digest;
 use atlas_common::node_id::NodeId;
@@ -13,7 +15,8 @@ use atlas_communication::reconfiguration_node::NetworkInformationProvider;
 use atlas_communication::serialize::{Buf, Serializable};
 use atlas_execution::serialize::ApplicationData;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
-use crate::messages::{ForwardedRequestsMessage, SystemMessage};
+use crate::messages::{ForwardedRequestsMessage, Protocol, SystemMessage};
+use crate::messages::authenticator::{authenticator_for, MessageClass};
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
 use crate::serialize::Service;
 use crate::smr::networking::NodeWrap;
@@ -54,6 +57,20 @@ pub trait OrderProtocolSendNode<D, OPM>: Send + Sync where D: ApplicationData +
     /// on the success of the message dispatch
     fn broadcast_signed(&self, message: OPM::ProtocolMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>>;
 
+    /// Sends a message to a given target, authenticated as dictated by our [AuthenticationPolicy](crate::messages::authenticator::AuthenticationPolicy):
+    /// either signed or carrying an [Authenticator](crate::messages::authenticator::Authenticator) with the MAC for the target.
+    /// Does not block on the message sent. Returns a result that is
+    /// Ok if there is a current connection to the target or err if not. No other checks are made
+    /// on the success of the message dispatch
+    fn send_authenticated(&self, message: OPM::ProtocolMessage, target: NodeId, flush: bool) -> Result<()>;
+
+    /// Broadcast a message to all of the given targets, authenticated as dictated by our policy
+    /// (a single authenticator carries the MACs for all of the targets).
+    /// Does not block on the message sent. Returns a result that is
+    /// Ok if there is a current connection to the targets or err if not. No other checks are made
+    /// on the success of the message dispatch
+    fn broadcast_authenticated(&self, message: OPM::ProtocolMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>>;
+
     /// Serialize a message to a given target.
     /// Creates the serialized byte buffer along with the header, so we can send it later.
     fn serialize_digest_message(&self, message: OPM::ProtocolMessage) -> Result<(SerializedMessage<OPM::ProtocolMessage>, Digest)>;
@@ -107,6 +124,46 @@ impl<NT, D, P, S, L, RM, NI> OrderProtocolSendNode<D, P> for NodeWrap<NT, D, P,
         self.0.broadcast_signed(SystemMessage::from_protocol_message(message), targets)
     }
 
+    fn send_authenticated(&self, message: P::ProtocolMessage, target: NodeId, flush: bool) -> Result<()> {
+        let authentication = match authenticator_for(MessageClass::OrderProtocol) {
+            Some(authentication) => authentication,
+            None => return self.0.send_signed(SystemMessage::from_protocol_message(message), target, flush),
+        };
+
+        let protocol = Protocol::new(message);
+
+        let digest = Service::<D, P, S, L>::protocol_authentication_digest(self.0.id(), protocol.wire_version(), protocol.payload())?;
+
+        let authenticator = authentication.authenticate(&digest, std::iter::once(target))?;
+
+        self.0.send(SystemMessage::ProtocolMessage(protocol.with_authenticator(Some(authenticator))), target, flush)
+    }
+
+    fn broadcast_authenticated(&self, message: P::ProtocolMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>> {
+        let authentication = match authenticator_for(MessageClass::OrderProtocol) {
+            Some(authentication) => authentication,
+            None => return self.0.broadcast_signed(SystemMessage::from_protocol_message(message), targets),
+        };
+
+        let targets: Vec<NodeId> = targets.collect();
+
+        let protocol = Protocol::new(message);
+
+        let authenticator = Service::<D, P, S, L>::protocol_authentication_digest(self.0.id(), protocol.wire_version(), protocol.payload())
+            .and_then(|digest| authentication.authenticate(&digest, targets.iter().copied()));
+
+        match authenticator {
+            Ok(authenticator) => {
+                self.0.broadcast(SystemMessage::ProtocolMessage(protocol.with_authenticator(Some(authenticator))), targets.into_iter())
+            }
+            Err(err) => {
+                error!("Failed to authenticate order protocol message: {:?}", err);
+
+                Err(targets)
+            }
+        }
+    }
+
     /// Why do we do this wrapping/unwrapping? Well, since we want to avoid having to store all of the
     /// generics that are used at the replica level (with all message types), we can't
     /// just return a system message type.
@@ -9,6 +9,10 @@ use atlas_communication::message::Header;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
 use atlas_smr_application::serialize::ApplicationData;
 use crate::ordering_protocol::networking::signature_ver::OrderProtocolSignatureVerificationHelper;
+#[cfg(feature = "serialize_capnp")]
+use crate::serialize::versioning::WireVersion;
+#[cfg(feature = "serialize_serde")]
+use crate::serialize::versioning::VersionedDeserialize;
 
 /// The basic methods needed for a view
 pub trait NetworkView: Orderable + Clone {
@@ -40,11 +44,19 @@ pub trait PermissionedOrderingProtocolMessage: Send + Sync {
 
     #[cfg(feature = "serialize_serde")]
     type ViewInfo: NetworkView + for<'a> Deserialize<'a> + Serialize + Send + Clone + Debug;
-    
+
+    #[cfg(feature = "serialize_capnp")]
+    fn serialize_view_capnp(builder: capnp::any_pointer::Builder, msg: &Self::ViewInfo) -> Result<()>;
+
+    #[cfg(feature = "serialize_capnp")]
+    fn deserialize_view_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::ViewInfo>;
 }
 
 /// We do not need a serde module since serde serialization is just done on the network level.
 /// The abstraction for ordering protocol messages.
+///
+/// With capnp, the messages, proofs and views are written into the `AnyPointer` slots of the
+/// atlas-core schema (see `schemas/messages.capnp`), so each protocol can use its own schema for them.
 pub trait OrderingProtocolMessage<D>: Send + Sync {
 
     /// The general protocol type for all messages in the ordering protocol
@@ -52,7 +64,7 @@ pub trait OrderingProtocolMessage<D>: Send + Sync {
     type ProtocolMessage: Orderable + Send + Clone;
 
     #[cfg(feature = "serialize_serde")]
-    type ProtocolMessage: Orderable + for<'a> Deserialize<'a> + Serialize + Send + Clone + Debug;
+    type ProtocolMessage: Orderable + VersionedDeserialize + Serialize + Send + Clone + Debug;
 
     /// A shortcut type to messages that are going to be logged. (this is useful for situations
     /// where we don't log all message types that we send)
@@ -80,36 +92,40 @@ pub trait OrderingProtocolMessage<D>: Send + Sync {
     #[cfg(feature = "serialize_capnp")]
     type ProofMetadata: Orderable + Send + Clone;
 
+    /// Verify the signatures of a message (and of the messages it carries), without taking ownership of it
     fn verify_order_protocol_message<NI, OPVH>(network_info: &Arc<NI>,
                                                header: &Header,
-                                               message: Self::ProtocolMessage) -> Result<(bool, Self::ProtocolMessage)>
+                                               message: &Self::ProtocolMessage) -> Result<bool>
         where NI: NetworkInformationProvider,
               OPVH: OrderProtocolSignatureVerificationHelper<D, Self, NI>,
               D: ApplicationData, Self: Sized;
 
     fn verify_proof<NI, OPVH>(network_info: &Arc<NI>,
-                              proof: Self::Proof) -> Result<(bool, Self::Proof)>
+                              proof: &Self::Proof) -> Result<bool>
         where NI: NetworkInformationProvider,
               OPVH: OrderProtocolSignatureVerificationHelper<D, Self, NI>,
               D: ApplicationData, Self: Sized;
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(builder: febft_capnp::consensus_messages_capnp::protocol_message::Builder, msg: &Self::ProtocolMessage) -> Result<()>;
-
-    #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(reader: febft_capnp::consensus_messages_capnp::protocol_message::Reader) -> Result<Self::ProtocolMessage>;
+    fn serialize_capnp(builder: capnp::any_pointer::Builder, msg: &Self::ProtocolMessage) -> Result<()>;
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_view_capnp(builder: febft_capnp::cst_messages_capnp::view_info::Builder, msg: &Self::ViewInfo) -> Result<()>;
+    fn deserialize_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::ProtocolMessage>;
 
+    /// Decode a message encoded by a peer which is still running the previous wire version
+    /// ([MIN_SUPPORTED_WIRE_VERSION](crate::serialize::versioning::MIN_SUPPORTED_WIRE_VERSION)).
+    /// The wire version is read before the payload, so the reader is still undecoded.
+    /// By default, no previous version is supported
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_view_capnp(reader: febft_capnp::cst_messages_capnp::view_info::Reader) -> Result<Self::ViewInfo>;
+    fn deserialize_previous_capnp(_version: WireVersion, _reader: capnp::any_pointer::Reader) -> Result<Self::ProtocolMessage> {
+        Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "No decoder for the protocol messages of the previous wire version"))
+    }
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_proof_capnp(builder: febft_capnp::cst_messages_capnp::proof::Builder, msg: &Self::Proof) -> Result<()>;
+    fn serialize_proof_capnp(builder: capnp::any_pointer::Builder, msg: &Self::Proof) -> Result<()>;
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_proof_capnp(reader: febft_capnp::cst_messages_capnp::proof::Reader) -> Result<Self::Proof>;
+    fn deserialize_proof_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::Proof>;
 }
 
 /// The messages for the stateful ordering protocol
@@ -122,16 +138,16 @@ pub trait StatefulOrderProtocolMessage<D, OPM>: Send + Sync {
     #[cfg(feature = "serialize_serde")]
     type DecLog: OrderProtocolLog + for<'a> Deserialize<'a> + Serialize + Send + Clone;
 
-    fn verify_decision_log<NI, OPVH>(network_info: &Arc<NI>, dec_log: Self::DecLog)
-                                            -> Result<(bool, Self::DecLog)>
+    fn verify_decision_log<NI, OPVH>(network_info: &Arc<NI>, dec_log: &Self::DecLog)
+                                            -> Result<bool>
         where NI: NetworkInformationProvider,
               D: ApplicationData,
               OPM: OrderingProtocolMessage<D>,
               OPVH: OrderProtocolSignatureVerificationHelper<D, OPM, NI>,;
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_declog_capnp(builder: febft_capnp::cst_messages_capnp::dec_log::Builder, msg: &Self::DecLog) -> Result<()>;
+    fn serialize_declog_capnp(builder: capnp::any_pointer::Builder, msg: &Self::DecLog) -> Result<()>;
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_declog_capnp(reader: febft_capnp::cst_messages_capnp::dec_log::Reader) -> Result<Self::DecLog>;
+    fn deserialize_declog_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::DecLog>;
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
+use atlas_common::crypto::hash::Digest;
 use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
 use atlas_communication::message::Header;
 use atlas_communication::message_signing::NetworkMessageSignatureVerifier;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
@@ -8,21 +10,40 @@ use atlas_execution::serialize::ApplicationData;
 
 use crate::log_transfer::networking::serialize::LogTransferMessage;
 use crate::messages::{ReplyMessage, RequestMessage, SystemMessage};
+use crate::messages::authenticator::{Authenticator, MacKeyProvider};
 use crate::messages::signature_ver::SigVerifier;
+use crate::ordering_protocol::networking::certificate;
+use crate::ordering_protocol::networking::certificate::{CertificateScheme, QuorumCertificate};
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
 use crate::serialize::Service;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 
 /// This is a helper trait to verify signatures of messages for the ordering protocol
 pub trait OrderProtocolSignatureVerificationHelper<D, OP, NI> where D: ApplicationData, OP: OrderingProtocolMessage<D>, NI: NetworkInformationProvider {
-    /// This is a helper to verify internal player requests
-    fn verify_request_message(network_info: &Arc<NI>, header: &Header, request: RequestMessage<D::Request>) -> Result<(bool, RequestMessage<D::Request>)>;
+    /// This is a helper to verify internal player requests.
+    /// Requests whose operation can't be decoded are not valid
+    fn verify_request_message(network_info: &Arc<NI>, header: &Header, request: &RequestMessage<D::Request>) -> Result<bool>;
 
     /// Another helper to verify internal player replies
-    fn verify_reply_message(network_info: &Arc<NI>, header: &Header, reply: ReplyMessage<D::Reply>) -> Result<(bool, ReplyMessage<D::Reply>)>;
+    fn verify_reply_message(network_info: &Arc<NI>, header: &Header, reply: &ReplyMessage<D::Reply>) -> Result<bool>;
 
     /// helper mostly to verify forwarded consensus messages, for example
-    fn verify_protocol_message(network_info: &Arc<NI>, header: &Header, message: OP::ProtocolMessage) -> Result<(bool, OP::ProtocolMessage)>;
+    fn verify_protocol_message(network_info: &Arc<NI>, header: &Header, message: &OP::ProtocolMessage) -> Result<bool>;
+
+    /// helper to verify the compact quorum certificates used in proofs (and decision logs),
+    /// instead of verifying each of the messages of the quorum
+    fn verify_quorum_certificate<CS>(network_info: &Arc<NI>, certificate: &QuorumCertificate<CS::Certificate>,
+                                     quorum_members: &[NodeId], quorum: usize) -> Result<bool>
+        where CS: CertificateScheme<NI> {
+        certificate::verify_quorum_certificate::<NI, CS>(network_info, certificate, quorum_members, quorum)
+    }
+
+    /// helper to verify messages sent in the [AuthenticationMode::Authenticator](crate::messages::authenticator::AuthenticationMode::Authenticator) mode,
+    /// which carry a vector of MACs (over the given authentication digest) instead of a signature
+    fn verify_authenticator<K>(keys: &K, header: &Header, digest: &Digest, authenticator: &Authenticator) -> Result<bool>
+        where K: MacKeyProvider + ?Sized {
+        Ok(authenticator.verify(keys, header.from(), digest))
+    }
 }
 
 impl<SV, NI, D, P, S, L> OrderProtocolSignatureVerificationHelper<D, P, NI> for SigVerifier<SV, NI, D, P, S, L>
@@ -33,24 +54,28 @@ impl<SV, NI, D, P, S, L> OrderProtocolSignatureVerificationHelper<D, P, NI> for
           NI: NetworkInformationProvider + 'static,
           SV: NetworkMessageSignatureVerifier<Service<D, P, S, L>, NI>
 {
-    fn verify_request_message(network_info: &Arc<NI>, header: &Header, request: RequestMessage<D::Request>) -> Result<(bool, RequestMessage<D::Request>)> {
-        let message = SystemMessage::<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>::OrderedRequest(request);
+    fn verify_request_message(network_info: &Arc<NI>, header: &Header, request: &RequestMessage<D::Request>) -> Result<bool> {
+        // The network signature verifier consumes the message it checks. A request only holds a handle
+        // to its (shared) payload, so this hands it a handle without copying the operation
+        let message = SystemMessage::<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>::OrderedRequest(request.clone());
+
+        let (valid, _) = SV::verify_signature(network_info, header, message)?;
 
-        SV::verify_signature(network_info, header, message)
-            .map(|(valid, msg)| { (valid, if let SystemMessage::OrderedRequest(r) = msg { r } else { unreachable!() }) })
+        // The operation is decoded here (and shared by every handle to the request), so the requests
+        // with malformed operations are rejected before they ever reach the executors
+        Ok(valid && request.try_operation().is_ok())
     }
 
-    fn verify_reply_message(network_info: &Arc<NI>, header: &Header, reply: ReplyMessage<D::Reply>) -> Result<(bool, ReplyMessage<D::Reply>)> {
-        let message = SystemMessage::<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>::OrderedReply(reply);
+    fn verify_reply_message(network_info: &Arc<NI>, header: &Header, reply: &ReplyMessage<D::Reply>) -> Result<bool> {
+        // As with requests, the network signature verifier consumes the message it checks
+        let message = SystemMessage::<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>::OrderedReply(reply.clone());
 
-        SV::verify_signature(network_info, header, message)
-            .map(|(valid, msg)| { (valid, if let SystemMessage::OrderedReply(r) = msg { r } else { unreachable!() }) })
+        SV::verify_signature(network_info, header, message).map(|(valid, _)| valid)
     }
 
-    fn verify_protocol_message(network_info: &Arc<NI>, header: &Header, message: P::ProtocolMessage) -> Result<(bool, P::ProtocolMessage)> {
-        let message = SystemMessage::<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>::from_protocol_message(message);
+    fn verify_protocol_message(network_info: &Arc<NI>, header: &Header, message: &P::ProtocolMessage) -> Result<bool> {
+        let message = SystemMessage::<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>::from_protocol_message(message.clone());
 
-        SV::verify_signature(network_info, header, message)
-            .map(|(valid, msg)| { (valid, if let SystemMessage::ProtocolMessage(r) = msg { r.into_inner() } else { unreachable!() }) })
+        SV::verify_signature(network_info, header, message).map(|(valid, _)| valid)
     }
 }
\ No newline at end of file
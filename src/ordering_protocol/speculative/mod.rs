@@ -0,0 +1,405 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+
+use atlas_common::error::*;
+use atlas_common::ordering::{Orderable, SeqNo};
+use atlas_execution::app::UpdateBatch;
+
+use crate::ordering_protocol::{OrderProtocolExecResult, OrderProtocolPoll, ProtocolConsensusDecision};
+
+/// Tells the application what to do with a batch before executing it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UpdateBatchTag {
+    /// The batch is committed, so it can be executed normally
+    Committed,
+    /// The batch is tentative. The application must take a rollback point before executing it,
+    /// so the execution can be undone if the decision is aborted
+    TakeRollbackPoint,
+}
+
+/// A decision that has not yet been committed by the ordering protocol,
+/// which can be executed speculatively
+pub struct SpeculativeDecision<O> {
+    /// The sequence number of the batch
+    seq: SeqNo,
+    /// The batch to be executed speculatively
+    executable_batch: UpdateBatch<O>,
+    /// What the application must do before executing this batch
+    tag: UpdateBatchTag,
+}
+
+/// The outcome of a tentative decision
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpeculationOutcome {
+    /// The tentative decision with this sequence number was committed.
+    /// The results of its speculative execution can be made visible
+    Commit(SeqNo),
+    /// The tentative decision with this sequence number was aborted. The application must roll back to
+    /// the rollback point taken before executing it, which discards every later tentative decision
+    Abort(SeqNo),
+}
+
+/// What the executor must do after a speculation outcome, as decided by the [SpeculationTracker]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SpeculationAction {
+    /// Release the results of the speculative execution of these decisions
+    Release(Vec<SeqNo>),
+    /// Restore the rollback point taken before the given decision.
+    /// All of the listed tentative decisions (the aborted one and every one after it) are discarded
+    RollBack(SeqNo, Vec<SeqNo>),
+}
+
+/// An ordering protocol which can, optionally, deliver tentative decisions before they are
+/// committed (through [crate::ordering_protocol::OrderProtocolExecResult::Tentative] and
+/// [crate::ordering_protocol::OrderProtocolPoll::Tentative]), followed by their outcome.
+pub trait SpeculativeOrderingProtocol {
+    /// Enable or disable the delivery of tentative decisions. It's disabled by default
+    fn set_speculation(&mut self, enabled: bool);
+
+    /// Are tentative decisions being delivered
+    fn is_speculating(&self) -> bool;
+}
+
+/// The executor side of speculative execution, which receives every batch along with its [UpdateBatchTag]
+/// and the actions decided by the [SpeculationTracker]
+pub trait SpeculativeExecutor<O>: Send {
+    /// Queue a batch for execution. When the tag is [UpdateBatchTag::TakeRollbackPoint], the
+    /// application must take a rollback point before executing it and hold back its results
+    fn queue_update(&self, batch: UpdateBatch<O>, tag: UpdateBatchTag) -> Result<()>;
+
+    /// Release the held back results of the given tentative decisions, which have been committed
+    fn release(&self, decisions: Vec<SeqNo>) -> Result<()>;
+
+    /// Restore the rollback point taken before the given decision, discarding the results of the listed decisions
+    fn roll_back(&self, rollback_point: SeqNo, discarded: Vec<SeqNo>) -> Result<()>;
+}
+
+/// Delivers the committed and tentative decisions of the ordering protocol to a [SpeculativeExecutor],
+/// tagging each batch and keeping the [SpeculationTracker] in sync with the outcomes
+pub struct SpeculativeDelivery<EX> {
+    tracker: SpeculationTracker,
+    executor: EX,
+}
+
+/// Keeps track of the tentative decisions that have been executed speculatively
+/// and have not yet been committed or aborted.
+///
+/// Tentative decisions must be delivered in order and are committed in order. Aborting a
+/// decision also aborts every tentative decision after it, since they were executed over its results.
+#[derive(Default)]
+pub struct SpeculationTracker {
+    outstanding: VecDeque<SeqNo>,
+}
+
+impl<O> SpeculativeDecision<O> {
+    /// A tentative decision, which must be executed over a rollback point
+    pub fn new(seq: SeqNo, executable_batch: UpdateBatch<O>) -> Self {
+        Self {
+            seq,
+            executable_batch,
+            tag: UpdateBatchTag::TakeRollbackPoint,
+        }
+    }
+
+    /// A decision that is already committed, so it is executed normally
+    pub fn committed(seq: SeqNo, executable_batch: UpdateBatch<O>) -> Self {
+        Self {
+            seq,
+            executable_batch,
+            tag: UpdateBatchTag::Committed,
+        }
+    }
+
+    pub fn tag(&self) -> UpdateBatchTag {
+        self.tag
+    }
+
+    pub fn update_batch(&self) -> &UpdateBatch<O> {
+        &self.executable_batch
+    }
+
+    pub fn into(self) -> (SeqNo, UpdateBatch<O>, UpdateBatchTag) {
+        (self.seq, self.executable_batch, self.tag)
+    }
+}
+
+impl<O> From<ProtocolConsensusDecision<O>> for SpeculativeDecision<O> {
+    fn from(decision: ProtocolConsensusDecision<O>) -> Self {
+        let (seq, executable_batch, _) = decision.into();
+
+        Self::committed(seq, executable_batch)
+    }
+}
+
+impl<O> Orderable for SpeculativeDecision<O> {
+    fn sequence_number(&self) -> SeqNo {
+        self.seq
+    }
+}
+
+impl<O> Debug for SpeculativeDecision<O> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SpeculativeDecision {{ seq: {:?}, executable_batch: {:?}, tag: {:?} }}", self.seq, self.executable_batch.len(), self.tag)
+    }
+}
+
+impl SpeculationOutcome {
+    pub fn seq_no(&self) -> SeqNo {
+        match self {
+            SpeculationOutcome::Commit(seq) => *seq,
+            SpeculationOutcome::Abort(seq) => *seq,
+        }
+    }
+}
+
+impl SpeculationTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The tentative decisions that are awaiting their outcome
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Register a decision which is about to be executed. Tentative decisions are kept until their
+    /// outcome is known, while committed ones must not be ahead of any outstanding tentative decision
+    pub fn register_tentative<O>(&mut self, decision: &SpeculativeDecision<O>) -> Result<()> {
+        if decision.tag() == UpdateBatchTag::Committed {
+            return self.committed_directly(decision.sequence_number());
+        }
+
+        if let Some(last) = self.outstanding.back() {
+            if *last >= decision.sequence_number() {
+                return Err(Error::simple_with_msg(ErrorKind::Communication, "Tentative decisions must be delivered in order"));
+            }
+        }
+
+        self.outstanding.push_back(decision.sequence_number());
+
+        Ok(())
+    }
+
+    /// Process the outcome of a tentative decision
+    pub fn resolve(&mut self, outcome: SpeculationOutcome) -> Result<SpeculationAction> {
+        match outcome {
+            SpeculationOutcome::Commit(seq) => {
+                match self.outstanding.front() {
+                    Some(oldest) if *oldest == seq => {
+                        self.outstanding.pop_front();
+
+                        Ok(SpeculationAction::Release(vec![seq]))
+                    }
+                    _ => Err(Error::simple_with_msg(ErrorKind::Communication, "Tentative decisions must be committed in order"))
+                }
+            }
+            SpeculationOutcome::Abort(seq) => {
+                let position = self.outstanding.iter().position(|outstanding| *outstanding == seq)
+                    .ok_or_else(|| Error::simple_with_msg(ErrorKind::Communication, "Aborted a decision that is not tentative"))?;
+
+                let discarded: Vec<SeqNo> = self.outstanding.drain(position..).collect();
+
+                Ok(SpeculationAction::RollBack(seq, discarded))
+            }
+        }
+    }
+
+    /// Process a committed decision that was not delivered tentatively.
+    /// Every tentative decision before it must have been committed already
+    pub fn committed_directly(&mut self, seq: SeqNo) -> Result<()> {
+        match self.outstanding.front() {
+            Some(oldest) if *oldest <= seq => {
+                Err(Error::simple_with_msg(ErrorKind::Communication, "Decision committed ahead of outstanding tentative decisions"))
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl<EX> SpeculativeDelivery<EX> {
+    pub fn new(executor: EX) -> Self {
+        Self {
+            tracker: SpeculationTracker::new(),
+            executor,
+        }
+    }
+
+    pub fn tracker(&self) -> &SpeculationTracker {
+        &self.tracker
+    }
+
+    pub fn executor(&self) -> &EX {
+        &self.executor
+    }
+
+    /// Deliver decisions which were committed without being delivered tentatively
+    pub fn deliver_decided<O>(&mut self, decisions: Vec<ProtocolConsensusDecision<O>>) -> Result<()>
+        where EX: SpeculativeExecutor<O> {
+        self.deliver(decisions.into_iter().map(SpeculativeDecision::from).collect())
+    }
+
+    /// Deliver decisions to the executor, each batch tagged with what the application must do before executing it
+    pub fn deliver<O>(&mut self, decisions: Vec<SpeculativeDecision<O>>) -> Result<()>
+        where EX: SpeculativeExecutor<O> {
+        for decision in decisions {
+            self.tracker.register_tentative(&decision)?;
+
+            let (_, batch, tag) = decision.into();
+
+            self.executor.queue_update(batch, tag)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the outcomes of previously delivered tentative decisions
+    pub fn resolve<O>(&mut self, outcomes: Vec<SpeculationOutcome>) -> Result<()>
+        where EX: SpeculativeExecutor<O> {
+        for outcome in outcomes {
+            match self.tracker.resolve(outcome)? {
+                SpeculationAction::Release(decisions) => self.executor.release(decisions)?,
+                SpeculationAction::RollBack(rollback_point, discarded) => self.executor.roll_back(rollback_point, discarded)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle the decisions in the result of executing a message in the ordering protocol.
+    /// Returns the result back if it carries no decisions
+    pub fn process_exec_result<O>(&mut self, result: OrderProtocolExecResult<O>) -> Result<Option<OrderProtocolExecResult<O>>>
+        where EX: SpeculativeExecutor<O> {
+        match result {
+            OrderProtocolExecResult::Decided(decisions) => self.deliver_decided(decisions)?,
+            OrderProtocolExecResult::Tentative(decisions) => self.deliver(decisions)?,
+            OrderProtocolExecResult::SpeculationResolved(outcomes) => self.resolve::<O>(outcomes)?,
+            result => return Ok(Some(result)),
+        }
+
+        Ok(None)
+    }
+
+    /// Handle the decisions in the result of polling the ordering protocol.
+    /// Returns the poll result back if it carries no decisions
+    pub fn process_poll<P, O>(&mut self, poll: OrderProtocolPoll<P, O>) -> Result<Option<OrderProtocolPoll<P, O>>>
+        where EX: SpeculativeExecutor<O> {
+        match poll {
+            OrderProtocolPoll::Decided(decisions) => self.deliver_decided(decisions)?,
+            OrderProtocolPoll::Tentative(decisions) => self.deliver(decisions)?,
+            OrderProtocolPoll::SpeculationResolved(outcomes) => self.resolve::<O>(outcomes)?,
+            poll => return Ok(Some(poll)),
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn seq(seq_no: u32) -> SeqNo {
+        SeqNo::from(seq_no)
+    }
+
+    fn tentative(seq_no: u32) -> SpeculativeDecision<()> {
+        SpeculativeDecision::new(seq(seq_no), UpdateBatch::new(seq(seq_no)))
+    }
+
+    fn committed(seq_no: u32) -> SpeculativeDecision<()> {
+        SpeculativeDecision::committed(seq(seq_no), UpdateBatch::new(seq(seq_no)))
+    }
+
+    #[derive(Default)]
+    struct RecordingExecutor {
+        queued: Mutex<Vec<UpdateBatchTag>>,
+        actions: Mutex<Vec<SpeculationAction>>,
+    }
+
+    impl SpeculativeExecutor<()> for RecordingExecutor {
+        fn queue_update(&self, _batch: UpdateBatch<()>, tag: UpdateBatchTag) -> Result<()> {
+            self.queued.lock().unwrap().push(tag);
+
+            Ok(())
+        }
+
+        fn release(&self, decisions: Vec<SeqNo>) -> Result<()> {
+            self.actions.lock().unwrap().push(SpeculationAction::Release(decisions));
+
+            Ok(())
+        }
+
+        fn roll_back(&self, rollback_point: SeqNo, discarded: Vec<SeqNo>) -> Result<()> {
+            self.actions.lock().unwrap().push(SpeculationAction::RollBack(rollback_point, discarded));
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tentative_decisions_commit_in_order() {
+        let mut tracker = SpeculationTracker::new();
+
+        tracker.register_tentative(&tentative(1)).unwrap();
+        tracker.register_tentative(&tentative(2)).unwrap();
+
+        assert!(tracker.register_tentative(&tentative(2)).is_err());
+        assert!(tracker.resolve(SpeculationOutcome::Commit(seq(2))).is_err());
+
+        assert_eq!(tracker.resolve(SpeculationOutcome::Commit(seq(1))).unwrap(), SpeculationAction::Release(vec![seq(1)]));
+        assert_eq!(tracker.resolve(SpeculationOutcome::Commit(seq(2))).unwrap(), SpeculationAction::Release(vec![seq(2)]));
+        assert_eq!(tracker.outstanding(), 0);
+    }
+
+    #[test]
+    fn abort_cascades_to_later_tentative_decisions() {
+        let mut tracker = SpeculationTracker::new();
+
+        for seq_no in 1..=4 {
+            tracker.register_tentative(&tentative(seq_no)).unwrap();
+        }
+
+        assert_eq!(tracker.resolve(SpeculationOutcome::Abort(seq(2))).unwrap(),
+                   SpeculationAction::RollBack(seq(2), vec![seq(2), seq(3), seq(4)]));
+        assert_eq!(tracker.outstanding(), 1);
+
+        assert!(tracker.resolve(SpeculationOutcome::Abort(seq(3))).is_err());
+        assert_eq!(tracker.resolve(SpeculationOutcome::Commit(seq(1))).unwrap(), SpeculationAction::Release(vec![seq(1)]));
+    }
+
+    #[test]
+    fn committed_directly_ahead_of_tentative_decisions() {
+        let mut tracker = SpeculationTracker::new();
+
+        assert!(tracker.committed_directly(seq(1)).is_ok());
+
+        tracker.register_tentative(&tentative(2)).unwrap();
+
+        assert!(tracker.committed_directly(seq(3)).is_err());
+        assert!(tracker.register_tentative(&committed(3)).is_err());
+        assert_eq!(tracker.outstanding(), 1);
+
+        tracker.resolve(SpeculationOutcome::Commit(seq(2))).unwrap();
+
+        assert!(tracker.committed_directly(seq(3)).is_ok());
+    }
+
+    #[test]
+    fn delivery_tags_batches_and_applies_outcomes() {
+        let mut delivery = SpeculativeDelivery::new(RecordingExecutor::default());
+
+        delivery.deliver(vec![committed(1), tentative(2), tentative(3)]).unwrap();
+
+        assert_eq!(*delivery.executor().queued.lock().unwrap(),
+                   vec![UpdateBatchTag::Committed, UpdateBatchTag::TakeRollbackPoint, UpdateBatchTag::TakeRollbackPoint]);
+        assert_eq!(delivery.tracker().outstanding(), 2);
+
+        delivery.resolve::<()>(vec![SpeculationOutcome::Commit(seq(2)), SpeculationOutcome::Abort(seq(3))]).unwrap();
+
+        assert_eq!(*delivery.executor().actions.lock().unwrap(),
+                   vec![SpeculationAction::Release(vec![seq(2)]), SpeculationAction::RollBack(seq(3), vec![seq(3)])]);
+        assert_eq!(delivery.tracker().outstanding(), 0);
+    }
+}
@@ -1,6 +1,7 @@
 use atlas_common::error::Result;
 use atlas_common::ordering::SeqNo;
 use atlas_smr_application::serialize::ApplicationData;
+use crate::messages::StoredRequestMessage;
 use crate::ordering_protocol::{OrderingProtocol, OrderingProtocolArgs, PermissionedOrderingProtocol, SerProof, View};
 use crate::ordering_protocol::networking::serialize::{OrderingProtocolMessage, StatefulOrderProtocolMessage};
 use crate::persistent_log::StatefulOrderingProtocolLog;
@@ -23,8 +24,10 @@ pub trait StatefulOrderProtocol<D,  NT, PL>: OrderingProtocol<D, NT, PL> + Permi
     /// Should only alter the necessary things within its own state and
     /// then should return the state and a list of all requests that should
     /// then be executed by the application.
+    /// The requests are returned as they were ordered, so the request pre processor can also be
+    /// told they have been decided (see [crate::log_transfer::LTResult::finished])
     fn install_state(&mut self, view_info: View<Self::PermissionedSerialization>,
-                     dec_log: DecLog<D, Self::Serialization, Self::StateSerialization>) -> Result<Vec<D::Request>>
+                     dec_log: DecLog<D, Self::Serialization, Self::StateSerialization>) -> Result<Vec<StoredRequestMessage<D::Request>>>
         where PL: StatefulOrderingProtocolLog<D, Self::Serialization, Self::StateSerialization, Self::PermissionedSerialization>;
 
     /// Snapshot the current log of the replica
@@ -0,0 +1,144 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use futures::channel::oneshot;
+use futures::Stream;
+
+use atlas_common::channel::{ChannelSyncTx, OneShotTx, RecvError, TryRecvError};
+use atlas_common::error::*;
+
+use crate::request_pre_processing::{BatchOutput, PreProcessorOutput, PreProcessorOutputMessage};
+
+/// Where the orchestrator should deliver the response to a request made through the
+/// [crate::request_pre_processing::RequestPreProcessor] handle.
+/// Async callers get a future aware channel, so waiting for the response does not park an executor thread
+pub enum PreProcessorResponder<T> {
+    Sync(OneShotTx<T>),
+    Async(oneshot::Sender<T>),
+}
+
+impl<T> PreProcessorResponder<T> {
+    pub fn send(self, value: T) -> Result<()> {
+        match self {
+            PreProcessorResponder::Sync(tx) => {
+                tx.send(value).map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Failed to deliver pre processor response"))
+            }
+            PreProcessorResponder::Async(tx) => {
+                tx.send(value).map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Async pre processor response receiver was dropped"))
+            }
+        }
+    }
+}
+
+/// The wakers of the async consumers of a [BatchOutput] that are waiting for requests
+#[derive(Default)]
+pub(super) struct BatchNotifier {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl BatchNotifier {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn notify_all(&self) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// The sending side of the batch output, used by the workers.
+/// Wakes up any async consumer of the [BatchOutput] whenever a new message is delivered
+pub(super) struct BatchProducer<O> {
+    tx: ChannelSyncTx<PreProcessorOutput<O>>,
+    notifier: Arc<BatchNotifier>,
+}
+
+impl<O> BatchProducer<O> {
+    pub(super) fn new(tx: ChannelSyncTx<PreProcessorOutput<O>>, notifier: Arc<BatchNotifier>) -> Self {
+        Self { tx, notifier }
+    }
+
+    pub(super) fn try_send(&self, message: PreProcessorOutputMessage<O>) -> Result<()> {
+        let result = self.tx.try_send((message, Instant::now()));
+
+        if result.is_ok() {
+            self.notifier.notify_all();
+        }
+
+        result.map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Failed to send requests to batch production"))
+    }
+}
+
+impl<O> Clone for BatchProducer<O> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            notifier: self.notifier.clone(),
+        }
+    }
+}
+
+impl<O> BatchOutput<O> {
+    /// Receive the next output of the pre processor without blocking the current thread
+    pub fn recv_async(&self) -> BatchRecv<'_, O> {
+        BatchRecv { output: self }
+    }
+
+    /// Turn this output into a [Stream] of the pre processor's outputs.
+    /// The stream ends when all of the workers have disconnected
+    pub fn into_stream(self) -> BatchStream<O> {
+        BatchStream { output: self }
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<std::result::Result<PreProcessorOutputMessage<O>, RecvError>> {
+        match self.try_recv() {
+            Ok(message) => return Poll::Ready(Ok(message)),
+            Err(TryRecvError::ChannelEmpty) | Err(TryRecvError::Timeout) => {}
+            Err(_) => return Poll::Ready(Err(RecvError::ChannelDc)),
+        }
+
+        self.2.register(cx.waker());
+
+        // Check again, as a message might have been delivered before we registered our waker
+        match self.try_recv() {
+            Ok(message) => Poll::Ready(Ok(message)),
+            Err(TryRecvError::ChannelEmpty) | Err(TryRecvError::Timeout) => Poll::Pending,
+            Err(_) => Poll::Ready(Err(RecvError::ChannelDc)),
+        }
+    }
+}
+
+/// The future returned by [BatchOutput::recv_async]
+pub struct BatchRecv<'a, O> {
+    output: &'a BatchOutput<O>,
+}
+
+impl<'a, O> Future for BatchRecv<'a, O> {
+    type Output = std::result::Result<PreProcessorOutputMessage<O>, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.output.poll_recv(cx)
+    }
+}
+
+/// A [Stream] over the outputs of the pre processor, see [BatchOutput::into_stream]
+pub struct BatchStream<O> {
+    output: BatchOutput<O>,
+}
+
+impl<O> Stream for BatchStream<O> {
+    type Item = PreProcessorOutputMessage<O>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.output.poll_recv(cx).map(|result| result.ok())
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What the pre processor should do when the global pending request budget is exhausted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverloadPolicy {
+    /// Reject newly arrived client requests, replying to the client with
+    /// [crate::messages::RejectionReason::Overloaded]
+    RejectNew,
+    /// Accept the new requests but evict the oldest pending requests of the worker
+    /// until we are back under the budget. Evicted requests are replied to with
+    /// [crate::messages::RejectionReason::Shed]
+    ShedOldest,
+    /// Stop receiving requests from the clients until there is space in the budget again.
+    /// This pushes the backpressure back into the network layer
+    BlockIntake,
+}
+
+/// The configuration for the pending request budget of the pre processor
+#[derive(Clone, Debug)]
+pub struct PendingRqBudgetConfig {
+    /// The maximum amount of requests that can be pending across all workers
+    pub max_pending_rqs: usize,
+    /// The maximum amount of payload bytes that can be pending across all workers
+    pub max_pending_bytes: usize,
+    /// The behaviour when the budget is exhausted
+    pub overload_policy: OverloadPolicy,
+}
+
+/// The global budget of pending requests, shared by the orchestrator and all of the workers.
+///
+/// This is a soft limit: the orchestrator only checks it before dispatching work,
+/// so it can be exceeded by the requests that are already in flight to the workers.
+/// Requests forwarded by other replicas and requests collected from a view change are
+/// always accepted (and accounted for), since dropping them could hurt liveness.
+pub struct PendingRqBudget {
+    config: PendingRqBudgetConfig,
+    pending_rqs: AtomicUsize,
+    pending_bytes: AtomicUsize,
+}
+
+impl PendingRqBudget {
+    pub fn new(config: PendingRqBudgetConfig) -> Self {
+        Self {
+            config,
+            pending_rqs: AtomicUsize::new(0),
+            pending_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> OverloadPolicy {
+        self.config.overload_policy
+    }
+
+    /// Account for a request that is now pending
+    pub fn reserve(&self, bytes: usize) {
+        self.pending_rqs.fetch_add(1, Ordering::Relaxed);
+        self.pending_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Account for a request that is no longer pending
+    pub fn release(&self, bytes: usize) {
+        self.pending_rqs.fetch_sub(1, Ordering::Relaxed);
+        self.pending_bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// The amount of requests that are currently pending
+    pub fn pending_rqs(&self) -> usize {
+        self.pending_rqs.load(Ordering::Relaxed)
+    }
+
+    /// The amount of payload bytes that are currently pending
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Have we used up the entire budget?
+    pub fn is_exhausted(&self) -> bool {
+        self.pending_rqs() >= self.config.max_pending_rqs
+            || self.pending_bytes() >= self.config.max_pending_bytes
+    }
+
+    /// Should the orchestrator stop receiving requests from the clients
+    pub fn should_block_intake(&self) -> bool {
+        self.config.overload_policy == OverloadPolicy::BlockIntake && self.is_exhausted()
+    }
+
+    /// Should newly received client requests be rejected
+    pub fn should_reject_new(&self) -> bool {
+        self.config.overload_policy == OverloadPolicy::RejectNew && self.is_exhausted()
+    }
+}
+
+impl Default for PendingRqBudgetConfig {
+    fn default() -> Self {
+        Self {
+            max_pending_rqs: usize::MAX,
+            max_pending_bytes: usize::MAX,
+            overload_policy: OverloadPolicy::BlockIntake,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_pending_rqs: usize, max_pending_bytes: usize, overload_policy: OverloadPolicy) -> PendingRqBudget {
+        PendingRqBudget::new(PendingRqBudgetConfig {
+            max_pending_rqs,
+            max_pending_bytes,
+            overload_policy,
+        })
+    }
+
+    #[test]
+    fn exhausted_by_request_count() {
+        let budget = budget(2, usize::MAX, OverloadPolicy::RejectNew);
+
+        budget.reserve(10);
+        assert!(!budget.is_exhausted());
+
+        budget.reserve(10);
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.pending_rqs(), 2);
+        assert_eq!(budget.pending_bytes(), 20);
+
+        budget.release(10);
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn exhausted_by_payload_bytes() {
+        let budget = budget(usize::MAX, 100, OverloadPolicy::RejectNew);
+
+        budget.reserve(60);
+        assert!(!budget.is_exhausted());
+
+        budget.reserve(40);
+        assert!(budget.is_exhausted());
+
+        budget.release(60);
+        assert!(!budget.is_exhausted());
+        assert_eq!(budget.pending_rqs(), 1);
+        assert_eq!(budget.pending_bytes(), 40);
+    }
+
+    #[test]
+    fn reject_new_policy() {
+        let budget = budget(1, usize::MAX, OverloadPolicy::RejectNew);
+
+        assert!(!budget.should_reject_new());
+
+        budget.reserve(1);
+
+        assert!(budget.should_reject_new());
+        assert!(!budget.should_block_intake());
+    }
+
+    #[test]
+    fn block_intake_policy() {
+        let budget = budget(1, usize::MAX, OverloadPolicy::BlockIntake);
+
+        assert!(!budget.should_block_intake());
+
+        budget.reserve(1);
+
+        assert!(budget.should_block_intake());
+        assert!(!budget.should_reject_new());
+
+        budget.release(1);
+
+        assert!(!budget.should_block_intake());
+    }
+
+    #[test]
+    fn shed_oldest_policy_neither_blocks_nor_rejects() {
+        let budget = budget(1, usize::MAX, OverloadPolicy::ShedOldest);
+
+        budget.reserve(1);
+        budget.reserve(1);
+
+        assert!(budget.is_exhausted());
+        assert!(!budget.should_block_intake());
+        assert!(!budget.should_reject_new());
+    }
+
+    #[test]
+    fn default_budget_is_never_exhausted() {
+        let budget = PendingRqBudget::new(PendingRqBudgetConfig::default());
+
+        budget.reserve(1 << 20);
+
+        assert!(!budget.is_exhausted());
+        assert!(!budget.should_block_intake());
+    }
+}
@@ -0,0 +1,82 @@
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Serialize};
+
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::{Orderable, SeqNo};
+
+use crate::request_pre_processing::operation_key_raw;
+
+/// The latest operation that has been decided for a given client session
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SessionLatestOp {
+    client: NodeId,
+    session: SeqNo,
+    seq_no: SeqNo,
+}
+
+/// A snapshot of the latest decided operation of every client session known to the
+/// request pre processor.
+///
+/// This is taken along with the checkpoints, so that a replica that restarts (or installs
+/// a state from other replicas) does not propose requests which have already been ordered.
+/// Since decided batches are delivered to the pre processor before they are executed, a snapshot
+/// may contain operations decided after the checkpoint. These are still contained in the
+/// decision log, so treating them as already ordered is safe. For the same reason, replicas can
+/// take different snapshots for the same checkpoint, so they are not covered by its digest.
+///
+/// The operations are kept sorted by their session, so the same table always produces
+/// the same snapshot.
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatestOpsSnapshot {
+    ops: Vec<SessionLatestOp>,
+}
+
+impl SessionLatestOp {
+    pub fn new(client: NodeId, session: SeqNo, seq_no: SeqNo) -> Self {
+        Self { client, session, seq_no }
+    }
+
+    pub fn client(&self) -> NodeId {
+        self.client
+    }
+
+    pub fn session(&self) -> SeqNo {
+        self.session
+    }
+
+    pub fn operation_key(&self) -> u64 {
+        operation_key_raw(self.client, self.session)
+    }
+}
+
+impl Orderable for SessionLatestOp {
+    fn sequence_number(&self) -> SeqNo {
+        self.seq_no
+    }
+}
+
+impl LatestOpsSnapshot {
+    pub fn new(mut ops: Vec<SessionLatestOp>) -> Self {
+        ops.sort_unstable_by_key(SessionLatestOp::operation_key);
+
+        Self { ops }
+    }
+
+    pub fn ops(&self) -> &Vec<SessionLatestOp> {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn into_inner(self) -> Vec<SessionLatestOp> {
+        self.ops
+    }
+}
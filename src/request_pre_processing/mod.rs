@@ -1,36 +1,51 @@
+use std::collections::VecDeque;
 use std::iter;
-use std::marker::PhantomData;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use log::error;
+use futures::channel::{mpsc, oneshot};
+use log::{error, info, warn};
 
 use atlas_common::channel;
 use atlas_common::channel::{ChannelSyncRx, ChannelSyncTx, new_bounded_sync, OneShotRx, OneShotTx, RecvError, TryRecvError};
 use atlas_common::crypto::hash::Digest;
+use atlas_common::error::*;
 use atlas_common::globals::ReadOnly;
 use atlas_common::node_id::NodeId;
 use atlas_common::ordering::{Orderable, SeqNo};
 use atlas_communication::message::{Header, NetworkMessage, StoredMessage};
 use atlas_communication::protocol_node::{NodeIncomingRqHandler, ProtocolNetworkNode};
 use atlas_execution::serialize::ApplicationData;
-use atlas_metrics::metrics::{metric_duration, metric_increment};
+use atlas_metrics::metrics::{metric_duration, metric_increment, metric_store_count};
 use crate::log_transfer::networking::serialize::LogTransferMessage;
 
-use crate::messages::{ClientRqInfo, ForwardedRequestsMessage, RequestMessage, StoredRequestMessage, SystemMessage};
-use crate::metric::{RQ_PP_CLIENT_COUNT_ID, RQ_PP_CLIENT_MSG_ID, RQ_PP_CLONE_PENDING_TIME_ID, RQ_PP_CLONE_RQS_ID, RQ_PP_COLLECT_PENDING_ID, RQ_PP_COLLECT_PENDING_TIME_ID, RQ_PP_DECIDED_RQS_ID, RQ_PP_FWD_RQS_ID, RQ_PP_TIMEOUT_RQS_ID, RQ_PP_WORKER_PROPOSER_PASSING_TIME_ID, RQ_PP_WORKER_STOPPED_TIME_ID};
+use crate::messages::{ClientRqInfo, ForwardedRequestsMessage, RejectionReason, RequestMessage, RequestRejectedMessage, StoredRequestMessage, SystemMessage};
+use crate::metric::{RQ_PP_BATCH_FORMATION_TIME_ID, RQ_PP_BATCH_SIZE_ID, RQ_PP_BLOCKED_INTAKE_ID, RQ_PP_CACHED_REPLIES_ID, RQ_PP_CLIENT_COUNT_ID, RQ_PP_CLIENT_MSG_ID, RQ_PP_CLONE_PENDING_TIME_ID, RQ_PP_CLONE_RQS_ID, RQ_PP_COLLECT_PENDING_ID, RQ_PP_COLLECT_PENDING_TIME_ID, RQ_PP_DECIDED_RQS_ID, RQ_PP_FWD_RQS_ID, RQ_PP_REJECTED_RQS_ID, RQ_PP_TIMEOUT_RQS_ID, RQ_PP_WORKER_PROPOSER_PASSING_TIME_ID, RQ_PP_WORKER_STOPPED_TIME_ID};
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
-use crate::request_pre_processing::worker::{PreProcessorWorkMessage, PreProcessorWorkMessageOuter, RequestPreProcessingWorker, RequestPreProcessingWorkerHandle};
+use crate::request_pre_processing::async_output::{BatchNotifier, BatchProducer, PreProcessorResponder};
+use crate::request_pre_processing::budget::{PendingRqBudget, PendingRqBudgetConfig};
+use crate::request_pre_processing::latest_ops::{LatestOpsSnapshot, SessionLatestOp};
+use crate::request_pre_processing::validation::RequestValidationHook;
+use crate::request_pre_processing::worker::{PreProcessorWorkMessage, PreProcessorWorkMessageOuter, RequestPreProcessingWorker, RequestPreProcessingWorkerHandle, SessionFilter, SessionMigration};
 use crate::serialize::Service;
+use crate::smr::exec::reply_cache::{CachedReplyLookup, ReplyCache};
+use crate::smr::exec::unordered::UnorderedRequestSink;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 use crate::timeouts::{RqTimeout, TimeoutKind, Timeouts};
 
 mod worker;
 pub mod work_dividers;
+pub mod budget;
+pub mod latest_ops;
+pub mod async_output;
+pub mod validation;
+pub mod session_window;
 
 const ORCHESTRATOR_RCV_TIMEOUT: Option<Duration> = Some(Duration::from_micros(50));
+const BLOCKED_INTAKE_DELAY: Duration = Duration::from_micros(50);
 const PROPOSER_QUEUE_SIZE: usize = 16384;
+const REJECTED_QUEUE_SIZE: usize = 1024;
 
 const RQ_PRE_PROCESSING_ORCHESTRATOR: &str = "RQ-PRE-PROCESSING-ORCHESTRATOR";
 
@@ -38,18 +53,51 @@ const RQ_PRE_PROCESSING_ORCHESTRATOR: &str = "RQ-PRE-PROCESSING-ORCHESTRATOR";
 /// This should sign a contract to maintain all client sessions in the same worker, never changing
 /// A session is defined by the client ID and the session ID.
 ///
-pub trait WorkPartitioner<O>: Send {
+/// Each request pre processor owns its partitioner, which is shared with the workers when they are resized
+pub trait WorkPartitioner<O>: Send + Sync {
     /// Get the worker that should process this request
-    fn get_worker_for(rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize;
+    fn get_worker_for(&self, rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize;
 
     /// Get the worker that should process this request
-    fn get_worker_for_processed(rq_info: &ClientRqInfo, worker_count: usize) -> usize;
+    fn get_worker_for_processed(&self, rq_info: &ClientRqInfo, worker_count: usize) -> usize;
+
+    /// Get the worker that handles the given client session
+    fn get_worker_for_raw(&self, client: NodeId, session: SeqNo, worker_count: usize) -> usize;
+
+    /// An ordered request of the given session has been accepted by its worker (after being deduplicated
+    /// and validated) and is now pending
+    fn request_admitted(&self, _client: NodeId, _session: SeqNo) {}
+
+    /// A pending request of the given session is no longer pending, as it has been decided, rejected or discarded.
+    /// This is only reported for requests that were reported by [WorkPartitioner::request_admitted]
+    fn request_completed(&self, _client: NodeId, _session: SeqNo) {}
 }
 
 type PreProcessorOutput<O> = (PreProcessorOutputMessage<O>, Instant);
 
+/// Requests that were rejected by the workers, along with the reason for the rejection
+type RejectedRequests = (Vec<ClientRqInfo>, RejectionReason);
+
+/// The output of the pre processor, to be consumed by the proposer.
+///
+/// Requests that were received but did not fit into a batch formed by [BatchOutput::next_batch]
+/// are kept (and shared between clones) so they are delivered first in the next call.
+/// Async consumers can use [BatchOutput::recv_async] or [BatchOutput::into_stream].
 #[derive(Clone)]
-pub struct BatchOutput<O>(ChannelSyncRx<PreProcessorOutput<O>>);
+pub struct BatchOutput<O>(ChannelSyncRx<PreProcessorOutput<O>>, Arc<Mutex<VecDeque<PreProcessorOutputMessage<O>>>>, Arc<BatchNotifier>);
+
+/// The limits of a batch formed by [BatchOutput::next_batch]
+#[derive(Clone, Debug)]
+pub struct BatchLimits {
+    /// The maximum amount of requests in a batch
+    pub max_count: usize,
+    /// The maximum amount of payload bytes in a batch.
+    /// A single request that is larger than this will still be delivered, in a batch of its own
+    pub max_bytes: usize,
+    /// How long we are willing to wait for more requests after the first request
+    /// of the batch has arrived
+    pub max_linger: Duration,
+}
 
 /// Message to the request pre processor
 pub enum PreProcessorMessage<O> {
@@ -62,9 +110,16 @@ pub enum PreProcessorMessage<O> {
     /// A batch of requests that has been decided by the system
     DecidedBatch(Vec<ClientRqInfo>),
     /// Collect all pending messages from all workers.
-    CollectAllPendingMessages(OneShotTx<Vec<StoredRequestMessage<O>>>),
+    CollectAllPendingMessages(PreProcessorResponder<Vec<StoredRequestMessage<O>>>),
     /// Clone a vec of requests to be used
-    CloneRequests(Vec<ClientRqInfo>, OneShotTx<Vec<StoredRequestMessage<O>>>),
+    CloneRequests(Vec<ClientRqInfo>, PreProcessorResponder<Vec<StoredRequestMessage<O>>>),
+    /// Snapshot the latest decided operation of every session, to be stored with a checkpoint
+    SnapshotLatestOps(OneShotTx<LatestOpsSnapshot>),
+    /// Install the latest decided operations from a checkpoint
+    InstallLatestOps(LatestOpsSnapshot),
+    /// Change the amount of workers, migrating the sessions that change workers.
+    /// The responder is notified when the migration is done
+    ResizeWorkers(usize, OneShotTx<()>),
 }
 
 /// Output messages of the preprocessor
@@ -75,9 +130,12 @@ pub enum PreProcessorOutputMessage<O> {
     DeDupedUnorderedRequests(Vec<StoredRequestMessage<O>>),
 }
 
-/// Request pre processor handle
+/// Request pre processor handle.
+///
+/// Async requests go through their own unbounded queue, so sending them never waits for space in the
+/// orchestrator's queue (which would park the executor thread)
 #[derive(Clone)]
-pub struct RequestPreProcessor<O>(ChannelSyncTx<PreProcessorMessage<O>>);
+pub struct RequestPreProcessor<O>(ChannelSyncTx<PreProcessorMessage<O>>, mpsc::UnboundedSender<PreProcessorMessage<O>>);
 
 impl<O> RequestPreProcessor<O> {
     pub fn clone_pending_rqs(&self, client_rqs: Vec<ClientRqInfo>) -> Vec<StoredRequestMessage<O>> {
@@ -85,7 +143,7 @@ impl<O> RequestPreProcessor<O> {
 
         let (tx, rx) = channel::new_oneshot_channel();
 
-        self.0.send(PreProcessorMessage::CloneRequests(client_rqs, tx)).unwrap();
+        self.0.send(PreProcessorMessage::CloneRequests(client_rqs, PreProcessorResponder::Sync(tx))).unwrap();
 
         let result = rx.recv().unwrap();
 
@@ -99,7 +157,7 @@ impl<O> RequestPreProcessor<O> {
 
         let (tx, rx) = channel::new_oneshot_channel();
 
-        self.0.send(PreProcessorMessage::CollectAllPendingMessages(tx)).unwrap();
+        self.0.send(PreProcessorMessage::CollectAllPendingMessages(PreProcessorResponder::Sync(tx))).unwrap();
 
         let result = rx.recv().unwrap();
 
@@ -108,9 +166,81 @@ impl<O> RequestPreProcessor<O> {
         result
     }
 
+    /// The async counterpart of [RequestPreProcessor::clone_pending_rqs], which does not block the current thread
+    /// while the request is delivered or while the workers collect the requests.
+    /// Fails if the pre processor has shut down
+    pub async fn clone_pending_rqs_async(&self, client_rqs: Vec<ClientRqInfo>) -> Result<Vec<StoredRequestMessage<O>>> {
+        let start = Instant::now();
+
+        let (tx, rx) = oneshot::channel();
+
+        self.send_async(PreProcessorMessage::CloneRequests(client_rqs, PreProcessorResponder::Async(tx)))?;
+
+        let result = rx.await.map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "The request pre processor dropped the request"))?;
+
+        metric_duration(RQ_PP_CLONE_PENDING_TIME_ID, start.elapsed());
+
+        Ok(result)
+    }
+
+    /// The async counterpart of [RequestPreProcessor::collect_all_pending_rqs], which does not block the current thread
+    /// while the request is delivered or while the workers collect the requests.
+    /// Fails if the pre processor has shut down
+    pub async fn collect_all_pending_rqs_async(&self) -> Result<Vec<StoredRequestMessage<O>>> {
+        let start = Instant::now();
+
+        let (tx, rx) = oneshot::channel();
+
+        self.send_async(PreProcessorMessage::CollectAllPendingMessages(PreProcessorResponder::Async(tx)))?;
+
+        let result = rx.await.map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "The request pre processor dropped the request"))?;
+
+        metric_duration(RQ_PP_COLLECT_PENDING_TIME_ID, start.elapsed());
+
+        Ok(result)
+    }
+
+    fn send_async(&self, message: PreProcessorMessage<O>) -> Result<()> {
+        self.1.unbounded_send(message)
+            .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "The request pre processor has shut down"))
+    }
+
     pub fn process_timeouts(&self, timeouts: Vec<RqTimeout>, response: ChannelSyncTx<(Vec<RqTimeout>, Vec<RqTimeout>)>) {
         self.0.send(PreProcessorMessage::TimeoutsReceived(timeouts, response)).unwrap();
     }
+
+    /// Snapshot the latest decided operation of every client session.
+    /// This should be stored along with the checkpoints (see [crate::state_transfer::Checkpoint::new_with_sessions])
+    pub fn snapshot_latest_ops(&self) -> LatestOpsSnapshot {
+        let (tx, rx) = channel::new_oneshot_channel();
+
+        self.0.send(PreProcessorMessage::SnapshotLatestOps(tx)).unwrap();
+
+        rx.recv().unwrap()
+    }
+
+    /// Install the latest decided operations of the client sessions, when installing a checkpoint
+    /// (after a restart or a state transfer) so we do not propose requests that have already been ordered.
+    pub fn install_latest_ops(&self, snapshot: LatestOpsSnapshot) {
+        self.0.send(PreProcessorMessage::InstallLatestOps(snapshot)).unwrap();
+    }
+
+    /// Grow or shrink the worker pool to the given amount of workers.
+    /// The pending requests and latest operations of the sessions which are assigned to a
+    /// different worker by the [WorkPartitioner] are moved to their new worker.
+    /// Blocks until the migration is done
+    pub fn resize_workers(&self, worker_count: usize) -> Result<()> {
+        if worker_count == 0 {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "The request pre processor needs at least one worker"));
+        }
+
+        let (tx, rx) = channel::new_oneshot_channel();
+
+        self.0.send(PreProcessorMessage::ResizeWorkers(worker_count, tx))
+            .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "The request pre processor has shut down"))?;
+
+        rx.recv().map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "The request pre processor has shut down"))
+    }
 }
 
 impl<O> Deref for RequestPreProcessor<O> {
@@ -128,12 +258,32 @@ struct RequestPreProcessingOrchestrator<WD, D, NT> where D: ApplicationData, WD:
     thread_count: usize,
     /// Work message transmission for each worker
     work_comms: Vec<RequestPreProcessingWorkerHandle<D::Request>>,
+    /// The output of the workers, kept so we can spawn new workers
+    batch_tx: BatchProducer<D::Request>,
+    /// The rejected requests channel of the workers, kept so we can spawn new workers
+    rejected_tx: ChannelSyncTx<RejectedRequests>,
+    /// The application's validation of the requests, shared by all workers
+    validation: Option<Arc<dyn RequestValidationHook<D::Request>>>,
+    /// Where the unordered requests are executed, shared by all workers
+    unordered_sink: Option<Arc<dyn UnorderedRequestSink<D::Request>>>,
+    /// How many outstanding operations each client session can have
+    session_window: usize,
     /// The RX end for a work channel for the request pre processor
     work_receiver: ChannelSyncRx<PreProcessorMessage<D::Request>>,
+    /// The RX end for the requests made by async callers
+    async_work_receiver: mpsc::UnboundedReceiver<PreProcessorMessage<D::Request>>,
+    /// The requests that have been rejected by the workers and must be replied to
+    rejected_receiver: ChannelSyncRx<RejectedRequests>,
+    /// The global budget of pending requests
+    budget: Arc<PendingRqBudget>,
+    /// Whether we are currently not receiving requests from the clients, due to the budget being exhausted
+    intake_blocked: bool,
+    /// The cache of replies sent to the clients, used to answer retransmitted requests
+    reply_cache: Option<Arc<ReplyCache<D>>>,
     /// The network node so we can poll messages received from the clients
     network_node: Arc<NT>,
     /// How we are going to divide the work between workers
-    work_divider: PhantomData<WD>,
+    work_divider: Arc<WD>,
 }
 
 impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: ApplicationData + 'static, WD: Send {
@@ -142,10 +292,82 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
               OP: OrderingProtocolMessage<D> + 'static,
               LP: LogTransferMessage<D, OP> + 'static,
               ST: StateTransferMessage + 'static,
-              WD: WorkPartitioner<D::Request> {
+              WD: WorkPartitioner<D::Request> + 'static {
         loop {
-            self.process_client_rqs::<OP, ST, LP>();
+            if self.budget.should_block_intake() {
+                // Only count the moment we start blocking, not every time we check the budget
+                if !self.intake_blocked {
+                    metric_increment(RQ_PP_BLOCKED_INTAKE_ID, None);
+
+                    self.intake_blocked = true;
+                }
+
+                std::thread::sleep(BLOCKED_INTAKE_DELAY);
+            } else {
+                self.intake_blocked = false;
+
+                self.process_client_rqs::<OP, ST, LP>();
+            }
+
             self.process_work_messages();
+            self.process_rejected_rqs::<OP, ST, LP>();
+        }
+    }
+
+    /// Reply to all the requests that have been rejected by the workers
+    fn process_rejected_rqs<OP, ST, LP>(&mut self)
+        where NT: ProtocolNetworkNode<Service<D, OP, ST, LP>>,
+              OP: OrderingProtocolMessage<D> + 'static,
+              ST: StateTransferMessage + 'static,
+              LP: LogTransferMessage<D, OP> + 'static {
+        while let Ok((rejected, reason)) = self.rejected_receiver.try_recv() {
+            for rq_info in rejected {
+                self.reply_rejected::<OP, ST, LP>(rq_info.sender, rq_info.session, rq_info.seq_no, reason);
+            }
+        }
+    }
+
+    /// Check if the request has already been executed, in which case we resend the cached reply (if we still have it).
+    /// Returns true if the request was already executed and should not be processed further
+    fn answer_from_reply_cache<OP, ST, LP>(&self, header: &Header, request: &RequestMessage<D::Request>) -> bool
+        where NT: ProtocolNetworkNode<Service<D, OP, ST, LP>>,
+              OP: OrderingProtocolMessage<D> + 'static,
+              ST: StateTransferMessage + 'static,
+              LP: LogTransferMessage<D, OP> + 'static {
+        let reply_cache = match &self.reply_cache {
+            Some(reply_cache) => reply_cache,
+            None => return false
+        };
+
+        match reply_cache.get_reply(header.from(), request.session_id(), request.sequence_number()) {
+            CachedReplyLookup::NotExecuted => false,
+            CachedReplyLookup::Outdated => true,
+            CachedReplyLookup::Cached(reply) => {
+                metric_increment(RQ_PP_CACHED_REPLIES_ID, None);
+
+                if let Err(err) = self.network_node.send(SystemMessage::OrderedReply(reply), header.from(), true) {
+                    warn!("Failed to resend cached reply to client {:?}: {:?}", header.from(), err);
+                }
+
+                true
+            }
+        }
+    }
+
+    fn reply_rejected<OP, ST, LP>(&self, client: NodeId, session: SeqNo, seq_no: SeqNo, reason: RejectionReason)
+        where NT: ProtocolNetworkNode<Service<D, OP, ST, LP>>,
+              OP: OrderingProtocolMessage<D> + 'static,
+              ST: StateTransferMessage + 'static,
+              LP: LogTransferMessage<D, OP> + 'static {
+        // Shed requests are already accounted for by the workers, in RQ_PP_SHED_RQS
+        if reason == RejectionReason::Overloaded {
+            metric_increment(RQ_PP_REJECTED_RQS_ID, None);
+        }
+
+        let message = SystemMessage::RequestRejected(RequestRejectedMessage::new(session, seq_no, reason));
+
+        if let Err(err) = self.network_node.send(message, client, true) {
+            warn!("Failed to notify client {:?} of rejected request {:?}: {:?}", client, seq_no, err);
         }
     }
 
@@ -172,19 +394,33 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
 
             let mut unordered_worker_message = init_worker_vecs(self.thread_count, messages.len());
 
+            let reject_ordered = self.budget.should_reject_new();
+
             for message in messages {
                 let (header, message) = message.into_inner();
 
                 match message {
                     SystemMessage::OrderedRequest(req) => {
-                        let worker = WD::get_worker_for(&header, &req, self.thread_count);
+                        // Retransmissions of requests that were already executed can always be answered,
+                        // even when we are overloaded
+                        if self.answer_from_reply_cache::<OP, ST, LP>(&header, &req) {
+                            continue;
+                        }
+
+                        if reject_ordered {
+                            self.reply_rejected::<OP, ST, LP>(header.from(), req.session_id(), req.sequence_number(), RejectionReason::Overloaded);
+
+                            continue;
+                        }
+
+                        let worker = self.work_divider.get_worker_for(&header, &req, self.thread_count);
 
                         let stored_message = StoredMessage::new(header, req);
 
                         worker_message[worker % self.thread_count].push(stored_message);
                     }
                     SystemMessage::UnorderedRequest(req) => {
-                        let worker = WD::get_worker_for(&header, &req, self.thread_count);
+                        let worker = self.work_divider.get_worker_for(&header, &req, self.thread_count);
 
                         let stored_message = StoredMessage::new(header, req);
 
@@ -212,27 +448,46 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         metric_increment(RQ_PP_CLIENT_COUNT_ID, Some(msg_count as u64));
     }
 
-    fn process_work_messages(&mut self) where WD: WorkPartitioner<D::Request> {
+    fn process_work_messages(&mut self) where WD: WorkPartitioner<D::Request> + 'static {
         while let Ok(work_recved) = self.work_receiver.try_recv() {
-            match work_recved {
-                PreProcessorMessage::ForwardedRequests(fwd_reqs) => {
-                    self.process_forwarded_rqs(fwd_reqs);
-                }
-                PreProcessorMessage::DecidedBatch(decided) => {
-                    self.process_decided_batch(decided);
-                }
-                PreProcessorMessage::TimeoutsReceived(timeouts, responder) => {
-                    self.process_timeouts(timeouts, responder);
-                }
-                PreProcessorMessage::CollectAllPendingMessages(tx) => {
-                    self.collect_pending_rqs(tx);
-                }
-                PreProcessorMessage::StoppedRequests(stopped) => {
-                    self.process_stopped_rqs(stopped);
-                }
-                PreProcessorMessage::CloneRequests(client_rqs, tx) => {
-                    self.clone_pending_rqs(client_rqs, tx);
-                }
+            self.process_work_message(work_recved);
+        }
+
+        while let Ok(Some(work_recved)) = self.async_work_receiver.try_next() {
+            self.process_work_message(work_recved);
+        }
+    }
+
+    fn process_work_message(&mut self, work_recved: PreProcessorMessage<D::Request>) where WD: WorkPartitioner<D::Request> + 'static {
+        match work_recved {
+            PreProcessorMessage::ForwardedRequests(fwd_reqs) => {
+                self.process_forwarded_rqs(fwd_reqs);
+            }
+            PreProcessorMessage::DecidedBatch(decided) => {
+                self.process_decided_batch(decided);
+            }
+            PreProcessorMessage::TimeoutsReceived(timeouts, responder) => {
+                self.process_timeouts(timeouts, responder);
+            }
+            PreProcessorMessage::CollectAllPendingMessages(tx) => {
+                self.collect_pending_rqs(tx);
+            }
+            PreProcessorMessage::StoppedRequests(stopped) => {
+                self.process_stopped_rqs(stopped);
+            }
+            PreProcessorMessage::CloneRequests(client_rqs, tx) => {
+                self.clone_pending_rqs(client_rqs, tx);
+            }
+            PreProcessorMessage::SnapshotLatestOps(tx) => {
+                self.snapshot_latest_ops(tx);
+            }
+            PreProcessorMessage::InstallLatestOps(snapshot) => {
+                self.install_latest_ops(snapshot);
+            }
+            PreProcessorMessage::ResizeWorkers(worker_count, responder) => {
+                self.resize_workers(worker_count);
+
+                responder.send(()).unwrap();
             }
         }
     }
@@ -248,7 +503,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         let mut worker_message = init_worker_vecs::<StoredRequestMessage<D::Request>>(self.thread_count, fwd_reqs.len());
 
         for stored_msgs in fwd_reqs {
-            let worker = WD::get_worker_for(stored_msgs.header(), stored_msgs.message(), self.thread_count);
+            let worker = self.work_divider.get_worker_for(stored_msgs.header(), stored_msgs.message(), self.thread_count);
 
             worker_message[worker % self.thread_count].push(stored_msgs);
         }
@@ -268,7 +523,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         let mut worker_messages = init_worker_vecs::<ClientRqInfo>(self.thread_count, decided.len());
 
         for request in decided {
-            let worker = WD::get_worker_for_processed(&request, self.thread_count);
+            let worker = self.work_divider.get_worker_for_processed(&request, self.thread_count);
 
             worker_messages[worker % self.thread_count].push(request);
         }
@@ -289,7 +544,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
 
         for timeout in timeouts {
             if let TimeoutKind::ClientRequestTimeout(rq) = timeout.timeout_kind() {
-                let worker = WD::get_worker_for_processed(&rq, self.thread_count);
+                let worker = self.work_divider.get_worker_for_processed(&rq, self.thread_count);
 
                 worker_messages[worker % self.thread_count].push(timeout);
             }
@@ -303,7 +558,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         metric_duration(RQ_PP_TIMEOUT_RQS_ID, start.elapsed());
     }
 
-    fn collect_pending_rqs(&self, tx: OneShotTx<Vec<StoredRequestMessage<D::Request>>>) {
+    fn collect_pending_rqs(&self, tx: PreProcessorResponder<Vec<StoredRequestMessage<D::Request>>>) {
         let start = Instant::now();
 
         let mut worker_responses = init_for_workers(self.thread_count, || channel::new_oneshot_channel());
@@ -323,7 +578,10 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
             final_requests.append(&mut requests);
         }
 
-        tx.send(final_requests).unwrap();
+        // The caller may have given up on the response (an async caller dropping its future, for example)
+        if let Err(err) = tx.send(final_requests) {
+            warn!("Failed to deliver the pending requests: {:?}", err);
+        }
 
         metric_duration(RQ_PP_COLLECT_PENDING_ID, start.elapsed());
     }
@@ -335,7 +593,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         let mut worker_message = init_worker_vecs::<StoredRequestMessage<D::Request>>(self.thread_count, rqs.len());
 
         for stored_msgs in rqs {
-            let worker = WD::get_worker_for(stored_msgs.header(), stored_msgs.message(), self.thread_count);
+            let worker = self.work_divider.get_worker_for(stored_msgs.header(), stored_msgs.message(), self.thread_count);
 
             worker_message[worker % self.thread_count].push(stored_msgs);
         }
@@ -348,7 +606,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         metric_duration(RQ_PP_WORKER_STOPPED_TIME_ID, start.elapsed());
     }
 
-    fn clone_pending_rqs(&self, digests: Vec<ClientRqInfo>, responder: OneShotTx<Vec<StoredRequestMessage<D::Request>>>)
+    fn clone_pending_rqs(&self, digests: Vec<ClientRqInfo>, responder: PreProcessorResponder<Vec<StoredRequestMessage<D::Request>>>)
         where WD: WorkPartitioner<D::Request> {
         let start = Instant::now();
 
@@ -358,7 +616,7 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
         let worker_responses = init_for_workers(self.thread_count, || channel::new_oneshot_channel());
 
         for rq in digests {
-            let worker = WD::get_worker_for_processed(&rq, self.thread_count);
+            let worker = self.work_divider.get_worker_for_processed(&rq, self.thread_count);
 
             worker_messages[worker % self.thread_count].push(rq);
         }
@@ -376,14 +634,131 @@ impl<WD, D, NT> RequestPreProcessingOrchestrator<WD, D, NT> where D: Application
             pending_rqs.extend(rqs)
         }
 
-        responder.send(pending_rqs).unwrap();
+        if let Err(err) = responder.send(pending_rqs) {
+            warn!("Failed to deliver the cloned pending requests: {:?}", err);
+        }
 
         metric_duration(RQ_PP_CLONE_RQS_ID, start.elapsed());
     }
+
+    fn snapshot_latest_ops(&self, responder: OneShotTx<LatestOpsSnapshot>) {
+        let rxs: Vec<OneShotRx<Vec<SessionLatestOp>>> = self.work_comms.iter().map(|worker| {
+            let (tx, rx) = channel::new_oneshot_channel();
+
+            worker.send(PreProcessorWorkMessage::SnapshotLatestOps(tx));
+
+            rx
+        }).collect();
+
+        let mut ops = Vec::new();
+
+        for rx in rxs {
+            ops.append(&mut rx.recv().unwrap());
+        }
+
+        responder.send(LatestOpsSnapshot::new(ops)).unwrap();
+    }
+
+    fn install_latest_ops(&self, snapshot: LatestOpsSnapshot) where WD: WorkPartitioner<D::Request> {
+        let mut worker_messages = init_worker_vecs::<SessionLatestOp>(self.thread_count, snapshot.len());
+
+        for op in snapshot.into_inner() {
+            let worker = self.work_divider.get_worker_for_raw(op.client(), op.session(), self.thread_count);
+
+            worker_messages[worker % self.thread_count].push(op);
+        }
+
+        for (worker, messages)
+        in iter::zip(&self.work_comms, worker_messages) {
+            worker.send(PreProcessorWorkMessage::InstallLatestOps(messages));
+        }
+    }
+
+    /// Resize the worker pool.
+    /// Since every worker processes its messages in order and the orchestrator only routes
+    /// new work with the new worker count after the migration, no session is ever handled
+    /// by two workers at the same time.
+    fn resize_workers(&mut self, worker_count: usize) where WD: WorkPartitioner<D::Request> + 'static {
+        let old_count = self.thread_count;
+
+        if worker_count == old_count || worker_count == 0 {
+            return;
+        }
+
+        info!("Resizing the request pre processing workers from {} to {}", old_count, worker_count);
+
+        for worker_id in old_count..worker_count {
+            let worker_handle = worker::spawn_worker(worker_id, self.batch_tx.clone(), self.budget.clone(), self.rejected_tx.clone(),
+                                                    self.validation.clone(), self.unordered_sink.clone(), self.work_divider.clone(),
+                                                    self.session_window);
+
+            self.work_comms.push(worker_handle);
+        }
+
+        // Extract the sessions that will no longer belong to their current worker
+        let rxs: Vec<OneShotRx<SessionMigration<D::Request>>> = self.work_comms.iter().take(old_count).enumerate().map(|(worker_id, worker)| {
+            let (tx, rx) = channel::new_oneshot_channel();
+
+            let work_divider = self.work_divider.clone();
+
+            let filter: SessionFilter = Box::new(move |client, session| {
+                work_divider.get_worker_for_raw(client, session, worker_count) % worker_count != worker_id
+            });
+
+            worker.send(PreProcessorWorkMessage::ExtractSessions(filter, tx));
+
+            rx
+        }).collect();
+
+        let mut migrations = init_for_workers(worker_count, SessionMigration::new);
+
+        for rx in rxs {
+            let migration = rx.recv().unwrap();
+
+            for (key, op) in migration.latest_ops {
+                let (client, session) = split_operation_key(key);
+
+                let worker = self.work_divider.get_worker_for_raw(client, session, worker_count);
+
+                migrations[worker % worker_count].latest_ops.push((key, op));
+            }
+
+            for op in migration.latest_decided {
+                let worker = self.work_divider.get_worker_for_raw(op.client(), op.session(), worker_count);
+
+                migrations[worker % worker_count].latest_decided.push(op);
+            }
+
+            for request in migration.pending_requests {
+                let worker = self.work_divider.get_worker_for(request.header(), request.message(), worker_count);
+
+                migrations[worker % worker_count].pending_requests.push(request);
+            }
+        }
+
+        for (worker, migration) in iter::zip(&self.work_comms, migrations) {
+            worker.send(PreProcessorWorkMessage::InstallSessions(migration));
+        }
+
+        // The removed workers have already handed over all of their sessions
+        if worker_count < old_count {
+            for worker in self.work_comms.drain(worker_count..) {
+                worker.send(PreProcessorWorkMessage::Shutdown);
+            }
+        }
+
+        self.thread_count = worker_count;
+    }
 }
 
 
-pub fn initialize_request_pre_processor<WD, D, OP, ST, LP, NT>(concurrency: usize, node: Arc<NT>)
+/// Initialize the request pre processor, with `concurrency` workers, dividing the sessions between them with `work_divider`.
+/// `session_window` is the amount of outstanding operations a client session can have, see [session_window::SessionWindow]
+pub fn initialize_request_pre_processor<WD, D, OP, ST, LP, NT>(work_divider: WD, concurrency: usize, session_window: usize, budget: PendingRqBudgetConfig,
+                                                               reply_cache: Option<Arc<ReplyCache<D>>>,
+                                                               validation: Option<Arc<dyn RequestValidationHook<D::Request>>>,
+                                                               unordered_sink: Option<Arc<dyn UnorderedRequestSink<D::Request>>>,
+                                                               node: Arc<NT>)
                                                                -> (RequestPreProcessor<D::Request>, BatchOutput<D::Request>)
     where D: ApplicationData + 'static,
           OP: OrderingProtocolMessage<D> + 'static,
@@ -393,12 +768,25 @@ pub fn initialize_request_pre_processor<WD, D, OP, ST, LP, NT>(concurrency: usiz
           WD: WorkPartitioner<D::Request> + 'static {
     let (batch_tx, receiver) = new_bounded_sync(PROPOSER_QUEUE_SIZE);
 
+    let batch_notifier = Arc::new(BatchNotifier::default());
+
+    let batch_tx = BatchProducer::new(batch_tx, batch_notifier.clone());
+
     let (work_sender, work_rcvr) = new_bounded_sync(PROPOSER_QUEUE_SIZE);
 
+    let (async_work_sender, async_work_rcvr) = mpsc::unbounded();
+
+    let (rejected_tx, rejected_rx) = new_bounded_sync(REJECTED_QUEUE_SIZE);
+
+    let budget = Arc::new(PendingRqBudget::new(budget));
+
+    let work_divider = Arc::new(work_divider);
+
     let mut work_comms = Vec::with_capacity(concurrency);
 
     for worker_id in 0..concurrency {
-        let worker_handle = worker::spawn_worker(worker_id, batch_tx.clone());
+        let worker_handle = worker::spawn_worker(worker_id, batch_tx.clone(), budget.clone(), rejected_tx.clone(),
+                                                validation.clone(), unordered_sink.clone(), work_divider.clone(), session_window);
 
         work_comms.push(worker_handle);
     }
@@ -406,14 +794,24 @@ pub fn initialize_request_pre_processor<WD, D, OP, ST, LP, NT>(concurrency: usiz
     let orchestrator = RequestPreProcessingOrchestrator::<WD, D, NT> {
         thread_count: concurrency,
         work_comms,
+        batch_tx,
+        rejected_tx,
+        validation,
+        unordered_sink,
+        session_window,
         work_receiver: work_rcvr,
+        async_work_receiver: async_work_rcvr,
+        rejected_receiver: rejected_rx,
+        budget,
+        intake_blocked: false,
+        reply_cache,
         network_node: node,
-        work_divider: Default::default(),
+        work_divider,
     };
 
     launch_orchestrator_thread(orchestrator);
 
-    (RequestPreProcessor(work_sender), BatchOutput(receiver))
+    (RequestPreProcessor(work_sender, async_work_sender), BatchOutput(receiver, Default::default(), batch_notifier))
 }
 
 fn init_for_workers<V, F>(thread_count: usize, init: F) -> Vec<V> where F: FnMut() -> V {
@@ -447,6 +845,28 @@ fn launch_orchestrator_thread<WD, D, OP, ST, LP, NT>(orchestrator: RequestPrePro
         }).expect("Failed to launch orchestrator thread.");
 }
 
+impl<O> PreProcessorOutputMessage<O> {
+    pub fn is_ordered(&self) -> bool {
+        matches!(self, PreProcessorOutputMessage::DeDupedOrderedRequests(_))
+    }
+
+    /// Unwraps the requests contained in this message
+    pub fn into_requests(self) -> Vec<StoredRequestMessage<O>> {
+        match self {
+            PreProcessorOutputMessage::DeDupedOrderedRequests(rqs) => rqs,
+            PreProcessorOutputMessage::DeDupedUnorderedRequests(rqs) => rqs,
+        }
+    }
+
+    /// Create a message of the same kind as this one, with the given requests
+    fn with_requests(&self, requests: Vec<StoredRequestMessage<O>>) -> Self {
+        match self {
+            PreProcessorOutputMessage::DeDupedOrderedRequests(_) => PreProcessorOutputMessage::DeDupedOrderedRequests(requests),
+            PreProcessorOutputMessage::DeDupedUnorderedRequests(_) => PreProcessorOutputMessage::DeDupedUnorderedRequests(requests),
+        }
+    }
+}
+
 impl<O> Deref for PreProcessorOutputMessage<O> {
     type Target = Vec<StoredRequestMessage<O>>;
 
@@ -477,8 +897,116 @@ pub fn operation_key_raw(from: NodeId, session: SeqNo) -> u64 {
     client_id | (session_id << 32)
 }
 
+/// The inverse of [operation_key_raw]
+#[inline]
+pub fn split_operation_key(key: u64) -> (NodeId, SeqNo) {
+    let client_id = (key & 0xFFFF_FFFF) as u32;
+    let session_id = (key >> 32) as u32;
+
+    (NodeId::from(client_id), SeqNo::from(session_id))
+}
+
 impl<O> BatchOutput<O> {
-    pub fn recv(&self) -> Result<PreProcessorOutputMessage<O>, RecvError> {
+    fn take_leftover(&self) -> Option<PreProcessorOutputMessage<O>> {
+        self.1.lock().unwrap().pop_front()
+    }
+
+    fn return_leftover(&self, message: PreProcessorOutputMessage<O>) {
+        self.1.lock().unwrap().push_front(message)
+    }
+
+    /// Form a batch of requests, merging the outputs of all workers.
+    ///
+    /// We wait until the `deadline` for the first requests to arrive. After that, we keep collecting
+    /// requests until the batch reaches the given limits, the linger time runs out or the deadline is reached.
+    /// Ordered and unordered requests are never mixed in the same batch.
+    /// Returns [TryRecvError::Timeout] if no requests arrived until the deadline.
+    pub fn next_batch(&self, limits: &BatchLimits, deadline: Instant) -> std::result::Result<PreProcessorOutputMessage<O>, TryRecvError> {
+        let start = Instant::now();
+
+        let mut batch: Option<PreProcessorOutputMessage<O>> = None;
+        let mut batch_bytes = 0;
+        let mut batch_deadline = deadline;
+
+        'formation: loop {
+            let message = match self.take_leftover() {
+                Some(message) => message,
+                None => {
+                    let now = Instant::now();
+
+                    if now >= batch_deadline {
+                        break;
+                    }
+
+                    match self.recv_timeout(batch_deadline - now) {
+                        Ok(message) => message,
+                        Err(err) if batch.is_none() && !matches!(err, TryRecvError::Timeout) => {
+                            return Err(err);
+                        }
+                        Err(_) => break
+                    }
+                }
+            };
+
+            if batch.is_none() {
+                batch_deadline = deadline.min(Instant::now() + limits.max_linger);
+
+                batch = Some(message.with_requests(Vec::with_capacity(limits.max_count.min(message.len()))));
+            }
+
+            let current = batch.as_mut().unwrap();
+
+            if current.is_ordered() != message.is_ordered() {
+                self.return_leftover(message);
+
+                break;
+            }
+
+            let current = match current {
+                PreProcessorOutputMessage::DeDupedOrderedRequests(rqs) => rqs,
+                PreProcessorOutputMessage::DeDupedUnorderedRequests(rqs) => rqs,
+            };
+
+            let kind = message.with_requests(Vec::new());
+
+            let mut requests = message.into_requests().into_iter();
+
+            while let Some(request) = requests.next() {
+                let rq_bytes = request.header().payload_length();
+
+                if !current.is_empty() && (current.len() >= limits.max_count || batch_bytes + rq_bytes > limits.max_bytes) {
+                    let mut remaining = vec![request];
+
+                    remaining.extend(requests);
+
+                    self.return_leftover(kind.with_requests(remaining));
+
+                    break 'formation;
+                }
+
+                batch_bytes += rq_bytes;
+
+                current.push(request);
+            }
+
+            if current.len() >= limits.max_count || batch_bytes >= limits.max_bytes {
+                break;
+            }
+        }
+
+        let batch = batch.ok_or(TryRecvError::Timeout)?;
+
+        metric_duration(RQ_PP_BATCH_FORMATION_TIME_ID, start.elapsed());
+        metric_store_count(RQ_PP_BATCH_SIZE_ID, batch.len());
+
+        Ok(batch)
+    }
+
+    pub fn recv(&self) -> std::result::Result<PreProcessorOutputMessage<O>, RecvError> {
+        if let Some(message) = self.take_leftover() {
+            return Ok(message);
+        }
+
         let (message, instant) = self.0.recv().unwrap();
 
         metric_duration(RQ_PP_WORKER_PROPOSER_PASSING_TIME_ID, instant.elapsed());
@@ -486,7 +1014,11 @@ impl<O> BatchOutput<O> {
         Ok(message)
     }
 
-    pub fn try_recv(&self) -> Result<PreProcessorOutputMessage<O>, TryRecvError> {
+    pub fn try_recv(&self) -> std::result::Result<PreProcessorOutputMessage<O>, TryRecvError> {
+        if let Some(message) = self.take_leftover() {
+            return Ok(message);
+        }
+
         let (message, instant) = self.0.try_recv()?;
 
         metric_duration(RQ_PP_WORKER_PROPOSER_PASSING_TIME_ID, instant.elapsed());
@@ -494,7 +1026,11 @@ impl<O> BatchOutput<O> {
         Ok(message)
     }
 
-    pub fn recv_timeout(&self, timeout: Duration) -> Result<PreProcessorOutputMessage<O>, TryRecvError> {
+    pub fn recv_timeout(&self, timeout: Duration) -> std::result::Result<PreProcessorOutputMessage<O>, TryRecvError> {
+        if let Some(message) = self.take_leftover() {
+            return Ok(message);
+        }
+
         let (message, instant) = self.0.recv_timeout(timeout)?;
 
         metric_duration(RQ_PP_WORKER_PROPOSER_PASSING_TIME_ID, instant.elapsed());
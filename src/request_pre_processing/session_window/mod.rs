@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use atlas_common::crypto::hash::Digest;
+use atlas_common::ordering::SeqNo;
+
+/// The operations of a client session that have been seen by the pre processor.
+///
+/// Clients can have up to `window` outstanding operations per session, which may arrive out of order.
+/// We accept any operation that is newer than `highest - window` and that we have not seen before.
+/// Operations that fall behind the window are forgotten, and their pending requests are discarded.
+/// With a window of 1, only operations newer than the latest one are accepted.
+pub struct SessionWindow {
+    /// The highest operation received or decided for this session
+    highest: SeqNo,
+    /// The operations within the window, along with the digest of their pending request
+    /// (None if the operation has already been decided)
+    ops: BTreeMap<SeqNo, Option<Digest>>,
+}
+
+impl SessionWindow {
+    pub fn new() -> Self {
+        // Operation zero is never accepted, so the operations of a session start at one
+        let mut ops = BTreeMap::new();
+
+        ops.insert(SeqNo::ZERO, None);
+
+        Self {
+            highest: SeqNo::ZERO,
+            ops,
+        }
+    }
+
+    pub fn highest(&self) -> SeqNo {
+        self.highest
+    }
+
+    /// Is the given operation behind the window
+    fn is_behind(&self, seq_no: SeqNo, window: usize) -> bool {
+        u32::from(seq_no) as u64 + window as u64 <= u32::from(self.highest) as u64
+    }
+
+    /// Would we accept an operation with the given sequence number
+    pub fn accepts(&self, seq_no: SeqNo, window: usize) -> bool {
+        !self.is_behind(seq_no, window) && !self.ops.contains_key(&seq_no)
+    }
+
+    /// Record that a request has been received.
+    /// Returns the digests of the pending requests which fell behind the window
+    pub fn receive(&mut self, seq_no: SeqNo, digest: Digest, window: usize) -> Vec<Digest> {
+        self.ops.insert(seq_no, Some(digest));
+
+        self.advance(seq_no, window)
+    }
+
+    /// Record that an operation has been decided.
+    /// Returns the digests of the pending requests which fell behind the window
+    pub fn decide(&mut self, seq_no: SeqNo, window: usize) -> Vec<Digest> {
+        if self.is_behind(seq_no, window) {
+            return Vec::new();
+        }
+
+        let mut discarded: Vec<Digest> = self.ops.insert(seq_no, None).flatten().into_iter().collect();
+
+        discarded.append(&mut self.advance(seq_no, window));
+
+        discarded
+    }
+
+    /// Mark every operation up to (and including) the given one as decided.
+    /// Returns the digests of the pending requests of those operations
+    pub fn decide_up_to(&mut self, seq_no: SeqNo, window: usize) -> Vec<Digest> {
+        let mut discarded: Vec<Digest> = self.ops.range_mut(..=seq_no)
+            .filter_map(|(_, digest)| digest.take())
+            .collect();
+
+        discarded.append(&mut self.decide(seq_no, window));
+
+        discarded
+    }
+
+    fn advance(&mut self, seq_no: SeqNo, window: usize) -> Vec<Digest> {
+        if seq_no > self.highest {
+            self.highest = seq_no;
+        }
+
+        let mut discarded = Vec::new();
+
+        while let Some((oldest, _)) = self.ops.first_key_value() {
+            if !self.is_behind(*oldest, window) {
+                break;
+            }
+
+            if let Some((_, Some(digest))) = self.ops.pop_first() {
+                discarded.push(digest);
+            }
+        }
+
+        discarded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seq(seq_no: u32) -> SeqNo {
+        SeqNo::from(seq_no)
+    }
+
+    fn digest(byte: u8) -> Digest {
+        Digest::from_bytes(&[byte; Digest::LENGTH]).unwrap()
+    }
+
+    #[test]
+    fn operation_zero_is_never_accepted() {
+        let window = SessionWindow::new();
+
+        assert!(!window.accepts(seq(0), 4));
+        assert!(window.accepts(seq(1), 4));
+    }
+
+    #[test]
+    fn accepts_out_of_order_operations_within_the_window() {
+        let mut window = SessionWindow::new();
+
+        assert!(window.receive(seq(3), digest(3), 4).is_empty());
+        assert_eq!(window.highest(), seq(3));
+
+        assert!(window.accepts(seq(1), 4));
+        assert!(window.accepts(seq(2), 4));
+        assert!(!window.accepts(seq(3), 4));
+
+        assert!(window.receive(seq(1), digest(1), 4).is_empty());
+        assert!(!window.accepts(seq(1), 4));
+        assert_eq!(window.highest(), seq(3));
+    }
+
+    #[test]
+    fn discards_pending_requests_behind_the_window() {
+        let mut window = SessionWindow::new();
+
+        assert!(window.receive(seq(1), digest(1), 2).is_empty());
+
+        assert_eq!(window.receive(seq(3), digest(3), 2), vec![digest(1)]);
+
+        assert!(!window.accepts(seq(1), 2));
+        assert!(window.accepts(seq(2), 2));
+    }
+
+    #[test]
+    fn window_of_one_only_accepts_newer_operations() {
+        let mut window = SessionWindow::new();
+
+        assert!(window.receive(seq(1), digest(1), 1).is_empty());
+
+        assert!(!window.accepts(seq(1), 1));
+        assert!(window.accepts(seq(2), 1));
+
+        assert_eq!(window.receive(seq(2), digest(2), 1), vec![digest(1)]);
+        assert!(!window.accepts(seq(1), 1));
+    }
+
+    #[test]
+    fn decide_returns_the_pending_request() {
+        let mut window = SessionWindow::new();
+
+        window.receive(seq(1), digest(1), 4);
+
+        assert_eq!(window.decide(seq(1), 4), vec![digest(1)]);
+        assert!(!window.accepts(seq(1), 4));
+
+        // A decided operation that was never received is also no longer accepted
+        assert!(window.decide(seq(2), 4).is_empty());
+        assert!(!window.accepts(seq(2), 4));
+        assert_eq!(window.highest(), seq(2));
+    }
+
+    #[test]
+    fn decide_behind_the_window_is_ignored() {
+        let mut window = SessionWindow::new();
+
+        window.receive(seq(5), digest(5), 2);
+
+        assert!(window.decide(seq(1), 2).is_empty());
+        assert_eq!(window.highest(), seq(5));
+    }
+
+    #[test]
+    fn decide_up_to_discards_every_earlier_pending_request() {
+        let mut window = SessionWindow::new();
+
+        window.receive(seq(1), digest(1), 4);
+        window.receive(seq(2), digest(2), 4);
+        window.receive(seq(4), digest(4), 4);
+
+        assert_eq!(window.decide_up_to(seq(2), 4), vec![digest(1), digest(2)]);
+
+        assert!(!window.accepts(seq(1), 4));
+        assert!(!window.accepts(seq(2), 4));
+        assert!(window.accepts(seq(3), 4));
+        assert!(!window.accepts(seq(4), 4));
+
+        assert_eq!(window.decide_up_to(seq(4), 4), vec![digest(4)]);
+    }
+}
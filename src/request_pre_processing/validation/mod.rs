@@ -0,0 +1,89 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use log::error;
+
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::Orderable;
+use atlas_communication::message::Header;
+use atlas_execution::serialize::ApplicationData;
+
+use crate::messages::{ReplyMessage, RequestMessage};
+use crate::smr::exec::{ReplyNode, ReplyType};
+
+/// The result of the validation of a request by the application
+pub enum ValidationResult<R> {
+    /// The request can be ordered and executed
+    Valid,
+    /// The request is invalid. The given reply is sent to the client
+    /// and the request is dropped before being ordered
+    Invalid(R),
+}
+
+/// An application provided validator, which allows malformed or unauthorized operations
+/// to be rejected before they go through the ordering protocol.
+///
+/// Every correct replica must reach the same verdict for the same request, so the validation
+/// must be deterministic and can't depend on the application state.
+pub trait RequestValidator<D>: Send + Sync where D: ApplicationData {
+    fn validate(&self, client: NodeId, request: &RequestMessage<D::Request>) -> ValidationResult<D::Reply>;
+}
+
+/// The verdict of a [RequestValidationHook] on a request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ValidationVerdict {
+    /// The request is kept
+    Accepted,
+    /// The request is dropped, and the hook has already answered the client
+    Answered,
+    /// The request is dropped, and the pre processor must let the client know
+    /// (with [RejectionReason::Invalid](crate::messages::RejectionReason::Invalid))
+    Rejected,
+}
+
+/// The validation hook invoked by the pre processing workers before a request becomes pending.
+/// The reply type is the one the request would have been answered with, had it been executed
+pub trait RequestValidationHook<O>: Send + Sync {
+    fn check(&self, reply_type: ReplyType, header: &Header, request: &RequestMessage<O>) -> ValidationVerdict;
+}
+
+/// A validation hook which uses a [RequestValidator] and replies to the
+/// rejected requests with the [ReplyNode]
+pub struct ReplyingValidationHook<D, V, RN> where D: ApplicationData {
+    validator: Arc<V>,
+    reply_node: Arc<RN>,
+    _phantom: PhantomData<fn() -> D>,
+}
+
+impl<D, V, RN> ReplyingValidationHook<D, V, RN> where D: ApplicationData {
+    pub fn new(validator: Arc<V>, reply_node: Arc<RN>) -> Self {
+        Self {
+            validator,
+            reply_node,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<D, V, RN> RequestValidationHook<D::Request> for ReplyingValidationHook<D, V, RN>
+    where D: ApplicationData + 'static,
+          V: RequestValidator<D>,
+          RN: ReplyNode<D> {
+    fn check(&self, reply_type: ReplyType, header: &Header, request: &RequestMessage<D::Request>) -> ValidationVerdict {
+        match self.validator.validate(header.from(), request) {
+            ValidationResult::Valid => ValidationVerdict::Accepted,
+            ValidationResult::Invalid(reply) => {
+                let reply = ReplyMessage::new(request.session_id(), request.sequence_number(), reply);
+
+                if let Err(err) = self.reply_node.send(reply_type, reply, header.from(), true) {
+                    error!("Failed to reply to invalid request from {:?}: {:?}", header.from(), err);
+
+                    // Fall back to the pre processor's rejection, so the client is not left waiting
+                    return ValidationVerdict::Rejected;
+                }
+
+                ValidationVerdict::Answered
+            }
+        }
+    }
+}
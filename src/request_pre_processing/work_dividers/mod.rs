@@ -1,19 +1,420 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::SeqNo;
 use atlas_communication::message::Header;
 use crate::messages::{ClientRqInfo, RequestMessage};
 use crate::request_pre_processing::{operation_key_raw, WorkPartitioner};
 
+/// The amount of buckets the sessions are grouped into by [WDLoadAware]
+const LOAD_AWARE_BUCKETS: usize = 4096;
+
+/// How long a session without pending requests can go idle before [WDLoadAware] forgets it
+const LOAD_AWARE_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Partitions the sessions by the modulo of their operation key.
+/// Very cheap, but skews badly when the client ids are clustered
 pub struct WDRoundRobin;
 
 impl<O> WorkPartitioner<O> for WDRoundRobin {
-    fn get_worker_for(rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize {
+    fn get_worker_for(&self, rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize {
         let op_key = operation_key_raw(rq_info.from(), message.session_id());
 
         (op_key % worker_count as u64) as usize
     }
 
-    fn get_worker_for_processed(rq_info: &ClientRqInfo, worker_count: usize) -> usize {
+    fn get_worker_for_processed(&self, rq_info: &ClientRqInfo, worker_count: usize) -> usize {
         let op_key = operation_key_raw(rq_info.sender, rq_info.session);
 
         (op_key % worker_count as u64) as usize
     }
-}
\ No newline at end of file
+
+    fn get_worker_for_raw(&self, client: NodeId, session: SeqNo, worker_count: usize) -> usize {
+        let op_key = operation_key_raw(client, session);
+
+        (op_key % worker_count as u64) as usize
+    }
+}
+
+/// Partitions the sessions by the modulo of a mixed hash of their operation key,
+/// so clustered client ids (and sessions) are spread evenly between the workers
+pub struct WDHashMixing;
+
+impl<O> WorkPartitioner<O> for WDHashMixing {
+    fn get_worker_for(&self, rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize {
+        <Self as WorkPartitioner<O>>::get_worker_for_raw(self, rq_info.from(), message.session_id(), worker_count)
+    }
+
+    fn get_worker_for_processed(&self, rq_info: &ClientRqInfo, worker_count: usize) -> usize {
+        <Self as WorkPartitioner<O>>::get_worker_for_raw(self, rq_info.sender, rq_info.session, worker_count)
+    }
+
+    fn get_worker_for_raw(&self, client: NodeId, session: SeqNo, worker_count: usize) -> usize {
+        (mix64(operation_key_raw(client, session)) % worker_count as u64) as usize
+    }
+}
+
+/// Partitions the sessions with a consistent hash (jump consistent hashing) of their operation key.
+/// When the worker count changes from n to m, only about |n - m| / max(n, m) of the sessions
+/// are moved to another worker, which keeps migrations small when the worker pool is resized
+pub struct WDConsistentHash;
+
+impl<O> WorkPartitioner<O> for WDConsistentHash {
+    fn get_worker_for(&self, rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize {
+        <Self as WorkPartitioner<O>>::get_worker_for_raw(self, rq_info.from(), message.session_id(), worker_count)
+    }
+
+    fn get_worker_for_processed(&self, rq_info: &ClientRqInfo, worker_count: usize) -> usize {
+        <Self as WorkPartitioner<O>>::get_worker_for_raw(self, rq_info.sender, rq_info.session, worker_count)
+    }
+
+    fn get_worker_for_raw(&self, client: NodeId, session: SeqNo, worker_count: usize) -> usize {
+        jump_consistent_hash(mix64(operation_key_raw(client, session)), worker_count)
+    }
+}
+
+/// Assigns the sessions to the workers with the least outstanding requests.
+///
+/// The sessions are grouped into a fixed amount of buckets (by a mixed hash of their operation key),
+/// and each bucket is placed on the least loaded worker when it first sees a request. Once placed,
+/// a bucket (and therefore every session in it) stays with the same worker, so the session affinity
+/// contract of [WorkPartitioner] is kept while the placement table stays bounded.
+///
+/// The load of a worker is the amount of requests pending in it (accepted by the worker and not yet decided,
+/// rejected or discarded). The pending requests are tracked per session, and sessions without pending requests
+/// which have been idle for longer than the idle timeout are forgotten.
+pub struct WDLoadAware {
+    table: Mutex<LoadAwareTable>,
+}
+
+/// The placement of the buckets and the load of the sessions
+struct LoadAwareTable {
+    /// The worker each bucket was placed on
+    placements: Vec<Option<usize>>,
+    /// The outstanding requests of the sessions of each bucket
+    bucket_load: Vec<usize>,
+    /// The sessions with recent activity
+    sessions: HashMap<u64, SessionLoad>,
+    idle_timeout: Duration,
+    last_eviction: Instant,
+}
+
+struct SessionLoad {
+    outstanding: usize,
+    last_active: Instant,
+}
+
+impl WDLoadAware {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            table: Mutex::new(LoadAwareTable::new(idle_timeout)),
+        }
+    }
+}
+
+impl Default for WDLoadAware {
+    fn default() -> Self {
+        Self::new(LOAD_AWARE_IDLE_TIMEOUT)
+    }
+}
+
+impl<O> WorkPartitioner<O> for WDLoadAware {
+    fn get_worker_for(&self, rq_info: &Header, message: &RequestMessage<O>, worker_count: usize) -> usize {
+        <Self as WorkPartitioner<O>>::get_worker_for_raw(self, rq_info.from(), message.session_id(), worker_count)
+    }
+
+    fn get_worker_for_processed(&self, rq_info: &ClientRqInfo, worker_count: usize) -> usize {
+        <Self as WorkPartitioner<O>>::get_worker_for_raw(self, rq_info.sender, rq_info.session, worker_count)
+    }
+
+    fn get_worker_for_raw(&self, client: NodeId, session: SeqNo, worker_count: usize) -> usize {
+        self.table.lock().unwrap().route(operation_key_raw(client, session), worker_count)
+    }
+
+    fn request_admitted(&self, client: NodeId, session: SeqNo) {
+        self.table.lock().unwrap().admitted(operation_key_raw(client, session))
+    }
+
+    fn request_completed(&self, client: NodeId, session: SeqNo) {
+        self.table.lock().unwrap().completed(operation_key_raw(client, session))
+    }
+}
+
+impl LoadAwareTable {
+    fn new(idle_timeout: Duration) -> Self {
+        Self {
+            placements: vec![None; LOAD_AWARE_BUCKETS],
+            bucket_load: vec![0; LOAD_AWARE_BUCKETS],
+            sessions: Default::default(),
+            idle_timeout,
+            last_eviction: Instant::now(),
+        }
+    }
+
+    fn route(&mut self, op_key: u64, worker_count: usize) -> usize {
+        let bucket = bucket_of(op_key);
+
+        match self.placements[bucket] {
+            // Buckets placed on workers which no longer exist are placed again
+            Some(worker) if worker < worker_count => worker,
+            _ => {
+                let worker = self.least_loaded(worker_count);
+
+                self.placements[bucket] = Some(worker);
+
+                worker
+            }
+        }
+    }
+
+    fn admitted(&mut self, op_key: u64) {
+        let now = Instant::now();
+
+        let session = self.sessions.entry(op_key).or_insert(SessionLoad {
+            outstanding: 0,
+            last_active: now,
+        });
+
+        session.outstanding += 1;
+        session.last_active = now;
+
+        self.bucket_load[bucket_of(op_key)] += 1;
+
+        if now.duration_since(self.last_eviction) >= self.idle_timeout {
+            self.evict_idle(now);
+        }
+    }
+
+    fn completed(&mut self, op_key: u64) {
+        if let Some(session) = self.sessions.get_mut(&op_key) {
+            if session.outstanding > 0 {
+                session.outstanding -= 1;
+
+                self.bucket_load[bucket_of(op_key)] -= 1;
+            }
+
+            session.last_active = Instant::now();
+        }
+    }
+
+    /// Forget the sessions without pending requests that have been idle for longer than the idle timeout
+    fn evict_idle(&mut self, now: Instant) {
+        let idle_timeout = self.idle_timeout;
+
+        self.sessions.retain(|_, session| {
+            session.outstanding > 0 || now.duration_since(session.last_active) < idle_timeout
+        });
+
+        self.last_eviction = now;
+    }
+
+    /// The worker with the least outstanding requests, breaking ties by the amount of buckets placed on each worker
+    fn least_loaded(&self, worker_count: usize) -> usize {
+        let mut load = vec![(0usize, 0usize); worker_count];
+
+        for (bucket, placement) in self.placements.iter().enumerate() {
+            if let Some(worker) = placement {
+                if *worker < worker_count {
+                    load[*worker].0 += self.bucket_load[bucket];
+                    load[*worker].1 += 1;
+                }
+            }
+        }
+
+        load.iter()
+            .enumerate()
+            .min_by_key(|(_, load)| **load)
+            .map(|(worker, _)| worker)
+            .unwrap_or(0)
+    }
+}
+
+#[inline]
+fn bucket_of(op_key: u64) -> usize {
+    (mix64(op_key) % LOAD_AWARE_BUCKETS as u64) as usize
+}
+
+/// The finalizer of the murmur3 hash, which gives a good avalanche of the input bits
+#[inline]
+fn mix64(mut key: u64) -> u64 {
+    key ^= key >> 33;
+    key = key.wrapping_mul(0xff51afd7ed558ccd);
+    key ^= key >> 33;
+    key = key.wrapping_mul(0xc4ceb9fe1a85ec53);
+    key ^= key >> 33;
+
+    key
+}
+
+/// Jump consistent hashing, as described by Lamping and Veach
+#[inline]
+fn jump_consistent_hash(mut key: u64, buckets: usize) -> usize {
+    let mut bucket: i64 = -1;
+    let mut jump: i64 = 0;
+
+    while jump < buckets as i64 {
+        bucket = jump;
+
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+
+        jump = ((bucket + 1) as f64 * ((1i64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+
+    bucket as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions() -> Vec<(NodeId, SeqNo)> {
+        (1000..1256u32)
+            .flat_map(|client| (0..4u32).map(move |session| (NodeId::from(client), SeqNo::from(session))))
+            .collect()
+    }
+
+    fn worker_for<WP>(partitioner: &WP, client: NodeId, session: SeqNo, worker_count: usize) -> usize where WP: WorkPartitioner<()> {
+        partitioner.get_worker_for_raw(client, session, worker_count)
+    }
+
+    #[test]
+    fn round_robin_uses_the_operation_key() {
+        for (client, session) in sessions() {
+            let expected = (operation_key_raw(client, session) % 8) as usize;
+
+            assert_eq!(worker_for(&WDRoundRobin, client, session, 8), expected);
+        }
+    }
+
+    #[test]
+    fn hash_mixing_spreads_clustered_sessions() {
+        let mut load = [0usize; 8];
+
+        for (client, session) in sessions() {
+            let worker = worker_for(&WDHashMixing, client, session, 8);
+
+            assert_eq!(worker, worker_for(&WDHashMixing, client, session, 8));
+
+            load[worker] += 1;
+        }
+
+        // 1024 sessions over 8 workers, 128 each on average
+        assert!(load.iter().all(|load| *load > 64 && *load < 192), "Skewed load {:?}", load);
+    }
+
+    #[test]
+    fn consistent_hash_only_moves_sessions_to_the_new_worker() {
+        let sessions = sessions();
+
+        let mut moved = 0;
+
+        for (client, session) in &sessions {
+            let before = worker_for(&WDConsistentHash, *client, *session, 8);
+            let after = worker_for(&WDConsistentHash, *client, *session, 9);
+
+            assert!(before < 8 && after < 9);
+
+            if before != after {
+                assert_eq!(after, 8, "A session moved between two of the previous workers");
+
+                moved += 1;
+            }
+        }
+
+        // About 1/9 of the sessions should move
+        assert!(moved > sessions.len() / 18 && moved < sessions.len() / 5, "Moved {} sessions", moved);
+    }
+
+    /// Two operation keys which fall into different buckets
+    fn keys_in_different_buckets() -> (u64, u64) {
+        let first = operation_key_raw(NodeId::from(1000u32), SeqNo::from(0u32));
+
+        let second = (1001..2000u32)
+            .map(|client| operation_key_raw(NodeId::from(client), SeqNo::from(0u32)))
+            .find(|key| bucket_of(*key) != bucket_of(first))
+            .unwrap();
+
+        (first, second)
+    }
+
+    #[test]
+    fn load_aware_places_new_buckets_on_the_least_loaded_worker() {
+        let mut table = LoadAwareTable::new(LOAD_AWARE_IDLE_TIMEOUT);
+
+        let (first, second) = keys_in_different_buckets();
+
+        let first_worker = table.route(first, 2);
+
+        table.admitted(first);
+        table.admitted(first);
+
+        let second_worker = table.route(second, 2);
+
+        assert_ne!(first_worker, second_worker);
+
+        // Placements are sticky, regardless of the load
+        table.admitted(second);
+        table.admitted(second);
+        table.admitted(second);
+
+        assert_eq!(table.route(first, 2), first_worker);
+        assert_eq!(table.route(second, 2), second_worker);
+    }
+
+    #[test]
+    fn load_aware_replaces_buckets_of_removed_workers() {
+        let mut table = LoadAwareTable::new(LOAD_AWARE_IDLE_TIMEOUT);
+
+        let (first, second) = keys_in_different_buckets();
+
+        table.route(first, 1);
+
+        assert_eq!(table.route(second, 2), 1);
+        assert_eq!(table.route(second, 1), 0);
+    }
+
+    #[test]
+    fn load_aware_only_releases_charged_requests() {
+        let mut table = LoadAwareTable::new(LOAD_AWARE_IDLE_TIMEOUT);
+
+        let (first, second) = keys_in_different_buckets();
+
+        // Completing a request that was never charged must not change the load
+        table.completed(first);
+
+        assert_eq!(table.bucket_load[bucket_of(first)], 0);
+
+        table.admitted(first);
+        table.admitted(first);
+        table.admitted(second);
+
+        table.completed(first);
+        table.completed(first);
+        table.completed(first);
+
+        assert_eq!(table.bucket_load[bucket_of(first)], 0);
+        assert_eq!(table.bucket_load[bucket_of(second)], 1);
+    }
+
+    #[test]
+    fn load_aware_only_evicts_idle_sessions_without_pending_requests() {
+        let mut table = LoadAwareTable::new(Duration::ZERO);
+
+        let (first, second) = keys_in_different_buckets();
+
+        table.admitted(first);
+        table.completed(first);
+
+        table.admitted(second);
+
+        assert!(!table.sessions.contains_key(&first));
+        assert_eq!(table.sessions.get(&second).map(|session| session.outstanding), Some(1));
+
+        table.admitted(first);
+
+        assert!(table.sessions.contains_key(&second));
+        assert_eq!(table.bucket_load[bucket_of(second)], 1);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -15,9 +16,16 @@ use atlas_communication::message::{Header, StoredMessage};
 use atlas_execution::serialize::ApplicationData;
 use atlas_metrics::metrics::{metric_duration, metric_increment};
 
-use crate::messages::{ClientRqInfo, RequestMessage, StoredRequestMessage};
-use crate::metric::{RQ_PP_ORCHESTRATOR_WORKER_PASSING_TIME_ID, RQ_PP_WORKER_DECIDED_PROCESS_TIME_ID, RQ_PP_WORKER_ORDER_PROCESS_COUNT_ID, RQ_PP_WORKER_ORDER_PROCESS_ID};
-use crate::request_pre_processing::{operation_key, operation_key_raw, PreProcessorOutput, PreProcessorOutputMessage};
+use crate::messages::{ClientRqInfo, RejectionReason, RequestMessage, StoredRequestMessage};
+use crate::metric::{RQ_PP_INVALID_RQS_ID, RQ_PP_ORCHESTRATOR_WORKER_PASSING_TIME_ID, RQ_PP_SHED_RQS_ID, RQ_PP_WORKER_DECIDED_PROCESS_TIME_ID, RQ_PP_WORKER_ORDER_PROCESS_COUNT_ID, RQ_PP_WORKER_ORDER_PROCESS_ID};
+use crate::request_pre_processing::{operation_key, operation_key_raw, PreProcessorOutputMessage, RejectedRequests, split_operation_key, WorkPartitioner};
+use crate::request_pre_processing::async_output::BatchProducer;
+use crate::request_pre_processing::budget::{OverloadPolicy, PendingRqBudget};
+use crate::request_pre_processing::latest_ops::SessionLatestOp;
+use crate::request_pre_processing::session_window::SessionWindow;
+use crate::request_pre_processing::validation::{RequestValidationHook, ValidationVerdict};
+use crate::smr::exec::ReplyType;
+use crate::smr::exec::unordered::UnorderedRequestSink;
 use crate::timeouts::{RqTimeout, TimeoutKind, TimeoutPhase};
 
 const WORKER_QUEUE_SIZE: usize = 124;
@@ -25,6 +33,16 @@ const WORKER_THREAD_NAME: &str = "RQ-PRE-PROCESSING-WORKER-{}";
 
 pub type PreProcessorWorkMessageOuter<O> = (Instant, PreProcessorWorkMessage<O>);
 
+/// Decides whether a given session (client, session id) should leave the worker
+pub type SessionFilter = Box<dyn Fn(NodeId, SeqNo) -> bool + Send>;
+
+/// The state of a set of sessions, being moved between workers when the worker pool is resized
+pub struct SessionMigration<O> {
+    pub(super) latest_ops: Vec<(u64, SessionWindow)>,
+    pub(super) latest_decided: Vec<SessionLatestOp>,
+    pub(super) pending_requests: Vec<StoredRequestMessage<O>>,
+}
+
 pub enum PreProcessorWorkMessage<O> {
     /// We have received requests from the clients, which need
     /// to be processed
@@ -44,6 +62,16 @@ pub enum PreProcessorWorkMessage<O> {
     ClonePendingRequests(Vec<ClientRqInfo>, OneShotTx<Vec<StoredRequestMessage<O>>>),
     /// Remove all requests associated with this client (due to a disconnection, for example)
     CleanClient(NodeId),
+    /// Snapshot the latest decided operations of the sessions handled by this worker
+    SnapshotLatestOps(OneShotTx<Vec<SessionLatestOp>>),
+    /// Install the latest decided operations of a set of sessions (from a checkpoint, for example)
+    InstallLatestOps(Vec<SessionLatestOp>),
+    /// Remove the state of all sessions accepted by the filter from this worker, so they can be moved to another worker
+    ExtractSessions(SessionFilter, OneShotTx<SessionMigration<O>>),
+    /// Take over the state of sessions that were handled by another worker
+    InstallSessions(SessionMigration<O>),
+    /// Stop this worker. All of its sessions must have been extracted beforehand
+    Shutdown,
 }
 
 /// Each worker will be assigned a given set of clients
@@ -53,25 +81,56 @@ pub struct RequestPreProcessingWorker<O> {
     message_rx: ChannelSyncRx<PreProcessorWorkMessageOuter<O>>,
 
     /// Output for the requests that have been processed and should now be proposed
-    batch_production: ChannelSyncTx<PreProcessorOutput<O>>,
+    batch_production: BatchProducer<O>,
 
-    /// The latest operations seen by this worker.
+    /// The latest operations seen by this worker, for each session.
     /// Since a given session will always be handled by the same worker,
     /// we can use this to filter out duplicates.
-    latest_ops: IntMap<(SeqNo, Option<Digest>)>,
+    latest_ops: IntMap<SessionWindow>,
+    /// How many outstanding operations each session can have
+    session_window: usize,
+    /// The latest operations that have been decided for each session handled by this worker.
+    /// Unlike [Self::latest_ops], this does not account for requests that have only been received,
+    /// so it can be persisted along with the checkpoints
+    latest_decided: IntMap<SessionLatestOp>,
     /// The requests that have not been added to a batch yet.
     pending_requests: HashMap<Digest, StoredRequestMessage<O>>,
+    /// The order in which the pending requests were inserted, so we can shed the oldest ones.
+    /// Entries are removed lazily, so this can contain digests that are no longer pending
+    pending_order: VecDeque<Digest>,
+    /// The pending request budget, shared with all other workers
+    budget: Arc<PendingRqBudget>,
+    /// Requests that were rejected by this worker, to be replied to by the orchestrator
+    rejected_tx: ChannelSyncTx<RejectedRequests>,
+    /// The application's validation of the requests, if any
+    validation: Option<Arc<dyn RequestValidationHook<O>>>,
+    /// Where the unordered requests are executed, if they should not go to the proposer
+    unordered_sink: Option<Arc<dyn UnorderedRequestSink<O>>>,
+    /// The partitioner of the pre processor, which is told when requests start and stop being pending
+    partitioner: Arc<dyn WorkPartitioner<O>>,
 }
 
 
 impl<O> RequestPreProcessingWorker<O> where O: Clone {
-    pub fn new(worker_id: usize, message_rx: ChannelSyncRx<PreProcessorWorkMessageOuter<O>>, batch_production: ChannelSyncTx<PreProcessorOutput<O>>) -> Self {
+    pub fn new(worker_id: usize, message_rx: ChannelSyncRx<PreProcessorWorkMessageOuter<O>>, batch_production: BatchProducer<O>,
+               budget: Arc<PendingRqBudget>, rejected_tx: ChannelSyncTx<RejectedRequests>,
+               validation: Option<Arc<dyn RequestValidationHook<O>>>,
+               unordered_sink: Option<Arc<dyn UnorderedRequestSink<O>>>, partitioner: Arc<dyn WorkPartitioner<O>>,
+               session_window: usize) -> Self {
         Self {
             worker_id,
             message_rx,
             batch_production,
             latest_ops: Default::default(),
+            session_window: session_window.max(1),
+            latest_decided: Default::default(),
             pending_requests: Default::default(),
+            pending_order: Default::default(),
+            budget,
+            rejected_tx,
+            validation,
+            unordered_sink,
+            partitioner,
         }
     }
 
@@ -109,52 +168,208 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
                 PreProcessorWorkMessage::StoppedRequestsReceived(reqs) => {
                     self.stopped_requests(reqs);
                 }
+                PreProcessorWorkMessage::SnapshotLatestOps(tx) => {
+                    tx.send(self.snapshot_latest_ops()).expect("Failed to send latest ops snapshot");
+                }
+                PreProcessorWorkMessage::InstallLatestOps(ops) => {
+                    self.install_latest_ops(ops);
+                }
+                PreProcessorWorkMessage::ExtractSessions(filter, tx) => {
+                    tx.send(self.extract_sessions(filter)).expect("Failed to send extracted sessions");
+                }
+                PreProcessorWorkMessage::InstallSessions(migration) => {
+                    self.install_sessions(migration);
+                }
+                PreProcessorWorkMessage::Shutdown => {
+                    debug!("Worker {} // Shutting down, {} pending requests left", self.worker_id, self.pending_requests.len());
+
+                    break;
+                }
             }
 
             metric_duration(RQ_PP_ORCHESTRATOR_WORKER_PASSING_TIME_ID, sent_time.elapsed());
         }
     }
 
-    /// Checks if we have received a more recent message for a given client/session combo
+    /// Checks if we have already received this operation (or if it is behind the window of its session).
+    /// If not, it is registered in the session's window
     fn has_received_more_recent_and_update(&mut self, header: &Header, message: &RequestMessage<O>, unique_digest: &Digest) -> bool {
         let key = operation_key::<O>(header, message);
 
-        let (seq_no, digest) = {
-            if let Some((seq_no, digest)) = self.latest_ops.get_mut(key) {
-                (seq_no.clone(), digest.clone())
-            } else {
-                (SeqNo::ZERO, None)
-            }
-        };
+        let window = self.session_window;
 
-        let has_received_more_recent = seq_no >= message.sequence_number();
+        if !self.latest_ops.contains_key(key) {
+            self.latest_ops.insert(key, SessionWindow::new());
+        }
 
-        if !has_received_more_recent {
-            digest.map(|digest| self.pending_requests.remove(&digest));
+        let session = self.latest_ops.get_mut(key).unwrap();
 
-            self.latest_ops.insert(key, (message.sequence_number(), Some(unique_digest.clone())));
+        if !session.accepts(message.sequence_number(), window) {
+            return true;
         }
 
-        has_received_more_recent
+        let discarded = session.receive(message.sequence_number(), unique_digest.clone(), window);
+
+        discarded.iter().for_each(|digest| {
+            self.remove_pending(digest);
+        });
+
+        false
     }
 
     fn update_most_recent(&mut self, rq_info: &ClientRqInfo) {
-        let key = operation_key_raw(rq_info.sender, rq_info.session);
+        self.update_most_recent_raw(rq_info.sender, rq_info.session, rq_info.seq_no);
+    }
 
-        let (seq_no, digest) = {
-            if let Some((seq_no, digest)) = self.latest_ops.get_mut(key) {
-                (seq_no.clone(), digest.clone())
-            } else {
-                (SeqNo::ZERO, None)
+    fn update_most_recent_raw(&mut self, sender: NodeId, session: SeqNo, decided_seq: SeqNo) {
+        let key = operation_key_raw(sender, session);
+
+        match self.latest_decided.get_mut(key) {
+            Some(latest) if latest.sequence_number() >= decided_seq => {}
+            _ => {
+                self.latest_decided.insert(key, SessionLatestOp::new(sender, session, decided_seq));
             }
+        }
+
+        let window = self.session_window;
+
+        let discarded = match self.latest_ops.get_mut(key) {
+            Some(session) => session.decide(decided_seq, window),
+            None => {
+                let mut session = SessionWindow::new();
+
+                session.decide(decided_seq, window);
+
+                self.latest_ops.insert(key, session);
+
+                Vec::new()
+            }
+        };
+
+        discarded.iter().for_each(|digest| {
+            self.remove_pending(digest);
+        });
+    }
+
+    /// Check that the operation of the request can be decoded and then check it with the application's validation hook.
+    /// The clients of invalid requests are notified, either by the hook or with a rejection
+    fn is_valid(&self, request: &StoredRequestMessage<O>, reply_type: ReplyType) -> bool {
+        // The operation is only decoded lazily, so malformed operations would otherwise only be
+        // noticed when executing them
+        if let Err(err) = request.message().try_operation() {
+            warn!("Discarding request from {:?} with a malformed operation: {:?}", request.header().from(), err);
+
+            return self.reject_invalid(request);
+        }
+
+        let verdict = match &self.validation {
+            Some(validation) => validation.check(reply_type, request.header(), request.message()),
+            None => ValidationVerdict::Accepted
         };
 
-        let has_received_more_recent = seq_no >= rq_info.seq_no;
+        match verdict {
+            ValidationVerdict::Accepted => true,
+            ValidationVerdict::Answered => {
+                metric_increment(RQ_PP_INVALID_RQS_ID, Some(1));
+
+                false
+            }
+            ValidationVerdict::Rejected => self.reject_invalid(request),
+        }
+    }
+
+    fn reject_invalid(&self, request: &StoredRequestMessage<O>) -> bool {
+        metric_increment(RQ_PP_INVALID_RQS_ID, Some(1));
+
+        self.reject_requests(vec![ClientRqInfo::from(request)], RejectionReason::Invalid);
+
+        false
+    }
+
+    /// Insert a request into the pending requests, accounting for it in the budget and in the load of the partitioner
+    fn insert_pending(&mut self, digest: Digest, request: StoredRequestMessage<O>) {
+        self.budget.reserve(request.header().payload_length());
+
+        let (client, session) = (request.header().from(), request.message().session_id());
+
+        if let Some(previous) = self.pending_requests.insert(digest.clone(), request) {
+            self.budget.release(previous.header().payload_length());
+        } else {
+            self.partitioner.request_admitted(client, session);
+
+            self.pending_order.push_back(digest);
+        }
+    }
+
+    /// Remove a request from the pending requests, releasing its space in the budget and its load in the partitioner
+    fn remove_pending(&mut self, digest: &Digest) -> Option<StoredRequestMessage<O>> {
+        let removed = self.pending_requests.remove(digest);
+
+        if let Some(request) = &removed {
+            self.budget.release(request.header().payload_length());
+
+            self.partitioner.request_completed(request.header().from(), request.message().session_id());
+        }
+
+        // Since entries are removed lazily, make sure the order queue does not grow unbounded
+        if self.pending_order.len() > (self.pending_requests.len() * 2).max(WORKER_QUEUE_SIZE) {
+            let pending_requests = &self.pending_requests;
+
+            self.pending_order.retain(|digest| pending_requests.contains_key(digest));
+        }
+
+        removed
+    }
+
+    /// Shed the oldest pending requests of this worker until we are back under the budget.
+    /// Only applicable with the [OverloadPolicy::ShedOldest] policy
+    fn shed_oldest_if_necessary(&mut self) {
+        if self.budget.policy() != OverloadPolicy::ShedOldest {
+            return;
+        }
+
+        let mut shed = Vec::new();
+
+        while self.budget.is_exhausted() {
+            let digest = match self.pending_order.pop_front() {
+                Some(digest) => digest,
+                None => break
+            };
+
+            if let Some(request) = self.remove_pending(&digest) {
+                shed.push(ClientRqInfo::from(&request));
+            }
+        }
+
+        if !shed.is_empty() {
+            warn!("Worker {} // Shed {} pending requests as the pending request budget is exhausted", self.worker_id, shed.len());
 
-        if !has_received_more_recent {
-            digest.map(|digest| self.pending_requests.remove(&digest));
+            metric_increment(RQ_PP_SHED_RQS_ID, Some(shed.len() as u64));
 
-            self.latest_ops.insert(key, (rq_info.seq_no, None));
+            self.reject_requests(shed, RejectionReason::Shed);
+        }
+    }
+
+    /// Deliver a batch of requests to the proposer.
+    /// We never block the worker on a full proposer queue (that would stall the orchestrator along with it),
+    /// so when it is full the requests are rejected back to the clients instead of being silently lost
+    fn deliver_to_proposer(&mut self, message: PreProcessorOutputMessage<O>) {
+        let rq_infos: Vec<ClientRqInfo> = message.iter().map(ClientRqInfo::from).collect();
+
+        if let Err(err) = self.batch_production.try_send(message) {
+            error!("Worker {} // Failed to send {} requests to batch production, rejecting them: {:?}", self.worker_id, rq_infos.len(), err);
+
+            rq_infos.iter().for_each(|rq_info| {
+                self.remove_pending(&rq_info.digest);
+            });
+
+            self.reject_requests(rq_infos, RejectionReason::Overloaded);
+        }
+    }
+
+    fn reject_requests(&self, requests: Vec<ClientRqInfo>, reason: RejectionReason) {
+        if let Err(err) = self.rejected_tx.try_send((requests, reason)) {
+            error!("Worker {} // Failed to deliver rejected requests to the orchestrator: {:?}", self.worker_id, err);
         }
     }
 
@@ -165,21 +380,30 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
         let processed_rqs = requests.len();
 
         let requests: Vec<StoredRequestMessage<O>> = requests.into_iter().filter(|request| {
+            if !self.is_valid(request, ReplyType::Ordered) {
+                return false;
+            }
+
             let digest = request.header().unique_digest();
 
             if self.has_received_more_recent_and_update(request.header(), request.message(), &digest) {
                 return false;
             }
 
-            self.pending_requests.insert(digest.clone(), request.clone());
+            self.insert_pending(digest.clone(), request.clone());
 
             return true;
         }).collect();
 
+        self.shed_oldest_if_necessary();
+
+        // Requests that were shed right away must not be proposed
+        let requests: Vec<StoredRequestMessage<O>> = requests.into_iter()
+            .filter(|request| self.pending_requests.contains_key(&request.header().unique_digest()))
+            .collect();
+
         if !requests.is_empty() {
-            if let Err(err) = self.batch_production.try_send((PreProcessorOutputMessage::DeDupedOrderedRequests(requests), Instant::now())) {
-                error!("Worker {} // Failed to send client requests to batch production: {:?}", self.worker_id, err);
-            }
+            self.deliver_to_proposer(PreProcessorOutputMessage::DeDupedOrderedRequests(requests));
         }
 
         metric_duration(RQ_PP_WORKER_ORDER_PROCESS_ID, start.elapsed());
@@ -189,6 +413,10 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
     /// Process the unordered client pool requests
     fn process_unordered_client_pool_rqs(&mut self, requests: Vec<StoredRequestMessage<O>>) {
         let requests: Vec<StoredRequestMessage<O>> = requests.into_iter().filter(|request| {
+            if !self.is_valid(request, ReplyType::Unordered) {
+                return false;
+            }
+
             let digest = request.header().unique_digest();
 
             if self.has_received_more_recent_and_update(request.header(), request.message(), &digest) {
@@ -198,11 +426,19 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
             return true;
         }).collect();
 
-        if !requests.is_empty() {
-            if let Err(err) = self.batch_production.try_send((PreProcessorOutputMessage::DeDupedUnorderedRequests(requests), Instant::now())) {
-                error!("Worker {} // Failed to send unordered requests to batch production: {:?}", self.worker_id, err);
-            }
+        if requests.is_empty() {
+            return;
         }
+
+        let requests = match &self.unordered_sink {
+            Some(sink) => match sink.submit(requests) {
+                Ok(()) => return,
+                Err(requests) => requests,
+            },
+            None => requests,
+        };
+
+        self.deliver_to_proposer(PreProcessorOutputMessage::DeDupedUnorderedRequests(requests));
     }
 
     /// Process the forwarded requests
@@ -210,13 +446,17 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
         let initial_size = requests.len();
 
         let requests: Vec<StoredRequestMessage<O>> = requests.into_iter().filter(|request| {
+            if !self.is_valid(request, ReplyType::Ordered) {
+                return false;
+            }
+
             let digest = request.header().unique_digest();
 
             if self.has_received_more_recent_and_update(request.header(), request.message(), &digest) {
                 return false;
             }
 
-            self.pending_requests.insert(digest.clone(), request.clone());
+            self.insert_pending(digest.clone(), request.clone());
 
             return true;
         }).collect();
@@ -224,9 +464,7 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
         debug!("Worker {} // Forwarded requests processed, out of {} left with {:?}", self.worker_id, initial_size, requests);
 
         if !requests.is_empty() {
-            if let Err(err) = self.batch_production.try_send((PreProcessorOutputMessage::DeDupedOrderedRequests(requests), Instant::now())) {
-                error!("Worker {} // Failed to send forwarded requests to batch production: {:?}", self.worker_id, err);
-            }
+            self.deliver_to_proposer(PreProcessorOutputMessage::DeDupedOrderedRequests(requests));
         }
     }
 
@@ -244,14 +482,11 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
             let result = if let TimeoutKind::ClientRequestTimeout(rq_info) = timeout.timeout_kind() {
                 let key = operation_key_raw(rq_info.sender, rq_info.session);
 
-                if let Some((seq_no, _)) = self.latest_ops.get(key) {
-                    if *seq_no >= rq_info.seq_no {
+                match self.latest_ops.get(key) {
+                    Some(session) if !session.accepts(rq_info.seq_no, self.session_window) => {
                         self.pending_requests.contains_key(&rq_info.digest)
-                    } else {
-                        true
                     }
-                } else {
-                    true
+                    _ => true
                 }
             } else {
                 false
@@ -272,7 +507,7 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
         let start = Instant::now();
 
         requests.into_iter().for_each(|request| {
-            self.pending_requests.remove(&request.digest);
+            self.remove_pending(&request.digest);
 
             // Update so that if we later on receive the same request from the client, we can safely ignore it
             // And not get build up in the pending requests
@@ -297,8 +532,100 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
 
     /// Collect all pending requests stored in this worker
     fn collect_pending_requests(&mut self) -> Vec<StoredRequestMessage<O>> {
+        self.pending_order.clear();
+
         std::mem::replace(&mut self.pending_requests, Default::default())
-            .into_iter().map(|(_, request)| request).collect()
+            .into_iter().map(|(_, request)| {
+            self.budget.release(request.header().payload_length());
+
+            self.partitioner.request_completed(request.header().from(), request.message().session_id());
+
+            request
+        }).collect()
+    }
+
+    fn snapshot_latest_ops(&self) -> Vec<SessionLatestOp> {
+        self.latest_decided.values().cloned().collect()
+    }
+
+    /// Install the latest decided operations, for example after a restart or a state transfer.
+    /// Any pending request that has already been decided according to the installed operations is discarded
+    fn install_latest_ops(&mut self, ops: Vec<SessionLatestOp>) {
+        for op in ops {
+            let key = op.operation_key();
+
+            // We only know the latest decided operation, so every operation before it is treated as decided
+            if let Some(session) = self.latest_ops.get_mut(key) {
+                let discarded = session.decide_up_to(op.sequence_number(), self.session_window);
+
+                discarded.iter().for_each(|digest| {
+                    self.remove_pending(digest);
+                });
+            }
+
+            self.update_most_recent_raw(op.client(), op.session(), op.sequence_number());
+        }
+    }
+
+    /// Remove the state of every session accepted by the filter.
+    /// The pending requests remain accounted for in the budget and in the partitioner, as they are only changing workers
+    fn extract_sessions(&mut self, filter: SessionFilter) -> SessionMigration<O> {
+        let moves = |key: u64| {
+            let (client, session) = split_operation_key(key);
+
+            filter(client, session)
+        };
+
+        let latest_ops_keys: Vec<u64> = self.latest_ops.keys().cloned().filter(|key| moves(*key)).collect();
+        let decided_keys: Vec<u64> = self.latest_decided.keys().cloned().filter(|key| moves(*key)).collect();
+        let pending_digests: Vec<Digest> = self.pending_requests.iter()
+            .filter(|(_, request)| moves(operation_key::<O>(request.header(), request.message())))
+            .map(|(digest, _)| digest.clone())
+            .collect();
+
+        let latest_ops = latest_ops_keys.into_iter()
+            .filter_map(|key| self.latest_ops.remove(key).map(|op| (key, op)))
+            .collect();
+
+        let latest_decided = decided_keys.into_iter()
+            .filter_map(|key| self.latest_decided.remove(key))
+            .collect();
+
+        let pending_requests: Vec<StoredRequestMessage<O>> = pending_digests.iter()
+            .filter_map(|digest| self.pending_requests.remove(digest))
+            .collect();
+
+        if !pending_requests.is_empty() {
+            let pending = &self.pending_requests;
+
+            self.pending_order.retain(|digest| pending.contains_key(digest));
+        }
+
+        SessionMigration {
+            latest_ops,
+            latest_decided,
+            pending_requests,
+        }
+    }
+
+    /// Take over the sessions extracted from another worker
+    fn install_sessions(&mut self, migration: SessionMigration<O>) {
+        let SessionMigration { latest_ops, latest_decided, pending_requests } = migration;
+
+        for (key, op) in latest_ops {
+            self.latest_ops.insert(key, op);
+        }
+
+        for op in latest_decided {
+            self.latest_decided.insert(op.operation_key(), op);
+        }
+
+        for request in pending_requests {
+            let digest = request.header().unique_digest();
+
+            self.pending_order.push_back(digest.clone());
+            self.pending_requests.insert(digest, request);
+        }
     }
 
     fn clean_client(&self, node_id: NodeId) {
@@ -313,16 +640,20 @@ impl<O> RequestPreProcessingWorker<O> where O: Clone {
                 return;
             }
 
-            self.pending_requests.insert(digest.clone(), request.clone());
+            self.insert_pending(digest.clone(), request);
         })
     }
 }
 
-pub(super) fn spawn_worker<O>(worker_id: usize, batch_tx: ChannelSyncTx<(PreProcessorOutputMessage<O>, Instant)>) -> RequestPreProcessingWorkerHandle<O>
+pub(super) fn spawn_worker<O>(worker_id: usize, batch_tx: BatchProducer<O>,
+                              budget: Arc<PendingRqBudget>, rejected_tx: ChannelSyncTx<RejectedRequests>,
+                              validation: Option<Arc<dyn RequestValidationHook<O>>>,
+                              unordered_sink: Option<Arc<dyn UnorderedRequestSink<O>>>, partitioner: Arc<dyn WorkPartitioner<O>>,
+                              session_window: usize) -> RequestPreProcessingWorkerHandle<O>
     where O: Clone + Send + 'static {
     let (worker_tx, worker_rx) = atlas_common::channel::new_bounded_sync(WORKER_QUEUE_SIZE);
 
-    let worker = RequestPreProcessingWorker::new(worker_id, worker_rx, batch_tx);
+    let worker = RequestPreProcessingWorker::new(worker_id, worker_rx, batch_tx, budget, rejected_tx, validation, unordered_sink, partitioner, session_window);
 
     std::thread::Builder::new()
         .name(format!("{}{}", WORKER_THREAD_NAME, worker_id))
@@ -339,4 +670,14 @@ impl<O> RequestPreProcessingWorkerHandle<O> {
     pub fn send(&self, message: PreProcessorWorkMessage<O>) {
         self.0.send((Instant::now(), message)).unwrap()
     }
+}
+
+impl<O> SessionMigration<O> {
+    pub(super) fn new() -> Self {
+        Self {
+            latest_ops: Vec::new(),
+            latest_decided: Vec::new(),
+            pending_requests: Vec::new(),
+        }
+    }
 }
\ No newline at end of file
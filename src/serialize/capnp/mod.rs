@@ -1,17 +1,57 @@
-use febft_capnp::{messages_capnp, service_messages_capnp};
-use atlas_execution::serialize::ApplicationData;
+use std::sync::Arc;
+
+use atlas_common::crypto::hash::Digest;
 use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
 use atlas_common::ordering::{Orderable, SeqNo};
 use atlas_communication::message::{Header, StoredMessage};
-use atlas_communication::serialize::{Buf, Serializable};
-use crate::messages::{ForwardedProtocolMessage, ForwardedRequestsMessage, Protocol, ReplyMessage, RequestMessage, StateTransfer, SystemMessage};
-use crate::serialize::{OrderingProtocolMessage, StateTransferMessage, Service};
+use atlas_communication::serialize::Serializable;
+use atlas_execution::serialize::ApplicationData;
+
+use crate::log_transfer::networking::serialize::LogTransferMessage;
+use crate::messages::authenticator::{Authenticator, Mac, MAC_LENGTH};
+use crate::messages::payload::decode_request;
+use crate::messages::{ForwardedProtocolMessage, ForwardedRequestsMessage, LogTransfer, Protocol, RejectionReason, ReplyMessage, RequestMessage, RequestRejectedMessage, StateTransfer, SystemMessage};
+use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
+use crate::serialize::compression::{CompressedPayload, CompressionAlgorithm, CompressionRequest, CompressionSet};
+use crate::serialize::Service;
+use crate::serialize::versioning::{CURRENT_WIRE_VERSION, WireCapabilities, WireHandshakeMessage, WireVersion};
+use crate::state_transfer::networking::serialize::StateTransferMessage;
+
+/// The code generated from the schemas in the `schemas` directory
+pub mod messages_capnp {
+    include!(concat!(env!("OUT_DIR"), "/messages_capnp.rs"));
+}
 
 const DEFAULT_SERIALIZE_BUFFER_SIZE: usize = 1024;
 
-pub type Message<D, P, SP> = <Service<D, P, SP> as Serializable>::Message;
+pub type Message<D, P, S, L> = <Service<D, P, S, L> as Serializable>::Message;
+
+pub(super) fn serialize_message<D, P, S, L>(mut builder: messages_capnp::system::Builder, msg: &Message<D, P, S, L>) -> Result<()>
+    where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+          S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static {
+    let wire_version = match msg {
+        SystemMessage::ProtocolMessage(protocol) => protocol.wire_version(),
+        SystemMessage::StateTransferMessage(state_transfer) => state_transfer.wire_version(),
+        SystemMessage::LogTransferMessage(log_transfer) => log_transfer.wire_version(),
+        _ => CURRENT_WIRE_VERSION,
+    };
+
+    builder.set_wire_version(wire_version.version());
+
+    let authenticator = match msg {
+        SystemMessage::ProtocolMessage(protocol) => protocol.authenticator(),
+        SystemMessage::StateTransferMessage(state_transfer) => state_transfer.authenticator(),
+        SystemMessage::LogTransferMessage(log_transfer) => log_transfer.authenticator(),
+        _ => None,
+    };
+
+    if let Some(authenticator) = authenticator {
+        builder.set_authenticator_seq(authenticator.seq());
+
+        serialize_authenticator(builder.reborrow().init_authenticator(authenticator.macs().len() as u32), authenticator);
+    }
 
-pub(super) fn serialize_message<D, P, SP>(builder: messages_capnp::system::Builder, msg: &Message<D, P, SP>) -> Result<()> where D: ApplicationData, P: OrderingProtocolMessage, SP: StateTransferMessage {
     match msg {
         SystemMessage::OrderedRequest(req) => {
             let rq_builder = builder.init_request();
@@ -41,7 +81,7 @@ pub(super) fn serialize_message<D, P, SP>(builder: messages_capnp::system::Build
         SystemMessage::ForwardedProtocolMessage(fwd_protocol) => {
             let fwd_protocol_builder = builder.init_fwd_protocol();
 
-            serialize_fwd_protocol_message::<P>(fwd_protocol_builder, fwd_protocol)?;
+            serialize_fwd_protocol_message::<D, P>(fwd_protocol_builder, fwd_protocol)?;
         }
         SystemMessage::ForwardedRequestMessage(fwd_req) => {
             let mut fwd_rqs_builder = builder.init_fwd_requests(fwd_req.requests().len() as u32);
@@ -53,26 +93,56 @@ pub(super) fn serialize_message<D, P, SP>(builder: messages_capnp::system::Build
 
                 // set header
                 {
-                    stored.header().serialize_into(&mut header[..]).unwrap();
+                    stored.header().serialize_into(&mut header[..])?;
                     forwarded.set_header(&header[..]);
                 }
 
                 // set request
                 {
-                    let mut request = forwarded.reborrow().init_request();
+                    let request = forwarded.reborrow().init_request();
 
                     serialize_request::<D>(request, stored.message())?;
                 }
             }
         }
-        SystemMessage::StateTransferMessage(_) => {}
+        SystemMessage::StateTransferMessage(state_transfer) => {
+            builder.set_accepted_compression(state_transfer.accepted_compression().bits());
+
+            S::serialize_capnp(builder.init_state_transfer(), state_transfer.payload())?;
+        }
+        SystemMessage::LogTransferMessage(log_transfer) => {
+            builder.set_accepted_compression(log_transfer.accepted_compression().bits());
+
+            L::serialize_capnp(builder.init_log_transfer(), log_transfer.payload())?;
+        }
+        SystemMessage::RequestRejected(rejection) => {
+            let rejection_builder = builder.init_request_rejected();
+
+            serialize_rejection(rejection_builder, rejection);
+        }
+        SystemMessage::WireHandshake(handshake) => {
+            let mut handshake_builder = builder.init_wire_handshake();
+
+            handshake_builder.set_min_version(handshake.capabilities().min().version());
+            handshake_builder.set_max_version(handshake.capabilities().max().version());
+        }
     }
 
     Ok(())
 }
 
-pub(super) fn deserialize_message<D, P, ST>(reader: messages_capnp::system::Reader) -> Result<Message<D, P, ST>>
-    where D: ApplicationData, P: OrderingProtocolMessage, ST: StateTransferMessage {
+pub(super) fn deserialize_message<D, P, S, L>(reader: messages_capnp::system::Reader) -> Result<Message<D, P, S, L>>
+    where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+          S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static {
+    let wire_version = WireVersion::new(reader.get_wire_version());
+    let accepted_compression = CompressionSet::from_bits(reader.get_accepted_compression());
+
+    let authenticator = if reader.has_authenticator() {
+        Some(deserialize_authenticator(reader.get_authenticator_seq(), reader.get_authenticator().wrapped(ErrorKind::CommunicationSerialize)?)?)
+    } else {
+        None
+    };
+
     let which = reader.which().wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to read which type of message for the system message")?;
 
     return match which {
@@ -88,20 +158,20 @@ pub(super) fn deserialize_message<D, P, ST>(reader: messages_capnp::system::Read
         messages_capnp::system::WhichReader::UnorderedReply(Ok(rep)) => {
             Ok(SystemMessage::UnorderedReply(deserialize_reply::<D>(rep)?))
         }
-        messages_capnp::system::WhichReader::Protocol(Ok(protocol)) => {
-            Ok(SystemMessage::ProtocolMessage(Protocol::new(P::deserialize_capnp(protocol)?)))
+        messages_capnp::system::WhichReader::Protocol(protocol) => {
+            Ok(SystemMessage::ProtocolMessage(Protocol::with_version(deserialize_versioned(wire_version, protocol, P::deserialize_capnp, P::deserialize_previous_capnp)?, wire_version)
+                .with_authenticator(authenticator)))
         }
         messages_capnp::system::WhichReader::FwdProtocol(Ok(fwd_protocol)) => {
-            Ok(SystemMessage::ForwardedProtocolMessage(deserialize_fwd_protocol_message::<P>(fwd_protocol)?))
+            Ok(SystemMessage::ForwardedProtocolMessage(deserialize_fwd_protocol_message::<D, P>(fwd_protocol)?))
         }
         messages_capnp::system::WhichReader::FwdRequests(Ok(reqs)) => {
-            let mut rqs = Vec::new();
+            let mut rqs = Vec::with_capacity(reqs.len() as usize);
 
             for fwd_req in reqs.iter() {
-                let header: Vec<u8> = fwd_req
+                let header = fwd_req
                     .get_header()
-                    .wrapped(ErrorKind::CommunicationSerialize)?
-                    .to_vec();
+                    .wrapped(ErrorKind::CommunicationSerialize)?;
 
                 let request = fwd_req
                     .get_request()
@@ -110,18 +180,34 @@ pub(super) fn deserialize_message<D, P, ST>(reader: messages_capnp::system::Read
                 let parsed_request = deserialize_request::<D>(request)?;
 
                 rqs.push(StoredMessage::new(
-                    Header::deserialize_from(&header[..])?,
+                    Header::deserialize_from(header)?,
                     parsed_request,
                 ));
             }
 
             Ok(SystemMessage::ForwardedRequestMessage(ForwardedRequestsMessage::new(rqs)))
         }
-        messages_capnp::system::WhichReader::StateTransfer(Ok(st)) => {
-            Ok(SystemMessage::StateTransferMessage(StateTransfer::new(ST::deserialize_capnp(st)?)))
+        messages_capnp::system::WhichReader::StateTransfer(st) => {
+            Ok(SystemMessage::StateTransferMessage(StateTransfer::with_version(deserialize_versioned(wire_version, st, S::deserialize_capnp, S::deserialize_previous_capnp)?, wire_version)
+                .with_accepted_compression(accepted_compression)
+                .with_authenticator(authenticator)))
         }
-        messages_capnp::system::WhichReader::StateTransfer(Err(err)) => {
-            Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
+        messages_capnp::system::WhichReader::LogTransfer(lt) => {
+            Ok(SystemMessage::LogTransferMessage(LogTransfer::with_version(deserialize_versioned(wire_version, lt, L::deserialize_capnp, L::deserialize_previous_capnp)?, wire_version)
+                .with_accepted_compression(accepted_compression)
+                .with_authenticator(authenticator)))
+        }
+        messages_capnp::system::WhichReader::Compressed(Ok(compressed)) => {
+            deserialize_compressed::<D, P, S, L>(compressed)
+        }
+        messages_capnp::system::WhichReader::RequestRejected(Ok(rejection)) => {
+            Ok(SystemMessage::RequestRejected(deserialize_rejection(rejection)?))
+        }
+        messages_capnp::system::WhichReader::WireHandshake(Ok(handshake)) => {
+            let capabilities = WireCapabilities::new(WireVersion::new(handshake.get_min_version()),
+                                                     WireVersion::new(handshake.get_max_version()));
+
+            Ok(SystemMessage::WireHandshake(WireHandshakeMessage::new(capabilities)))
         }
         messages_capnp::system::WhichReader::Request(Err(err)) => {
             Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
@@ -138,39 +224,160 @@ pub(super) fn deserialize_message<D, P, ST>(reader: messages_capnp::system::Read
         messages_capnp::system::WhichReader::FwdRequests(Err(err)) => {
             Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
         }
-        messages_capnp::system::WhichReader::Protocol(Err(err)) => {
+        messages_capnp::system::WhichReader::FwdProtocol(Err(err)) => {
             Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
         }
-        messages_capnp::system::WhichReader::FwdProtocol(Err(err)) => {
+        messages_capnp::system::WhichReader::RequestRejected(Err(err)) => {
+            Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
+        }
+        messages_capnp::system::WhichReader::WireHandshake(Err(err)) => {
+            Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
+        }
+        messages_capnp::system::WhichReader::Compressed(Err(err)) => {
             Err(Error::wrapped(ErrorKind::CommunicationSerialize, err))
         }
     };
 }
 
-pub fn serialize_request<D>(mut builder: service_messages_capnp::request::Builder, msg: &RequestMessage<D::Request>) -> Result<()> where D: ApplicationData {
+/// Compress the encoding of a state transfer or log transfer message,
+/// framing it in a system message of its own.
+/// Returns None if the message is not worth compressing, so it should be sent as it was encoded
+pub(super) fn compress_message(encoded: &[u8], compression: &CompressionRequest) -> Result<Option<Vec<u8>>> {
+    let compressed = match compression.compress(encoded)? {
+        Some(compressed) => compressed,
+        None => return Ok(None),
+    };
+
+    let mut message = ::capnp::message::Builder::new_default();
+
+    {
+        let mut builder: messages_capnp::system::Builder = message.init_root();
+
+        builder.set_wire_version(CURRENT_WIRE_VERSION.version());
+
+        serialize_compressed(builder.init_compressed(), &compressed);
+    }
+
+    let mut framed = Vec::with_capacity(compressed.bytes().len() + DEFAULT_SERIALIZE_BUFFER_SIZE);
+
+    ::capnp::serialize::write_message(&mut framed, &message)
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to encode the compressed message")?;
+
+    Ok(Some(framed))
+}
+
+/// Encode a payload as a standalone message
+pub(super) fn encode_payload<F>(serialize: F) -> Result<Vec<u8>>
+    where F: FnOnce(::capnp::any_pointer::Builder) -> Result<()> {
+    let mut message = ::capnp::message::Builder::new_default();
+
+    serialize(message.init_root())?;
+
+    let mut encoded = Vec::with_capacity(DEFAULT_SERIALIZE_BUFFER_SIZE);
+
+    ::capnp::serialize::write_message(&mut encoded, &message)
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to encode the payload")?;
+
+    Ok(encoded)
+}
+
+/// Decompress and decode a compressed state transfer or log transfer message.
+/// Any other message (including a compressed one) is rejected, so messages can't be nested
+fn deserialize_compressed<D, P, S, L>(reader: messages_capnp::compressed_payload::Reader) -> Result<Message<D, P, S, L>>
+    where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+          S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static {
+    let digest = Digest::from_bytes(reader.get_digest().wrapped(ErrorKind::CommunicationSerialize)?)?;
+
+    let compressed = CompressedPayload::from_parts(CompressionAlgorithm::from_tag(reader.get_algorithm())?,
+                                                   reader.get_uncompressed_length(), digest,
+                                                   reader.get_data().wrapped(ErrorKind::CommunicationSerialize)?.to_vec());
+
+    let decompressed = compressed.decompress()?;
+
+    // The messages carried here are the large ones (checkpoints and decision logs), so the default
+    // traversal limit would reject them. A well formed message is traversed once, so it never reads
+    // more words than it is made of
+    let mut options = ::capnp::message::ReaderOptions::new();
+
+    options.traversal_limit_in_words(Some(((compressed.uncompressed_length() + 7) / 8) as usize));
+
+    let message = ::capnp::serialize::read_message(&mut &decompressed[..], options)
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to decode the decompressed message")?;
+
+    let system: messages_capnp::system::Reader = message.get_root().wrapped(ErrorKind::CommunicationSerialize)?;
+
+    match system.which().wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to read which type of message for the system message")? {
+        messages_capnp::system::WhichReader::StateTransfer(_) | messages_capnp::system::WhichReader::LogTransfer(_) => {
+            deserialize_message::<D, P, S, L>(system)
+        }
+        _ => Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Only state transfer and log transfer messages can be compressed"))
+    }
+}
+
+fn serialize_authenticator(mut builder: ::capnp::struct_list::Builder<messages_capnp::authenticator_mac::Owned>, authenticator: &Authenticator) {
+    for (i, (node, mac)) in authenticator.macs().iter().enumerate() {
+        let mut mac_builder = builder.reborrow().get(i as u32);
+
+        let node: u64 = (*node).into();
+
+        mac_builder.set_node(node as u32);
+        mac_builder.set_mac(mac.as_bytes());
+    }
+}
+
+fn deserialize_authenticator(seq: u64, reader: ::capnp::struct_list::Reader<messages_capnp::authenticator_mac::Owned>) -> Result<Authenticator> {
+    let mut macs = Vec::with_capacity(reader.len() as usize);
+
+    for mac_reader in reader.iter() {
+        let mac: [u8; MAC_LENGTH] = mac_reader.get_mac().wrapped(ErrorKind::CommunicationSerialize)?
+            .try_into()
+            .map_err(|_| Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Invalid MAC length"))?;
+
+        macs.push((NodeId::from(mac_reader.get_node()), Mac::from_bytes(mac)));
+    }
+
+    Ok(Authenticator::from_macs(seq, macs))
+}
+
+fn serialize_compressed(mut builder: messages_capnp::compressed_payload::Builder, compressed: &CompressedPayload) {
+    builder.set_algorithm(compressed.algorithm().tag());
+    builder.set_uncompressed_length(compressed.uncompressed_length());
+    builder.set_digest(compressed.digest().as_ref());
+    builder.set_data(compressed.bytes());
+}
+
+pub fn serialize_request<D>(mut builder: messages_capnp::request::Builder, msg: &RequestMessage<D::Request>) -> Result<()> where D: ApplicationData {
     builder.set_operation_id(u32::from(msg.sequence_number()));
     builder.set_session_id(u32::from(msg.session_id()));
 
+    // Requests we received from the network are re-sent (e.g. when forwarded) with their original bytes
+    if let Some(operation) = msg.serialized_operation() {
+        builder.set_request(&operation[..]);
+
+        return Ok(());
+    }
+
     let mut rq_data = Vec::with_capacity(DEFAULT_SERIALIZE_BUFFER_SIZE);
 
-    D::serialize_request(&mut rq_data, msg.operation())?;
+    D::serialize_request(&mut rq_data, msg.try_operation()?)?;
 
     builder.set_request(&rq_data[..]);
 
     Ok(())
 }
 
-pub fn deserialize_request<D>(reader: service_messages_capnp::request::Reader) -> Result<RequestMessage<D::Request>>
+pub fn deserialize_request<D>(reader: messages_capnp::request::Reader) -> Result<RequestMessage<D::Request>>
     where D: ApplicationData {
     let seq_no = SeqNo::from(reader.get_operation_id());
     let session = SeqNo::from(reader.get_session_id());
 
-    let operation = D::deserialize_request(reader.get_request().wrapped(ErrorKind::CommunicationSerialize)?)?;
+    // Only copy the operation out of the message buffer, it is deserialized when it is first needed
+    let operation: Arc<[u8]> = Arc::from(reader.get_request().wrapped(ErrorKind::CommunicationSerialize)?);
 
-    Ok(RequestMessage::new(session, seq_no, operation))
+    Ok(RequestMessage::from_serialized(session, seq_no, operation, decode_request::<D>))
 }
 
-pub fn serialize_reply<D>(mut builder: service_messages_capnp::reply::Builder, msg: &ReplyMessage<D::Reply>) -> Result<()> where D: ApplicationData {
+pub fn serialize_reply<D>(mut builder: messages_capnp::reply::Builder, msg: &ReplyMessage<D::Reply>) -> Result<()> where D: ApplicationData {
     builder.set_operation_id(u32::from(msg.sequence_number()));
     builder.set_session_id(u32::from(msg.session_id()));
 
@@ -180,25 +387,64 @@ pub fn serialize_reply<D>(mut builder: service_messages_capnp::reply::Builder, m
 
     builder.set_reply(&rq_data[..]);
 
+    match msg.executed_seq() {
+        Some(executed_seq) => builder.init_executed_seq().set_seq(u32::from(executed_seq)),
+        None => builder.init_executed_seq().set_none(()),
+    }
+
     Ok(())
 }
 
-pub fn deserialize_reply<D>(reader: service_messages_capnp::reply::Reader) -> Result<ReplyMessage<D::Reply>>
+pub fn deserialize_reply<D>(reader: messages_capnp::reply::Reader) -> Result<ReplyMessage<D::Reply>>
     where D: ApplicationData {
     let seq_no = SeqNo::from(reader.get_operation_id());
     let session = SeqNo::from(reader.get_session_id());
 
     let operation = D::deserialize_reply(reader.get_reply().wrapped(ErrorKind::CommunicationSerialize)?)?;
 
-    Ok(ReplyMessage::new(session, seq_no, operation))
+    let executed_seq = reader.get_executed_seq().which()
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to read the executed sequence number of the reply")?;
+
+    Ok(match executed_seq {
+        messages_capnp::reply::executed_seq::WhichReader::None(()) => ReplyMessage::new(session, seq_no, operation),
+        messages_capnp::reply::executed_seq::WhichReader::Seq(executed_seq) => {
+            ReplyMessage::new_unordered(session, seq_no, SeqNo::from(executed_seq), operation)
+        }
+    })
 }
 
-pub fn serialize_fwd_protocol_message<P>(mut builder: messages_capnp::forwarded_protocol::Builder, msg: &ForwardedProtocolMessage<P::ProtocolMessage>) -> Result<()> where P: OrderingProtocolMessage {
+pub fn serialize_rejection(mut builder: messages_capnp::request_rejected::Builder, msg: &RequestRejectedMessage) {
+    builder.set_operation_id(u32::from(msg.sequence_number()));
+    builder.set_session_id(u32::from(msg.session_id()));
+
+    builder.set_reason(match msg.reason() {
+        RejectionReason::Overloaded => messages_capnp::RejectionReason::Overloaded,
+        RejectionReason::Shed => messages_capnp::RejectionReason::Shed,
+        RejectionReason::Invalid => messages_capnp::RejectionReason::Invalid,
+    });
+}
+
+pub fn deserialize_rejection(reader: messages_capnp::request_rejected::Reader) -> Result<RequestRejectedMessage> {
+    let seq_no = SeqNo::from(reader.get_operation_id());
+    let session = SeqNo::from(reader.get_session_id());
+
+    let reason = match reader.get_reason().wrapped_msg(ErrorKind::CommunicationSerialize, "Unknown rejection reason")? {
+        messages_capnp::RejectionReason::Overloaded => RejectionReason::Overloaded,
+        messages_capnp::RejectionReason::Shed => RejectionReason::Shed,
+        messages_capnp::RejectionReason::Invalid => RejectionReason::Invalid,
+    };
+
+    Ok(RequestRejectedMessage::new(session, seq_no, reason))
+}
+
+pub fn serialize_fwd_protocol_message<D, P>(mut builder: messages_capnp::forwarded_protocol::Builder, msg: &ForwardedProtocolMessage<P::ProtocolMessage>) -> Result<()>
+    where D: ApplicationData, P: OrderingProtocolMessage<D> {
     let mut header = [0; Header::LENGTH];
 
     msg.header().serialize_into(&mut header[..])?;
 
     builder.set_header(&header);
+    builder.set_wire_version(msg.message().message().wire_version().version());
 
     let protocol_builder = builder.init_message();
 
@@ -207,10 +453,27 @@ pub fn serialize_fwd_protocol_message<P>(mut builder: messages_capnp::forwarded_
     Ok(())
 }
 
-pub fn deserialize_fwd_protocol_message<P>(reader: messages_capnp::forwarded_protocol::Reader) -> Result<ForwardedProtocolMessage<P::ProtocolMessage>> where P: OrderingProtocolMessage {
+/// Decode a payload with the decoder of the wire version it was encoded with.
+/// The version is read before the payload, so the payload is only decoded once
+fn deserialize_versioned<'a, T>(version: WireVersion, reader: capnp::any_pointer::Reader<'a>,
+                                current: fn(capnp::any_pointer::Reader<'a>) -> Result<T>,
+                                previous: fn(WireVersion, capnp::any_pointer::Reader<'a>) -> Result<T>) -> Result<T> {
+    if version == CURRENT_WIRE_VERSION {
+        current(reader)
+    } else if version.is_supported() {
+        previous(version, reader)
+    } else {
+        Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Received a message with an unsupported wire version"))
+    }
+}
+
+pub fn deserialize_fwd_protocol_message<D, P>(reader: messages_capnp::forwarded_protocol::Reader) -> Result<ForwardedProtocolMessage<P::ProtocolMessage>>
+    where D: ApplicationData, P: OrderingProtocolMessage<D> {
     let header = Header::deserialize_from(reader.get_header().wrapped(ErrorKind::CommunicationSerialize)?)?;
 
-    let protocol_msg = P::deserialize_capnp(reader.get_message().wrapped(ErrorKind::CommunicationSerialize)?)?;
+    let wire_version = WireVersion::new(reader.get_wire_version());
+
+    let protocol_msg = deserialize_versioned(wire_version, reader.get_message(), P::deserialize_capnp, P::deserialize_previous_capnp)?;
 
-    Ok(ForwardedProtocolMessage::new(StoredMessage::new(header, Protocol::new(protocol_msg))))
+    Ok(ForwardedProtocolMessage::new(StoredMessage::new(header, Protocol::with_version(protocol_msg, wire_version))))
 }
@@ -0,0 +1,482 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "serialize_serde")]
+use std::fmt::Formatter;
+#[cfg(any(feature = "compression_lz4", feature = "compression_zstd"))]
+use std::io::Read;
+#[cfg(feature = "compression_lz4")]
+use std::io::Write;
+#[cfg(feature = "serialize_serde")]
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serialize_serde")]
+use serde::de::{Error as DeserializeError, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+use atlas_common::crypto::hash::{Context, Digest};
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_metrics::metrics::metric_increment;
+
+#[cfg(feature = "serialize_serde")]
+use crate::messages::authenticator::Authenticator;
+use crate::metric::{TRANSFER_BYTES_SAVED_ID, TRANSFER_COMPRESSED_ID};
+#[cfg(feature = "serialize_serde")]
+use crate::serialize::versioning::{VersionedDeserialize, VersionedPayload, WireVersion};
+
+// Compressed messages are framed by the serialization backend on their own, which the plain serde backend can't do
+#[cfg(all(any(feature = "compression_lz4", feature = "compression_zstd"),
+          not(any(feature = "serialize_capnp", feature = "serialize_postcard"))))]
+compile_error!("The compression features require the serialize_capnp or the serialize_postcard feature");
+
+/// Payloads smaller than this are not worth compressing
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+/// The largest message we accept to decompress, unless configured otherwise (see [init_decompression_limits])
+pub const DEFAULT_MAX_UNCOMPRESSED_LENGTH: u64 = 256 * 1024 * 1024;
+
+/// The largest ratio between the uncompressed and the compressed length of a message we accept to decompress,
+/// unless configured otherwise (see [init_decompression_limits])
+pub const DEFAULT_MAX_COMPRESSION_RATIO: u64 = 64;
+
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+static DECOMPRESSION_LIMITS: OnceLock<DecompressionLimits> = OnceLock::new();
+
+/// The algorithms which can be used to compress the payload of state transfer and log transfer messages
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CompressionAlgorithm {
+    Lz4,
+    Zstd,
+}
+
+/// A set of compression algorithms, advertised in every state transfer and log transfer message
+/// so the peers know which algorithms they can use when replying to us
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CompressionSet(u8);
+
+/// The compression to apply to a given message.
+/// The message is only compressed if its encoding is at least `threshold` bytes long
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionRequest {
+    algorithm: CompressionAlgorithm,
+    threshold: usize,
+    level: i32,
+}
+
+/// How we compress the state transfer and log transfer messages we send
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    /// The algorithms we are willing to use, in order of preference
+    pub preference: Vec<CompressionAlgorithm>,
+    /// The minimum length of an encoded payload for it to be compressed
+    pub threshold: usize,
+    /// The compression level, when using zstd
+    pub zstd_level: i32,
+}
+
+/// How much we are willing to decompress, so a peer can't make us allocate an arbitrary amount of memory.
+///
+/// Compressed messages are decompressed when they are decoded, before their signature is verified,
+/// so these bound the work any peer can make us do with a single message
+#[derive(Clone, Copy, Debug)]
+pub struct DecompressionLimits {
+    /// The largest uncompressed length of a message
+    pub max_uncompressed_length: u64,
+    /// The largest ratio between the uncompressed and the compressed length of a message
+    pub max_ratio: u64,
+}
+
+/// The compressed encoding of a state transfer or log transfer message.
+///
+/// The header of a compressed message is signed over the digest of the compressed bytes which are sent
+/// (see [StateTransferSendNode::serialize_digest_compressed_message](crate::state_transfer::networking::StateTransferSendNode::serialize_digest_compressed_message)).
+/// The digest of the uncompressed encoding is also carried here, so the decompressed bytes are checked against it before being decoded
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct CompressedPayload {
+    algorithm: CompressionAlgorithm,
+    uncompressed_length: u64,
+    digest: Digest,
+    bytes: Vec<u8>,
+}
+
+/// The compression algorithms accepted by each of the peers, as advertised in the messages they send us
+pub struct PeerCompressionTable {
+    config: CompressionConfig,
+    peers: BTreeMap<NodeId, CompressionSet>,
+}
+
+impl CompressionAlgorithm {
+    pub(crate) fn tag(&self) -> u8 {
+        match self {
+            CompressionAlgorithm::Lz4 => 0,
+            CompressionAlgorithm::Zstd => 1,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(CompressionAlgorithm::Lz4),
+            1 => Ok(CompressionAlgorithm::Zstd),
+            _ => Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Unknown compression algorithm"))
+        }
+    }
+
+    /// Was support for this algorithm compiled in
+    pub fn is_available(&self) -> bool {
+        match self {
+            CompressionAlgorithm::Lz4 => cfg!(feature = "compression_lz4"),
+            CompressionAlgorithm::Zstd => cfg!(feature = "compression_zstd"),
+        }
+    }
+
+    fn compress(&self, bytes: &[u8], level: i32) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression_lz4")]
+            CompressionAlgorithm::Lz4 => {
+                let _ = level;
+
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::with_capacity(bytes.len()));
+
+                encoder.write_all(bytes)
+                    .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to compress the payload")?;
+
+                encoder.finish()
+                    .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to compress the payload")
+            }
+            #[cfg(feature = "compression_zstd")]
+            CompressionAlgorithm::Zstd => {
+                zstd::bulk::compress(bytes, level)
+                    .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to compress the payload")
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = (bytes, level);
+
+                Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Compression algorithm is not available"))
+            }
+        }
+    }
+
+    /// Decompress the given bytes, as a stream, reading at most one byte past the expected length
+    /// (so a payload which decompresses into more than it declared is detected without decompressing it entirely)
+    fn decompress(&self, bytes: &[u8], uncompressed_length: u64) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression_lz4")]
+            CompressionAlgorithm::Lz4 => {
+                read_bounded(lz4_flex::frame::FrameDecoder::new(bytes), uncompressed_length)
+            }
+            #[cfg(feature = "compression_zstd")]
+            CompressionAlgorithm::Zstd => {
+                let decoder = zstd::stream::read::Decoder::new(bytes)
+                    .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to decompress the payload")?;
+
+                read_bounded(decoder, uncompressed_length)
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = (bytes, uncompressed_length);
+
+                Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Compression algorithm is not available"))
+            }
+        }
+    }
+}
+
+/// Read a decompressing stream, stopping one byte past the expected length
+#[cfg(any(feature = "compression_lz4", feature = "compression_zstd"))]
+fn read_bounded<R>(decoder: R, uncompressed_length: u64) -> Result<Vec<u8>> where R: Read {
+    let mut decompressed = Vec::with_capacity(uncompressed_length as usize);
+
+    decoder.take(uncompressed_length + 1)
+        .read_to_end(&mut decompressed)
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to decompress the payload")?;
+
+    Ok(decompressed)
+}
+
+impl CompressionSet {
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The algorithms we are able to decompress.
+    /// Compressed messages need a serialization backend which can frame them on its own
+    /// (capnp or postcard), so the compression features can't be enabled without one
+    pub fn local() -> Self {
+        let mut set = Self::empty();
+
+        for algorithm in [CompressionAlgorithm::Lz4, CompressionAlgorithm::Zstd] {
+            if algorithm.is_available() {
+                set.insert(algorithm);
+            }
+        }
+
+        set
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub(crate) fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn insert(&mut self, algorithm: CompressionAlgorithm) {
+        self.0 |= 1 << algorithm.tag();
+    }
+
+    pub fn contains(&self, algorithm: CompressionAlgorithm) -> bool {
+        self.0 & (1 << algorithm.tag()) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl CompressionRequest {
+    pub fn new(algorithm: CompressionAlgorithm, threshold: usize) -> Self {
+        Self { algorithm, threshold, level: DEFAULT_ZSTD_LEVEL }
+    }
+
+    pub fn with_level(self, level: i32) -> Self {
+        Self { level, ..self }
+    }
+
+    pub fn algorithm(&self) -> CompressionAlgorithm {
+        self.algorithm
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Compress the encoding of a payload, if it is large enough and compressing it actually makes it smaller
+    pub fn compress(&self, encoded: &[u8]) -> Result<Option<CompressedPayload>> {
+        if encoded.len() < self.threshold {
+            return Ok(None);
+        }
+
+        let bytes = self.algorithm.compress(encoded, self.level)?;
+
+        if bytes.len() >= encoded.len() {
+            return Ok(None);
+        }
+
+        metric_increment(TRANSFER_COMPRESSED_ID, Some(1));
+        metric_increment(TRANSFER_BYTES_SAVED_ID, Some((encoded.len() - bytes.len()) as u64));
+
+        Ok(Some(CompressedPayload {
+            algorithm: self.algorithm,
+            uncompressed_length: encoded.len() as u64,
+            digest: digest_of(encoded),
+            bytes,
+        }))
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            preference: vec![CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4],
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+}
+
+impl DecompressionLimits {
+    /// The largest uncompressed length we accept for a message compressed into the given amount of bytes
+    pub fn max_uncompressed_length_for(&self, compressed_length: usize) -> u64 {
+        self.max_uncompressed_length.min((compressed_length as u64).saturating_mul(self.max_ratio))
+    }
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        Self {
+            max_uncompressed_length: DEFAULT_MAX_UNCOMPRESSED_LENGTH,
+            max_ratio: DEFAULT_MAX_COMPRESSION_RATIO,
+        }
+    }
+}
+
+/// Configure how much we are willing to decompress.
+/// Should be called once, before any message is received. Otherwise the default limits are used
+pub fn init_decompression_limits(limits: DecompressionLimits) -> Result<()> {
+    DECOMPRESSION_LIMITS.set(limits)
+        .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Decompression limits have already been initialized"))
+}
+
+fn decompression_limits() -> &'static DecompressionLimits {
+    DECOMPRESSION_LIMITS.get_or_init(DecompressionLimits::default)
+}
+
+impl CompressedPayload {
+    pub(crate) fn from_parts(algorithm: CompressionAlgorithm, uncompressed_length: u64, digest: Digest, bytes: Vec<u8>) -> Self {
+        Self { algorithm, uncompressed_length, digest, bytes }
+    }
+
+    pub fn algorithm(&self) -> CompressionAlgorithm {
+        self.algorithm
+    }
+
+    pub fn uncompressed_length(&self) -> u64 {
+        self.uncompressed_length
+    }
+
+    /// The digest of the uncompressed encoding of the payload
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decompress the payload, checking it against the digest of the uncompressed encoding.
+    /// Payloads which declare (or decompress into) more than our [DecompressionLimits] allow are rejected
+    pub fn decompress(&self) -> Result<Vec<u8>> {
+        if !CompressionSet::local().contains(self.algorithm) {
+            return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Received a payload compressed with an algorithm we did not accept"));
+        }
+
+        if self.uncompressed_length > decompression_limits().max_uncompressed_length_for(self.bytes.len()) {
+            return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Compressed payload exceeds the decompression limits"));
+        }
+
+        let decompressed = self.algorithm.decompress(&self.bytes, self.uncompressed_length)?;
+
+        if decompressed.len() as u64 != self.uncompressed_length || digest_of(&decompressed) != self.digest {
+            return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Decompressed payload does not match its digest"));
+        }
+
+        Ok(decompressed)
+    }
+}
+
+pub(crate) fn digest_of(bytes: &[u8]) -> Digest {
+    let mut context = Context::new();
+
+    context.update(bytes);
+
+    context.finish()
+}
+
+impl PeerCompressionTable {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            peers: Default::default(),
+        }
+    }
+
+    /// Record the algorithms accepted by a peer, as advertised in a message we received from it
+    /// (see [StateTransfer::accepted_compression](crate::messages::StateTransfer::accepted_compression))
+    pub fn record(&mut self, peer: NodeId, accepted: CompressionSet) {
+        self.peers.insert(peer, accepted);
+    }
+
+    /// The compression to use for the messages sent to the given peer.
+    /// This is the first algorithm of our preference which we and the peer both support,
+    /// or none if we have not yet heard from the peer
+    pub fn compression_for(&self, peer: NodeId) -> Option<CompressionRequest> {
+        let accepted = self.peers.get(&peer)?;
+
+        self.config.preference.iter()
+            .find(|algorithm| algorithm.is_available() && accepted.contains(**algorithm))
+            .map(|algorithm| CompressionRequest::new(*algorithm, self.config.threshold).with_level(self.config.zstd_level))
+    }
+
+    pub fn forget_peer(&mut self, peer: NodeId) {
+        self.peers.remove(&peer);
+    }
+}
+
+/// The serde representation of the state transfer and log transfer envelopes
+#[cfg(feature = "serialize_serde")]
+#[derive(Serialize)]
+#[serde(rename = "Transfer")]
+struct TransferRef<'a, P> {
+    version: WireVersion,
+    accepts: CompressionSet,
+    authenticator: Option<&'a Authenticator>,
+    payload: &'a P,
+}
+
+#[cfg(feature = "serialize_serde")]
+pub(crate) fn serialize_transfer<S, P>(serializer: S, version: WireVersion, accepts: CompressionSet,
+                                       authenticator: Option<&Authenticator>, payload: &P) -> std::result::Result<S::Ok, S::Error>
+    where S: Serializer, P: Serialize {
+    TransferRef { version, accepts, authenticator, payload }.serialize(serializer)
+}
+
+/// Deserialize a state transfer or log transfer envelope.
+/// The wire version is decoded before the payload, which is then decoded with the decoder of that version
+#[cfg(feature = "serialize_serde")]
+pub(crate) fn deserialize_transfer<'de, DE, P>(deserializer: DE) -> std::result::Result<(WireVersion, CompressionSet, Option<Authenticator>, P), DE::Error>
+    where DE: Deserializer<'de>, P: VersionedDeserialize {
+    deserializer.deserialize_struct("Transfer", &["version", "accepts", "authenticator", "payload"], TransferVisitor(PhantomData))
+}
+
+#[cfg(feature = "serialize_serde")]
+struct TransferVisitor<P>(PhantomData<fn() -> P>);
+
+#[cfg(feature = "serialize_serde")]
+impl<'de, P> Visitor<'de> for TransferVisitor<P> where P: VersionedDeserialize {
+    type Value = (WireVersion, CompressionSet, Option<Authenticator>, P);
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "a state transfer or log transfer message")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+        let version: WireVersion = seq.next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+
+        let accepts: CompressionSet = seq.next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+
+        let authenticator: Option<Authenticator> = seq.next_element()?
+            .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+
+        let payload = seq.next_element_seed(VersionedPayload::<P>::new(version))?
+            .ok_or_else(|| A::Error::invalid_length(3, &self))?;
+
+        Ok((version, accepts, authenticator, payload))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error> where A: MapAccess<'de> {
+        let mut version = None;
+        let mut accepts = None;
+        let mut authenticator = None;
+        let mut payload = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => version = Some(map.next_value::<WireVersion>()?),
+                "accepts" => accepts = Some(map.next_value::<CompressionSet>()?),
+                "authenticator" => authenticator = Some(map.next_value::<Option<Authenticator>>()?),
+                "payload" => {
+                    // The payload can only be decoded once we know which version it was encoded with
+                    let version = version.ok_or_else(|| A::Error::custom("The wire version must precede the payload"))?;
+
+                    payload = Some(map.next_value_seed(VersionedPayload::<P>::new(version))?);
+                }
+                _ => { map.next_value::<IgnoredAny>()?; }
+            }
+        }
+
+        let version = version.ok_or_else(|| A::Error::missing_field("version"))?;
+        let accepts = accepts.ok_or_else(|| A::Error::missing_field("accepts"))?;
+        let payload = payload.ok_or_else(|| A::Error::missing_field("payload"))?;
+
+        Ok((version, accepts, authenticator.flatten(), payload))
+    }
+}
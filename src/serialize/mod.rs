@@ -6,9 +6,11 @@ use log::info;
 #[cfg(feature = "serialize_serde")]
 use serde::{Deserialize, Serialize};
 
+use atlas_common::crypto::hash::Digest;
+use atlas_common::error::{Error, ErrorKind};
 use atlas_common::node_id::NodeId;
 use atlas_common::ordering::{Orderable, SeqNo};
-use atlas_communication::message::{Header};
+use atlas_communication::message::{Header, StoredMessage};
 use atlas_communication::message_signing::NetworkMessageSignatureVerifier;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
 use atlas_communication::serialize::{Buf, Serializable};
@@ -16,8 +18,12 @@ use atlas_execution::serialize::ApplicationData;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
 use crate::log_transfer::networking::signature_ver::LogTransferVerificationHelper;
 
-use crate::messages::{RequestMessage, SystemMessage};
-use crate::messages::signature_ver::SigVerifier;
+use crate::messages::{ForwardedProtocolMessage, ForwardedRequestsMessage, RequestMessage, StoredRequestMessage, SystemMessage};
+use crate::messages::signature_ver::parallel_requests::parallel_request_verifier;
+use crate::messages::authenticator::{authentication_digest, authenticator_for, MessageClass};
+use crate::messages::signature_ver::{SigVerifier, Verified};
+use crate::serialize::compression::CompressionRequest;
+use crate::serialize::versioning::WireVersion;
 use crate::ordering_protocol::networking::serialize::{NetworkView, OrderingProtocolMessage, OrderProtocolProof};
 use crate::ordering_protocol::networking::signature_ver::OrderProtocolSignatureVerificationHelper;
 use crate::smr::networking::NodeWrap;
@@ -26,6 +32,11 @@ use crate::state_transfer::networking::signature_ver::StateTransferVerificationH
 
 #[cfg(feature = "serialize_capnp")]
 pub mod capnp;
+pub mod compression;
+#[cfg(feature = "serialize_postcard")]
+pub mod postcard;
+pub mod verification;
+pub mod versioning;
 
 /// Reconfiguration protocol messages
 pub trait ReconfigurationProtocolMessage: Serializable + Send + Sync {
@@ -57,70 +68,261 @@ impl<D, P, S, L> Serializable for Service<D, P, S, L> where
     type Message = SystemMessage<D, P::ProtocolMessage, S::StateTransferMessage, L::LogTransferMessage>;
 
     fn verify_message_internal<NI, SV>(info_provider: &Arc<NI>, header: &Header, msg: &Self::Message) -> atlas_common::error::Result<bool>
+        where NI: NetworkInformationProvider + 'static,
+              SV: NetworkMessageSignatureVerifier<Self, NI> {
+        Self::check_system_message::<NI, SV>(info_provider, header, msg)
+    }
+
+    #[cfg(feature = "serialize_capnp")]
+    fn serialize_capnp(builder: self::capnp::messages_capnp::system::Builder, msg: &Self::Message) -> atlas_common::error::Result<()> {
+        capnp::serialize_message::<D, P, S, L>(builder, msg)
+    }
+
+    #[cfg(feature = "serialize_capnp")]
+    fn deserialize_capnp(reader: self::capnp::messages_capnp::system::Reader) -> atlas_common::error::Result<Self::Message> {
+        capnp::deserialize_message::<D, P, S, L>(reader)
+    }
+}
+
+impl<D, P, S, L> Service<D, P, S, L> where
+    D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static, S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static {
+    /// Verify a message, producing the verified message (as returned by the verification of each of the protocols),
+    /// or None if the message is not valid.
+    pub fn verify_system_message<NI, SV>(info_provider: &Arc<NI>, header: &Header, msg: ServiceMessage<D, P, S, L>)
+                                         -> atlas_common::error::Result<Option<Verified<ServiceMessage<D, P, S, L>>>>
         where NI: NetworkInformationProvider + 'static,
               SV: NetworkMessageSignatureVerifier<Self, NI> {
         match msg {
-            SystemMessage::ProtocolMessage(protocol) => {
-                let (result, message) = P::verify_order_protocol_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, header, protocol.payload().clone())?;
+            SystemMessage::ForwardedRequestMessage(fwd_requests) => {
+                // Each of the requests of a forwarded message is verified on its own, spread over the
+                // verification threads, and a single forged request invalidates the entire message
+                let (result, requests) = parallel_request_verifier().verify_each(info_provider, fwd_requests.into_inner(),
+                                                                                 verify_forwarded_request::<NI, SV, D, P, S, L>)?;
 
-                Ok(result)
+                Ok(verified(result, SystemMessage::ForwardedRequestMessage(ForwardedRequestsMessage::new(requests))))
             }
-            SystemMessage::LogTransferMessage(log_transfer) => {
-                let (result, message) = L::verify_log_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, header, log_transfer.payload().clone())?;
+            msg => {
+                if !Self::check_system_message::<NI, SV>(info_provider, header, &msg)? {
+                    return Ok(None);
+                }
 
-                Ok(result)
+                Self::trust_verified_message(msg).map(Some)
             }
-            SystemMessage::StateTransferMessage(state_transfer) => {
-                let (result, message) = S::verify_state_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, header, state_transfer.payload().clone())?;
+        }
+    }
 
-                Ok(result)
+    /// Check the signatures (or authenticators) of a message, including the ones of the messages it carries.
+    /// The requests of a forwarded request message are checked one by one, on the calling thread
+    fn check_system_message<NI, SV>(info_provider: &Arc<NI>, header: &Header, msg: &ServiceMessage<D, P, S, L>) -> atlas_common::error::Result<bool>
+        where NI: NetworkInformationProvider + 'static,
+              SV: NetworkMessageSignatureVerifier<Self, NI> {
+        match msg {
+            SystemMessage::ProtocolMessage(protocol) => {
+                let version = check_wire_version(protocol.wire_version())?;
+
+                match protocol.authenticator() {
+                    // Only the classes of messages which our policy authenticates with MACs are accepted
+                    // with an authenticator, so a peer can't downgrade the ones which must be signed
+                    Some(authenticator) => match authenticator_for(MessageClass::OrderProtocol) {
+                        Some(authentication) => {
+                            let digest = Self::protocol_authentication_digest(header.from(), version, protocol.payload())?;
+
+                            <SigVerifier<SV, NI, D, P, S, L> as OrderProtocolSignatureVerificationHelper<D, P, NI>>::verify_authenticator(authentication.keys(), header, &digest, authenticator)
+                        }
+                        None => Ok(false),
+                    },
+                    None => P::verify_order_protocol_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, header, protocol.payload()),
+                }
             }
-            SystemMessage::OrderedRequest(request) => {
-                Ok(true)
+            SystemMessage::LogTransferMessage(log_transfer) => {
+                let version = check_wire_version(log_transfer.wire_version())?;
+
+                match log_transfer.authenticator() {
+                    Some(authenticator) => match authenticator_for(MessageClass::LogTransfer) {
+                        Some(authentication) => {
+                            let digest = Self::log_authentication_digest(header.from(), version, log_transfer.payload())?;
+
+                            <SigVerifier<SV, NI, D, P, S, L> as LogTransferVerificationHelper<D, P, NI>>::verify_log_authenticator(authentication.keys(), header, &digest, authenticator)
+                        }
+                        None => Ok(false),
+                    },
+                    None => L::verify_log_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, header, log_transfer.payload()),
+                }
             }
-            SystemMessage::OrderedReply(reply) => {
-                Ok(true)
+            SystemMessage::StateTransferMessage(state_transfer) => {
+                let version = check_wire_version(state_transfer.wire_version())?;
+
+                match state_transfer.authenticator() {
+                    Some(authenticator) => match authenticator_for(MessageClass::StateTransfer) {
+                        Some(authentication) => {
+                            let digest = Self::state_authentication_digest(header.from(), version, state_transfer.payload())?;
+
+                            <SigVerifier<SV, NI, D, P, S, L> as StateTransferVerificationHelper>::verify_authenticator(authentication.keys(), header, &digest, authenticator)
+                        }
+                        None => Ok(false),
+                    },
+                    None => S::verify_state_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, header, state_transfer.payload()),
+                }
             }
-            SystemMessage::UnorderedReply(reply) => {
-                Ok(true)
+            SystemMessage::OrderedRequest(request) => {
+                SigVerifier::<SV, NI, D, P, S, L>::verify_request_message(info_provider, header, request)
             }
             SystemMessage::UnorderedRequest(request) => {
-                Ok(true)
+                // As with the ordered requests, the verifier is handed a handle to the shared payload,
+                // and malformed operations are rejected here instead of at execution
+                let (result, _) = SV::verify_signature(info_provider, header, msg.clone())?;
+
+                Ok(result && request.try_operation().is_ok())
+            }
+            SystemMessage::OrderedReply(reply) => {
+                SigVerifier::<SV, NI, D, P, S, L>::verify_reply_message(info_provider, header, reply)
+            }
+            SystemMessage::UnorderedReply(_) | SystemMessage::RequestRejected(_) | SystemMessage::WireHandshake(_) => {
+                // The network signature verifier consumes the message it checks
+                let (result, _) = SV::verify_signature(info_provider, header, msg.clone())?;
+
+                Ok(result)
             }
             SystemMessage::ForwardedProtocolMessage(fwd_protocol) => {
-                let header = fwd_protocol.header();
                 let message = fwd_protocol.message();
 
-                let (result, message) = P::verify_order_protocol_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, message.header(), message.message().payload().clone())?;
+                // Forwarded messages keep the version they were signed with, since they might be
+                // forwarded again (or used as proofs)
+                check_wire_version(message.message().wire_version())?;
 
-                Ok(result)
+                P::verify_order_protocol_message::<NI, SigVerifier<SV, NI, D, P, S, L>>(info_provider, message.header(), message.message().payload())
             }
             SystemMessage::ForwardedRequestMessage(fwd_requests) => {
-                let mut result = true;
-
-                for stored_rq in fwd_requests.requests().iter() {
-                    let header = stored_rq.header();
-                    let message = stored_rq.message();
-
-                    let message = SystemMessage::OrderedRequest(message.clone());
-
-                    result &= Self::verify_message_internal::<NI, SV>(info_provider, header, &message)?;
+                for request in fwd_requests.requests() {
+                    if !verify_forwarded_request::<NI, SV, D, P, S, L>(info_provider, request)? {
+                        return Ok(false);
+                    }
                 }
 
-                Ok(result)
+                Ok(true)
             }
         }
     }
 
-    #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(builder: febft_capnp::messages_capnp::system::Builder, msg: &Self::Message) -> Result<()> {
-        capnp::serialize_message::<D, P, S, L>(builder, msg)
+    /// Produce the verified form of a message whose identical copy (same header digest, from the same sender)
+    /// has already passed [Self::verify_system_message], without checking its signatures again.
+    /// Messages of older wire versions were already decoded with the decoder of their version,
+    /// so only the version itself is checked
+    pub fn trust_verified_message(msg: ServiceMessage<D, P, S, L>) -> atlas_common::error::Result<Verified<ServiceMessage<D, P, S, L>>> {
+        match &msg {
+            SystemMessage::ProtocolMessage(protocol) => { check_wire_version(protocol.wire_version())?; }
+            SystemMessage::LogTransferMessage(log_transfer) => { check_wire_version(log_transfer.wire_version())?; }
+            SystemMessage::StateTransferMessage(state_transfer) => { check_wire_version(state_transfer.wire_version())?; }
+            _ => {}
+        }
+
+        Ok(Verified::new(msg))
+    }
+
+    /// The digest covered by the [Authenticator] of an order protocol message sent by the given node
+    pub fn protocol_authentication_digest(from: NodeId, version: WireVersion, payload: &P::ProtocolMessage) -> atlas_common::error::Result<Digest> {
+        #[cfg(feature = "serialize_capnp")]
+        let encoded = capnp::encode_payload(|builder| P::serialize_capnp(builder, payload))?;
+
+        #[cfg(feature = "serialize_serde")]
+        let encoded = encode_authenticated_payload(payload)?;
+
+        Ok(authentication_digest(from, version, &encoded))
+    }
+
+    /// The digest covered by the [Authenticator] of a state transfer message sent by the given node
+    pub fn state_authentication_digest(from: NodeId, version: WireVersion, payload: &S::StateTransferMessage) -> atlas_common::error::Result<Digest> {
+        #[cfg(feature = "serialize_capnp")]
+        let encoded = capnp::encode_payload(|builder| S::serialize_capnp(builder, payload))?;
+
+        #[cfg(feature = "serialize_serde")]
+        let encoded = encode_authenticated_payload(payload)?;
+
+        Ok(authentication_digest(from, version, &encoded))
+    }
+
+    /// The digest covered by the [Authenticator] of a log transfer message sent by the given node
+    pub fn log_authentication_digest(from: NodeId, version: WireVersion, payload: &L::LogTransferMessage) -> atlas_common::error::Result<Digest> {
+        #[cfg(feature = "serialize_capnp")]
+        let encoded = capnp::encode_payload(|builder| L::serialize_capnp(builder, payload))?;
+
+        #[cfg(feature = "serialize_serde")]
+        let encoded = encode_authenticated_payload(payload)?;
+
+        Ok(authentication_digest(from, version, &encoded))
     }
+}
+
+/// Can the serialization backend encode the payload of a message on its own, which is needed
+/// to compute the digest covered by an [Authenticator](crate::messages::authenticator::Authenticator).
+/// The serde backend only can with the `serialize_postcard` feature
+pub fn can_encode_payloads() -> bool {
+    cfg!(any(feature = "serialize_capnp", feature = "serialize_postcard"))
+}
+
+/// Encode the payload of a message authenticated with an [Authenticator](crate::messages::authenticator::Authenticator) on its own.
+/// As with compression, this requires the `serialize_postcard` feature with serde, which
+/// [init_authentication](crate::messages::authenticator::init_authentication) checks up front
+#[cfg(feature = "serialize_serde")]
+fn encode_authenticated_payload<T>(payload: &T) -> atlas_common::error::Result<Vec<u8>> where T: Serialize {
+    #[cfg(feature = "serialize_postcard")]
+    return ::postcard::to_stdvec(payload)
+        .map_err(|err| Error::wrapped(ErrorKind::CommunicationSerialize, err));
 
+    #[cfg(not(feature = "serialize_postcard"))]
+    Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Authenticators require the serialize_postcard feature"))
+}
+
+/// Compress the encoding of a state transfer or log transfer message, framing it in a message of its own.
+/// Returns the framed bytes along with their digest, which is the digest the header of the message must be signed over,
+/// since the network layer checks the payload it receives against the digest in the header.
+/// Returns None if the message is not worth compressing
+pub fn compress_message(encoded: &[u8], compression: &CompressionRequest) -> atlas_common::error::Result<Option<(Vec<u8>, Digest)>> {
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(reader: febft_capnp::messages_capnp::system::Reader) -> Result<Self::Message> {
-        capnp::deserialize_message::<D, P, S, L>(reader)
+    let framed = capnp::compress_message(encoded, compression)?;
+
+    #[cfg(feature = "serialize_postcard")]
+    let framed = postcard::compress_message(encoded, compression)?;
+
+    // Without a backend which can frame them, the compression features can't be enabled, so there is nothing to compress with
+    #[cfg(not(any(feature = "serialize_capnp", feature = "serialize_postcard")))]
+    let framed: Option<Vec<u8>> = {
+        let _ = (encoded, compression);
+
+        None
+    };
+
+    Ok(framed.map(|framed| {
+        let digest = compression::digest_of(&framed);
+
+        (framed, digest)
+    }))
+}
+
+/// Check that we are able to decode messages with the given wire version
+fn check_wire_version(version: WireVersion) -> atlas_common::error::Result<WireVersion> {
+    if !version.is_supported() {
+        return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Received a message with an unsupported wire version"));
     }
+
+    Ok(version)
+}
+
+fn verified<M>(valid: bool, message: M) -> Option<Verified<M>> {
+    if valid {
+        Some(Verified::new(message))
+    } else {
+        None
+    }
+}
+
+/// Verify the client signature of a request that was forwarded to us by another replica
+fn verify_forwarded_request<NI, SV, D, P, S, L>(info_provider: &Arc<NI>, request: &StoredRequestMessage<D::Request>) -> atlas_common::error::Result<bool>
+    where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+          S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static,
+          NI: NetworkInformationProvider + 'static,
+          SV: NetworkMessageSignatureVerifier<Service<D, P, S, L>, NI> {
+    SigVerifier::<SV, NI, D, P, S, L>::verify_request_message(info_provider, request.header(), request.message())
 }
 
 #[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
@@ -169,31 +371,31 @@ impl<D> OrderingProtocolMessage<D> for NoProtocol {
 
     type ProofMetadata = ();
 
-    fn verify_order_protocol_message<NI, OPVH>(network_info: &Arc<NI>, header: &Header, message: Self::ProtocolMessage) -> atlas_common::error::Result<(bool, Self::ProtocolMessage)> where NI: NetworkInformationProvider, OPVH: OrderProtocolSignatureVerificationHelper<D, Self, NI>, D: ApplicationData {
-        Ok((false, message))
+    fn verify_order_protocol_message<NI, OPVH>(network_info: &Arc<NI>, header: &Header, message: &Self::ProtocolMessage) -> atlas_common::error::Result<bool> where NI: NetworkInformationProvider, OPVH: OrderProtocolSignatureVerificationHelper<D, Self, NI>, D: ApplicationData {
+        Ok(false)
     }
 
-    fn verify_proof<NI, OPVH>(network_info: &Arc<NI>, proof: Self::Proof) -> atlas_common::error::Result<(bool, Self::Proof)> where NI: NetworkInformationProvider, OPVH: OrderProtocolSignatureVerificationHelper<D, Self, NI>, D: ApplicationData, Self: Sized {
-        Ok((false, proof))
+    fn verify_proof<NI, OPVH>(network_info: &Arc<NI>, proof: &Self::Proof) -> atlas_common::error::Result<bool> where NI: NetworkInformationProvider, OPVH: OrderProtocolSignatureVerificationHelper<D, Self, NI>, D: ApplicationData, Self: Sized {
+        Ok(false)
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(_: febft_capnp::consensus_messages_capnp::protocol_message::Builder, _: &Self::ProtocolMessage) -> Result<()> {
+    fn serialize_capnp(_: ::capnp::any_pointer::Builder, _: &Self::ProtocolMessage) -> atlas_common::error::Result<()> {
         unimplemented!()
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(_: febft_capnp::consensus_messages_capnp::protocol_message::Reader) -> Result<Self::ProtocolMessage> {
+    fn deserialize_capnp(_: ::capnp::any_pointer::Reader) -> atlas_common::error::Result<Self::ProtocolMessage> {
         unimplemented!()
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_view_capnp(_: febft_capnp::cst_messages_capnp::view_info::Builder, msg: &Self::ViewInfo) -> Result<()> {
+    fn serialize_proof_capnp(_: ::capnp::any_pointer::Builder, _: &Self::Proof) -> atlas_common::error::Result<()> {
         unimplemented!()
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_view_capnp(_: febft_capnp::cst_messages_capnp::view_info::Reader) -> Result<Self::ViewInfo> {
+    fn deserialize_proof_capnp(_: ::capnp::any_pointer::Reader) -> atlas_common::error::Result<Self::Proof> {
         unimplemented!()
     }
 }
@@ -201,17 +403,17 @@ impl<D> OrderingProtocolMessage<D> for NoProtocol {
 impl StateTransferMessage for NoProtocol {
     type StateTransferMessage = ();
 
-    fn verify_state_message<NI, SVH>(network_info: &Arc<NI>, header: &Header, message: Self::StateTransferMessage) -> atlas_common::error::Result<(bool, Self::StateTransferMessage)> where NI: NetworkInformationProvider, SVH: StateTransferVerificationHelper {
-        Ok((false, message))
+    fn verify_state_message<NI, SVH>(network_info: &Arc<NI>, header: &Header, message: &Self::StateTransferMessage) -> atlas_common::error::Result<bool> where NI: NetworkInformationProvider, SVH: StateTransferVerificationHelper {
+        Ok(false)
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(_: febft_capnp::cst_messages_capnp::cst_message::Builder, msg: &Self::StateTransferMessage) -> Result<()> {
+    fn serialize_capnp(_: ::capnp::any_pointer::Builder, msg: &Self::StateTransferMessage) -> atlas_common::error::Result<()> {
         unimplemented!()
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(_: febft_capnp::cst_messages_capnp::cst_message::Reader) -> Result<Self::StateTransferMessage> {
+    fn deserialize_capnp(_: ::capnp::any_pointer::Reader) -> atlas_common::error::Result<Self::StateTransferMessage> {
         unimplemented!()
     }
 }
@@ -219,20 +421,20 @@ impl StateTransferMessage for NoProtocol {
 impl<D, P> LogTransferMessage<D, P> for NoProtocol {
     type LogTransferMessage = ();
 
-    fn verify_log_message<NI, LVH>(network_info: &Arc<NI>, header: &Header, message: Self::LogTransferMessage) -> atlas_common::error::Result<(bool, Self::LogTransferMessage)>
+    fn verify_log_message<NI, LVH>(network_info: &Arc<NI>, header: &Header, message: &Self::LogTransferMessage) -> atlas_common::error::Result<bool>
         where NI: NetworkInformationProvider,
               D: ApplicationData, P: OrderingProtocolMessage<D>,
               LVH: LogTransferVerificationHelper<D, P, NI>, {
-        Ok((false, message))
+        Ok(false)
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(_: febft_capnp::cst_messages_capnp::cst_message::Builder, msg: &Self::LogTransferMessage) -> Result<()> {
+    fn serialize_capnp(_: ::capnp::any_pointer::Builder, msg: &Self::LogTransferMessage) -> atlas_common::error::Result<()> {
         unimplemented!()
     }
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(_: febft_capnp::cst_messages_capnp::cst_message::Reader) -> Result<Self::LogTransferMessage> {
+    fn deserialize_capnp(_: ::capnp::any_pointer::Reader) -> atlas_common::error::Result<Self::LogTransferMessage> {
         unimplemented!()
     }
 }
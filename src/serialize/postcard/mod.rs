@@ -0,0 +1,519 @@
+use serde::{Deserialize, Serialize};
+
+use atlas_common::error::*;
+use atlas_execution::serialize::ApplicationData;
+
+use crate::log_transfer::networking::serialize::LogTransferMessage;
+use crate::ordering_protocol::networking::serialize::{OrderingProtocolMessage, StatefulOrderProtocolMessage};
+use crate::messages::SystemMessage;
+use crate::serialize::compression::{CompressedPayload, CompressionRequest};
+use crate::serialize::ServiceMessage;
+use crate::serialize::versioning::{CURRENT_WIRE_VERSION, WireVersion};
+use crate::state_transfer::Checkpoint;
+use crate::state_transfer::networking::serialize::StateTransferMessage;
+
+/// The length of the envelope which precedes every encoding
+pub const ENVELOPE_LENGTH: usize = 3;
+
+const DEFAULT_SERIALIZE_BUFFER_SIZE: usize = 1024;
+
+/// The kind of payload carried by an encoding.
+///
+/// The envelope of every encoding is the kind (1 byte) followed by the wire version
+/// it was encoded with (2 bytes, little endian). The payload follows, encoded with postcard
+/// (variable length integers, enum variants by index and sequences prefixed by their length),
+/// which is what makes it compact and cheap to decode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PayloadKind {
+    SystemMessage,
+    Checkpoint,
+    DecisionLog,
+    /// The compressed encoding of a whole state transfer or log transfer message
+    CompressedMessage,
+}
+
+impl PayloadKind {
+    fn tag(&self) -> u8 {
+        match self {
+            PayloadKind::SystemMessage => 0,
+            PayloadKind::Checkpoint => 1,
+            PayloadKind::DecisionLog => 2,
+            PayloadKind::CompressedMessage => 3,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(PayloadKind::SystemMessage),
+            1 => Ok(PayloadKind::Checkpoint),
+            2 => Ok(PayloadKind::DecisionLog),
+            3 => Ok(PayloadKind::CompressedMessage),
+            _ => Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Unknown payload kind"))
+        }
+    }
+}
+
+/// Encode a system message into the given buffer
+pub fn serialize_message<D, P, S, L>(buf: &mut Vec<u8>, msg: &ServiceMessage<D, P, S, L>) -> Result<()>
+    where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+          S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static {
+    encode(buf, PayloadKind::SystemMessage, msg)
+}
+
+/// Decode a system message, decompressing it first if it was compressed.
+/// Only state transfer and log transfer messages can be compressed, and only once
+pub fn deserialize_message<D, P, S, L>(buf: &[u8]) -> Result<ServiceMessage<D, P, S, L>>
+    where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+          S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static {
+    let (kind, _) = read_envelope(buf)?;
+
+    if kind != PayloadKind::CompressedMessage {
+        return decode(buf, PayloadKind::SystemMessage);
+    }
+
+    let compressed: CompressedPayload = decode(buf, PayloadKind::CompressedMessage)?;
+
+    let message = decode(&compressed.decompress()?, PayloadKind::SystemMessage)?;
+
+    match message {
+        SystemMessage::StateTransferMessage(_) | SystemMessage::LogTransferMessage(_) => Ok(message),
+        _ => Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Only state transfer and log transfer messages can be compressed"))
+    }
+}
+
+/// Compress the encoding of a state transfer or log transfer message.
+/// Returns None if the message is not worth compressing, so it should be sent as it was encoded
+pub(super) fn compress_message(encoded: &[u8], compression: &CompressionRequest) -> Result<Option<Vec<u8>>> {
+    let compressed = match compression.compress(encoded)? {
+        Some(compressed) => compressed,
+        None => return Ok(None),
+    };
+
+    let mut framed = Vec::with_capacity(compressed.bytes().len() + DEFAULT_SERIALIZE_BUFFER_SIZE);
+
+    encode(&mut framed, PayloadKind::CompressedMessage, &compressed)?;
+
+    Ok(Some(framed))
+}
+
+/// Encode a checkpoint into the given buffer
+pub fn serialize_checkpoint<S>(buf: &mut Vec<u8>, checkpoint: &Checkpoint<S>) -> Result<()> where S: Serialize {
+    encode(buf, PayloadKind::Checkpoint, checkpoint)
+}
+
+pub fn deserialize_checkpoint<S>(buf: &[u8]) -> Result<Checkpoint<S>> where S: for<'a> Deserialize<'a> {
+    decode(buf, PayloadKind::Checkpoint)
+}
+
+/// Encode the decision log of a stateful ordering protocol into the given buffer
+pub fn serialize_decision_log<D, OPM, SOPM>(buf: &mut Vec<u8>, dec_log: &SOPM::DecLog) -> Result<()>
+    where D: ApplicationData, OPM: OrderingProtocolMessage<D>, SOPM: StatefulOrderProtocolMessage<D, OPM> {
+    encode(buf, PayloadKind::DecisionLog, dec_log)
+}
+
+pub fn deserialize_decision_log<D, OPM, SOPM>(buf: &[u8]) -> Result<SOPM::DecLog>
+    where D: ApplicationData, OPM: OrderingProtocolMessage<D>, SOPM: StatefulOrderProtocolMessage<D, OPM> {
+    decode(buf, PayloadKind::DecisionLog)
+}
+
+/// Read the envelope of an encoding, returning the kind and the wire version of its payload
+pub fn read_envelope(buf: &[u8]) -> Result<(PayloadKind, WireVersion)> {
+    if buf.len() < ENVELOPE_LENGTH {
+        return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Buffer is too short to contain the envelope"));
+    }
+
+    let kind = PayloadKind::from_tag(buf[0])?;
+    let version = WireVersion::new(u16::from_le_bytes([buf[1], buf[2]]));
+
+    Ok((kind, version))
+}
+
+fn write_envelope(buf: &mut Vec<u8>, kind: PayloadKind, version: WireVersion) {
+    buf.push(kind.tag());
+    buf.extend_from_slice(&version.version().to_le_bytes());
+}
+
+pub(super) fn encode<T>(buf: &mut Vec<u8>, kind: PayloadKind, payload: &T) -> Result<()> where T: Serialize + ?Sized {
+    buf.reserve(DEFAULT_SERIALIZE_BUFFER_SIZE);
+
+    write_envelope(buf, kind, CURRENT_WIRE_VERSION);
+
+    let encoded = ::postcard::to_extend(payload, std::mem::take(buf))
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to encode the payload")?;
+
+    *buf = encoded;
+
+    Ok(())
+}
+
+pub(super) fn decode<T>(buf: &[u8], kind: PayloadKind) -> Result<T> where T: for<'a> Deserialize<'a> {
+    let (found_kind, version) = read_envelope(buf)?;
+
+    if found_kind != kind {
+        return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Unexpected payload kind"));
+    }
+
+    if !version.is_supported() {
+        return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Unsupported wire version"));
+    }
+
+    let (payload, remaining) = ::postcard::take_from_bytes(&buf[ENVELOPE_LENGTH..])
+        .wrapped_msg(ErrorKind::CommunicationSerialize, "Failed to decode the payload")?;
+
+    if !remaining.is_empty() {
+        return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "Trailing bytes after the payload"));
+    }
+
+    Ok(payload)
+}
+
+/// The golden vectors of the postcard backend: fixed values together with the exact bytes they must be encoded into.
+///
+/// Any change to the encoding of the types covered here (or to the envelope) changes the bytes
+/// on the wire and in the persisted checkpoints and logs, so it must come with a bump of the
+/// [wire version](crate::serialize::versioning::CURRENT_WIRE_VERSION) and new vectors.
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+    use std::io::{Read, Write};
+
+    use serde::{Deserialize, Serialize};
+
+    use atlas_common::crypto::hash::Digest;
+    use atlas_common::error::*;
+    use atlas_common::node_id::NodeId;
+    use atlas_common::ordering::SeqNo;
+    use atlas_execution::serialize::ApplicationData;
+
+    use crate::messages::{LogTransfer, Protocol, RejectionReason, ReplyMessage, RequestMessage, RequestRejectedMessage, StateTransfer, SystemMessage};
+    use crate::messages::authenticator::{Authenticator, Mac, MAC_LENGTH};
+    use crate::request_pre_processing::latest_ops::{LatestOpsSnapshot, SessionLatestOp};
+    use crate::serialize::compression::CompressionSet;
+    use crate::serialize::versioning::{VersionedDeserialize, WireCapabilities, WireHandshakeMessage, WireVersion};
+    use crate::smr::exec::reply_cache::ReplyCache;
+    use crate::state_transfer::Checkpoint;
+
+    #[cfg(feature = "compression_lz4")]
+    use crate::serialize::compression::{CompressedPayload, CompressionAlgorithm, CompressionRequest};
+
+    use super::{decode, encode, PayloadKind};
+    #[cfg(feature = "compression_lz4")]
+    use super::compress_message;
+
+    /// An application whose requests and replies are their raw bytes
+    struct TestApp;
+
+    impl ApplicationData for TestApp {
+        type Request = Vec<u8>;
+        type Reply = Vec<u8>;
+
+        fn serialize_request<W>(mut w: W, request: &Self::Request) -> Result<()> where W: Write {
+            w.write_all(request).wrapped(ErrorKind::CommunicationSerialize)
+        }
+
+        fn deserialize_request<R>(r: R) -> Result<Self::Request> where R: Read {
+            read_all(r)
+        }
+
+        fn serialize_reply<W>(mut w: W, reply: &Self::Reply) -> Result<()> where W: Write {
+            w.write_all(reply).wrapped(ErrorKind::CommunicationSerialize)
+        }
+
+        fn deserialize_reply<R>(r: R) -> Result<Self::Reply> where R: Read {
+            read_all(r)
+        }
+    }
+
+    fn read_all<R>(mut r: R) -> Result<Vec<u8>> where R: Read {
+        let mut bytes = Vec::new();
+
+        r.read_to_end(&mut bytes).wrapped(ErrorKind::CommunicationSerialize)?;
+
+        Ok(bytes)
+    }
+
+    /// The protocol payloads of the test messages are their raw bytes, with no previous wire version
+    impl VersionedDeserialize for Vec<u8> {}
+
+    type TestMessage = SystemMessage<TestApp, Vec<u8>, Vec<u8>, Vec<u8>>;
+
+    /// A stand in for the decision log of an ordering protocol, which only has to be serde
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct TestDecisionLog {
+        last_executed: SeqNo,
+        decisions: Vec<Vec<u8>>,
+    }
+
+    /// The envelope of an encoding of the given kind, with wire version 1
+    fn envelope(kind: PayloadKind) -> Vec<u8> {
+        vec![kind.tag(), 0x01, 0x00]
+    }
+
+    /// Check that a value encodes into the expected bytes (with the envelope of the given kind, if any)
+    /// and that they decode back into it.
+    /// Since the messages don't implement [PartialEq], the decoded value is compared through the given key
+    fn check_encoding<T, K>(expected: &[u8], kind: Option<PayloadKind>, value: &T, key: fn(&T) -> K)
+        where T: Serialize + for<'a> Deserialize<'a>, K: PartialEq + Debug {
+        let encoded = match kind {
+            Some(kind) => {
+                let mut encoded = Vec::new();
+
+                encode(&mut encoded, kind, value).expect("Failed to encode the golden vector");
+
+                encoded
+            }
+            None => ::postcard::to_stdvec(value).expect("Failed to encode the golden vector")
+        };
+
+        assert_eq!(encoded, expected, "Golden vector encoding changed");
+
+        check_decoding(expected, kind, value, key);
+    }
+
+    /// Check that the expected bytes decode into the given value
+    fn check_decoding<T, K>(expected: &[u8], kind: Option<PayloadKind>, value: &T, key: fn(&T) -> K)
+        where T: for<'a> Deserialize<'a>, K: PartialEq + Debug {
+        let decoded: T = match kind {
+            Some(kind) => decode(expected, kind).expect("Failed to decode the golden vector"),
+            None => ::postcard::from_bytes(expected).expect("Failed to decode the golden vector")
+        };
+
+        assert_eq!(key(&decoded), key(value), "Golden vector does not decode into the original value");
+    }
+
+    fn check_message<K>(expected: &[u8], message: &TestMessage, key: fn(&TestMessage) -> Option<K>) where K: PartialEq + Debug {
+        check_encoding(expected, Some(PayloadKind::SystemMessage), message, key)
+    }
+
+    fn with_envelope(kind: PayloadKind, payload: &[u8]) -> Vec<u8> {
+        let mut encoded = envelope(kind);
+
+        encoded.extend_from_slice(payload);
+
+        encoded
+    }
+
+    #[test]
+    fn wire_handshake() {
+        // capabilities: min v0, max v1
+        check_encoding(&[0x00, 0x01], None, &WireHandshakeMessage::new(WireCapabilities::new(WireVersion::new(0), WireVersion::new(1))),
+                       |msg| (msg.capabilities().min(), msg.capabilities().max()));
+    }
+
+    #[test]
+    fn request_rejected() {
+        // session 3, operation 7, reason Shed (variant 1)
+        check_encoding(&[0x03, 0x07, 0x01], None, &RequestRejectedMessage::new(SeqNo::from(3), SeqNo::from(7), RejectionReason::Shed),
+                       |msg| (msg.session_id(), msg.reason()));
+    }
+
+    #[test]
+    fn request() {
+        // session 1, operation 2, the operation as a byte string of 3 bytes (its own encoding: 2 bytes)
+        check_encoding(&[0x01, 0x02, 0x03, 0x02, 0xAA, 0xBB], None, &RequestMessage::new(SeqNo::from(1), SeqNo::from(2), vec![0xAAu8, 0xBB]),
+                       |msg| (msg.session_id(), msg.operation().clone()));
+    }
+
+    #[test]
+    fn received_request_keeps_its_bytes() {
+        let expected = [0x01, 0x02, 0x03, 0x02, 0xAA, 0xBB];
+
+        let request: RequestMessage<Vec<u8>> = ::postcard::from_bytes(&expected).unwrap();
+
+        // The operation is kept in its serialized form, and re-encoded with the same bytes
+        assert_eq!(request.serialized_operation().map(|bytes| bytes.to_vec()), Some(vec![0x02, 0xAA, 0xBB]));
+        assert_eq!(::postcard::to_stdvec(&request.clone()).unwrap(), expected);
+
+        assert_eq!(request.into_inner_operation(), vec![0xAA, 0xBB]);
+
+        // A malformed operation is only noticed when decoding it
+        let request: RequestMessage<Vec<u8>> = ::postcard::from_bytes(&[0x01, 0x02, 0x01, 0x05]).unwrap();
+
+        assert!(request.try_operation().is_err());
+    }
+
+    #[test]
+    fn unordered_reply() {
+        // session 1, operation 2, payload of 1 byte, executed seq Some(5)
+        check_encoding(&[0x01, 0x02, 0x01, 0xCC, 0x01, 0x05], None, &ReplyMessage::new_unordered(SeqNo::from(1), SeqNo::from(2), SeqNo::from(5), vec![0xCCu8]),
+                       |msg| (msg.session_id(), msg.executed_seq(), msg.payload().clone()));
+    }
+
+    #[test]
+    fn system_message_variants() {
+        // OrderedRequest (variant 0): session 1, operation 2, operation of 2 bytes (3 with its own encoding)
+        check_message(&with_envelope(PayloadKind::SystemMessage, &[0x00, 0x01, 0x02, 0x03, 0x02, 0xAA, 0xBB]),
+                      &SystemMessage::OrderedRequest(RequestMessage::new(SeqNo::from(1), SeqNo::from(2), vec![0xAA, 0xBB])),
+                      |msg| match msg {
+                          SystemMessage::OrderedRequest(request) => Some(request.operation().clone()),
+                          _ => None,
+                      });
+
+        // RequestRejected (variant 9): session 3, operation 7, reason Shed
+        check_message(&with_envelope(PayloadKind::SystemMessage, &[0x09, 0x03, 0x07, 0x01]),
+                      &SystemMessage::RequestRejected(RequestRejectedMessage::new(SeqNo::from(3), SeqNo::from(7), RejectionReason::Shed)),
+                      |msg| match msg {
+                          SystemMessage::RequestRejected(rejected) => Some((rejected.session_id(), rejected.reason())),
+                          _ => None,
+                      });
+
+        // WireHandshake (variant 10): min v0, max v1
+        check_message(&with_envelope(PayloadKind::SystemMessage, &[0x0A, 0x00, 0x01]),
+                      &SystemMessage::WireHandshake(WireHandshakeMessage::new(WireCapabilities::new(WireVersion::new(0), WireVersion::new(1)))),
+                      |msg| match msg {
+                          SystemMessage::WireHandshake(handshake) => Some((handshake.capabilities().min(), handshake.capabilities().max())),
+                          _ => None,
+                      });
+    }
+
+    #[test]
+    fn protocol_envelope() {
+        // ProtocolMessage (variant 5): version 1, no authenticator, payload of 1 byte
+        check_message(&with_envelope(PayloadKind::SystemMessage, &[0x05, 0x01, 0x00, 0x01, 0xDD]),
+                      &SystemMessage::ProtocolMessage(Protocol::new(vec![0xDD])),
+                      |msg| match msg {
+                          SystemMessage::ProtocolMessage(protocol) if protocol.authenticator().is_none() => Some(protocol.payload().clone()),
+                          _ => None,
+                      });
+
+        // The same message, authenticated (with sequence number 5) with the MAC of 0x33s for node 2
+        let authenticator = Authenticator::from_macs(5, vec![(NodeId::from(2), Mac::from_bytes([0x33; MAC_LENGTH]))]);
+
+        let mut payload = vec![0x05, 0x01, 0x01, 0x05, 0x01, 0x02];
+        payload.extend_from_slice(&[0x33; MAC_LENGTH]);
+        payload.extend_from_slice(&[0x01, 0xDD]);
+
+        check_message(&with_envelope(PayloadKind::SystemMessage, &payload),
+                      &SystemMessage::ProtocolMessage(Protocol::new(vec![0xDD]).with_authenticator(Some(authenticator))),
+                      |msg| match msg {
+                          SystemMessage::ProtocolMessage(protocol) => protocol.authenticator()
+                              .map(|authenticator| (authenticator.seq(), authenticator.macs().clone(), protocol.payload().clone())),
+                          _ => None,
+                      });
+    }
+
+    #[test]
+    fn unsupported_payload_version() {
+        // A protocol message whose payload claims wire version 0, which we have no decoder for.
+        // The payload is rejected before being decoded
+        let encoded = with_envelope(PayloadKind::SystemMessage, &[0x05, 0x00, 0x00, 0x01, 0xDD]);
+
+        assert!(decode::<TestMessage>(&encoded, PayloadKind::SystemMessage).is_err());
+
+        // The same for a state transfer message
+        let encoded = with_envelope(PayloadKind::SystemMessage, &[0x07, 0x00, 0x03, 0x00, 0x01, 0xEE]);
+
+        assert!(decode::<TestMessage>(&encoded, PayloadKind::SystemMessage).is_err());
+    }
+
+    #[test]
+    fn state_transfer_envelope() {
+        // StateTransferMessage (variant 7): version 1, accepts lz4 and zstd, no authenticator, payload of 1 byte
+        check_message(&with_envelope(PayloadKind::SystemMessage, &[0x07, 0x01, 0x03, 0x00, 0x01, 0xEE]),
+                      &SystemMessage::StateTransferMessage(StateTransfer::new(vec![0xEE]).with_accepted_compression(CompressionSet::from_bits(0x03))),
+                      |msg| match msg {
+                          SystemMessage::StateTransferMessage(st) => Some((st.accepted_compression(), st.payload().clone())),
+                          _ => None,
+                      });
+    }
+
+    #[test]
+    fn log_transfer_envelope() {
+        // LogTransferMessage (variant 8): version 1, accepts only zstd, no authenticator, payload of 1 byte
+        check_message(&with_envelope(PayloadKind::SystemMessage, &[0x08, 0x01, 0x02, 0x00, 0x01, 0xEE]),
+                      &SystemMessage::LogTransferMessage(LogTransfer::new(vec![0xEE]).with_accepted_compression(CompressionSet::from_bits(0x02))),
+                      |msg| match msg {
+                          SystemMessage::LogTransferMessage(lt) => Some((lt.accepted_compression(), lt.payload().clone())),
+                          _ => None,
+                      });
+    }
+
+    #[test]
+    #[cfg(feature = "compression_lz4")]
+    fn compressed_state_transfer() {
+        let message: TestMessage = SystemMessage::StateTransferMessage(StateTransfer::new(vec![0xEE; 64])
+            .with_accepted_compression(CompressionSet::from_bits(0x03)));
+
+        let mut encoded = Vec::new();
+
+        encode(&mut encoded, PayloadKind::SystemMessage, &message).unwrap();
+
+        let framed = compress_message(&encoded, &CompressionRequest::new(CompressionAlgorithm::Lz4, 0))
+            .unwrap()
+            .expect("A repetitive message is worth compressing");
+
+        let compressed: CompressedPayload = decode(&framed, PayloadKind::CompressedMessage).unwrap();
+
+        // lz4 (variant 0), the uncompressed length (72 bytes), the digest of the uncompressed encoding
+        // (which the decompressed bytes are checked against) and the compressed bytes
+        let mut expected = vec![0x00, encoded.len() as u8];
+        expected.extend_from_slice(digest_of(&encoded).as_ref());
+        expected.push(compressed.bytes().len() as u8);
+        expected.extend_from_slice(compressed.bytes());
+
+        assert_eq!(encoded.len(), 72);
+        assert_eq!(framed, with_envelope(PayloadKind::CompressedMessage, &expected), "Golden vector encoding changed");
+
+        check_decoding(&compressed.decompress().unwrap(), Some(PayloadKind::SystemMessage), &message,
+                       |msg: &TestMessage| match msg {
+                           SystemMessage::StateTransferMessage(st) => Some((st.accepted_compression(), st.payload().clone())),
+                           _ => None,
+                       });
+    }
+
+    #[cfg(feature = "compression_lz4")]
+    fn digest_of(bytes: &[u8]) -> Digest {
+        let mut context = atlas_common::crypto::hash::Context::new();
+
+        context.update(bytes);
+
+        context.finish()
+    }
+
+    #[test]
+    fn checkpoint() {
+        // seq 9, state of 1 byte, digest of 0x11s, no latest ops and no cached replies
+        let mut expected = vec![0x09, 0x01, 0x42];
+        expected.extend_from_slice(&[0x11; Digest::LENGTH]);
+        expected.extend_from_slice(&[0x00, 0x00]);
+
+        let digest = Digest::from_bytes(&[0x11; Digest::LENGTH][..]).unwrap();
+
+        check_encoding(&with_envelope(PayloadKind::Checkpoint, &expected), Some(PayloadKind::Checkpoint), &Checkpoint::new_simple(SeqNo::from(9), vec![0x42u8], digest),
+                       |checkpoint: &Checkpoint<Vec<u8>>| (*checkpoint.last_seq(), checkpoint.state().clone(), checkpoint.digest().clone()));
+    }
+
+    #[test]
+    fn checkpoint_with_sessions() {
+        let app_digest = Digest::from_bytes(&[0x11; Digest::LENGTH][..]).unwrap();
+
+        // client 4, session 1, latest operation 6
+        let latest_ops = LatestOpsSnapshot::new(vec![SessionLatestOp::new(NodeId::from(4), SeqNo::from(1), SeqNo::from(6))]);
+
+        // client 4, session 1, operation 2, reply of 1 byte
+        let reply_cache = ReplyCache::<TestApp>::new();
+        reply_cache.cache_reply(NodeId::from(4), &ReplyMessage::new(SeqNo::from(1), SeqNo::from(2), vec![0xCC]));
+
+        let checkpoint = Checkpoint::new_with_sessions(SeqNo::from(9), vec![0x42u8], app_digest,
+                                                       latest_ops, reply_cache.snapshot().unwrap());
+        let checkpoint: Checkpoint<Vec<u8>> = (**checkpoint).clone();
+
+        // seq 9, state of 1 byte, the digest of the checkpoint (0x11s), one latest op and one cached reply
+        let mut expected = vec![0x09, 0x01, 0x42];
+        expected.extend_from_slice(&[0x11; Digest::LENGTH]);
+        expected.extend_from_slice(&[0x01, 0x04, 0x01, 0x06]);
+        expected.extend_from_slice(&[0x01, 0x04, 0x01, 0x02, 0x01, 0xCC]);
+
+        check_encoding(&with_envelope(PayloadKind::Checkpoint, &expected), Some(PayloadKind::Checkpoint), &checkpoint,
+                       |checkpoint: &Checkpoint<Vec<u8>>| (checkpoint.digest().clone(), checkpoint.latest_ops().clone(),
+                                                           checkpoint.replies().clone()));
+    }
+
+    #[test]
+    fn decision_log() {
+        // last executed 4, two decisions of 1 and 2 bytes
+        check_encoding(&with_envelope(PayloadKind::DecisionLog, &[0x04, 0x02, 0x01, 0xA1, 0x02, 0xB1, 0xB2]), Some(PayloadKind::DecisionLog),
+                       &TestDecisionLog { last_executed: SeqNo::from(4), decisions: vec![vec![0xA1], vec![0xB1, 0xB2]] },
+                       |log| (log.last_executed, log.decisions.clone()));
+    }
+}
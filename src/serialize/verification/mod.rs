@@ -0,0 +1,269 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+use atlas_common::channel;
+use atlas_common::channel::{ChannelSyncRx, ChannelSyncTx, OneShotRx, OneShotTx, TryRecvError};
+use atlas_common::crypto::hash::Digest;
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_communication::message::{Header, StoredMessage};
+use atlas_communication::message_signing::NetworkMessageSignatureVerifier;
+use atlas_communication::reconfiguration_node::NetworkInformationProvider;
+use atlas_communication::serialize::Serializable;
+use atlas_execution::serialize::ApplicationData;
+use atlas_metrics::metrics::{metric_duration, metric_increment};
+
+use crate::log_transfer::networking::serialize::LogTransferMessage;
+use crate::messages::signature_ver::Verified;
+use crate::metric::{MSG_VERIFICATION_CACHE_HITS_ID, MSG_VERIFICATION_REJECTED_ID, MSG_VERIFICATION_TIME_ID};
+use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
+use crate::serialize::Service;
+use crate::state_transfer::networking::serialize::StateTransferMessage;
+
+const VERIFIER_THREAD_NAME: &str = "MESSAGE-VERIFIER";
+const HAND_OFF_THREAD_NAME: &str = "MESSAGE-VERIFICATION-HAND-OFF";
+
+/// The configuration of the [VerificationPipeline]
+#[derive(Clone, Debug)]
+pub struct VerificationConfig {
+    /// The amount of threads verifying messages
+    pub thread_count: usize,
+    /// How many successful verifications are kept, so repeated messages
+    /// (with the same header digest, from the same sender) are not verified again
+    pub cache_size: usize,
+    /// The amount of messages that can be awaiting verification before submitting blocks
+    pub queue_size: usize,
+}
+
+type VerifyFn<NI, M> = fn(&Arc<NI>, &Header, M) -> Result<Option<Verified<M>>>;
+
+/// Produces the verified form of a message which is known to have passed verification already
+type TrustFn<M> = fn(M) -> Result<Verified<M>>;
+
+/// The outcome of verifying a message sent by the given node. None if it failed verification
+type VerificationResult<M> = (NodeId, Option<StoredMessage<Verified<M>>>);
+
+/// A message awaiting verification by one of the verifier threads
+struct VerificationJob<M> {
+    message: StoredMessage<M>,
+    result_tx: OneShotTx<VerificationResult<M>>,
+}
+
+/// The most recent successful verifications, as the digest of the message's header and its sender.
+/// Only whether the message was verified is kept, not the message itself.
+///
+/// Failed verifications are never cached: the header digest does not cover the signature,
+/// so a peer could otherwise get valid messages dropped by first sending them with a bad signature
+struct VerificationCache {
+    capacity: usize,
+    verified: HashSet<(Digest, NodeId)>,
+    insertion_order: VecDeque<(Digest, NodeId)>,
+}
+
+/// A verification stage which runs the verification of the incoming messages
+/// ([Service::verify_system_message]) on a dedicated thread pool, ahead of the protocol handlers.
+///
+/// Messages are verified in parallel but handed off in the order they were submitted, so the
+/// protocol threads see the same ordering they would have seen without the pipeline.
+/// Messages which fail verification are dropped.
+pub struct VerificationPipeline<M> {
+    job_tx: ChannelSyncTx<VerificationJob<M>>,
+    order_tx: ChannelSyncTx<OneShotRx<VerificationResult<M>>>,
+    cache: Arc<Mutex<VerificationCache>>,
+    trust: TrustFn<M>,
+}
+
+/// The verified messages, in the order they were submitted to the [VerificationPipeline]
+pub struct VerifiedMessages<M> {
+    rx: ChannelSyncRx<StoredMessage<Verified<M>>>,
+}
+
+impl<M> VerificationPipeline<M> where M: Send + 'static {
+    /// Spawn the verification threads for the system messages of the given service
+    pub fn spawn<D, P, S, L, NI, SV>(config: VerificationConfig, network_info: Arc<NI>) -> (Self, VerifiedMessages<M>)
+        where D: ApplicationData + 'static, P: OrderingProtocolMessage<D> + 'static,
+              S: StateTransferMessage + 'static, L: LogTransferMessage<D, P> + 'static,
+              Service<D, P, S, L>: Serializable<Message=M>,
+              NI: NetworkInformationProvider + Send + Sync + 'static,
+              SV: NetworkMessageSignatureVerifier<Service<D, P, S, L>, NI> {
+        // A function pointer does not carry the signature verifier type along to the verifier threads
+        let verify: VerifyFn<NI, M> = Service::<D, P, S, L>::verify_system_message::<NI, SV>;
+        let trust: TrustFn<M> = Service::<D, P, S, L>::trust_verified_message;
+
+        let cache = Arc::new(Mutex::new(VerificationCache::new(config.cache_size)));
+
+        let (job_tx, job_rx) = channel::new_bounded_sync::<VerificationJob<M>>(config.queue_size);
+        let (order_tx, order_rx) = channel::new_bounded_sync(config.queue_size);
+        let (verified_tx, verified_rx) = channel::new_bounded_sync(config.queue_size);
+
+        for thread in 0..config.thread_count.max(1) {
+            let job_rx = job_rx.clone();
+            let network_info = network_info.clone();
+            let cache = cache.clone();
+
+            std::thread::Builder::new()
+                .name(format!("{}-{}", VERIFIER_THREAD_NAME, thread))
+                .spawn(move || {
+                    run_verifier(job_rx, network_info, verify, cache);
+                }).expect("Failed to launch message verification thread");
+        }
+
+        std::thread::Builder::new()
+            .name(HAND_OFF_THREAD_NAME.to_string())
+            .spawn(move || {
+                run_hand_off(order_rx, verified_tx);
+            }).expect("Failed to launch message verification hand off thread");
+
+        (Self { job_tx, order_tx, cache, trust }, VerifiedMessages { rx: verified_rx })
+    }
+
+    /// Submit a message for verification. Blocks if the pipeline is full
+    pub fn submit(&self, message: StoredMessage<M>) -> Result<()> {
+        let (result_tx, result_rx) = channel::new_oneshot_channel();
+
+        // Reserve the message's place in the hand off order before it's verified
+        self.order_tx.send(result_rx)
+            .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Verification hand off thread is disconnected"))?;
+
+        let cached = self.cache.lock().unwrap().contains(message.header());
+
+        if cached {
+            metric_increment(MSG_VERIFICATION_CACHE_HITS_ID, Some(1));
+
+            let (header, message) = message.into_inner();
+            let from = header.from();
+
+            // The same message (the header digest covers the payload) was already verified, so its signatures are not checked again
+            let verified = match (self.trust)(message) {
+                Ok(verified) => Some(StoredMessage::new(header, verified)),
+                Err(err) => {
+                    warn!("Failed to accept cached verification of message from {:?}: {:?}", from, err);
+
+                    None
+                }
+            };
+
+            let _ = result_tx.send((from, verified));
+
+            return Ok(());
+        }
+
+        self.job_tx.send(VerificationJob { message, result_tx })
+            .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Verification threads are disconnected"))
+    }
+}
+
+impl<M> VerifiedMessages<M> {
+    pub fn recv(&self) -> Result<StoredMessage<Verified<M>>> {
+        self.rx.recv()
+            .map_err(|_| Error::simple_with_msg(ErrorKind::Communication, "Verification pipeline is disconnected"))
+    }
+
+    /// Returns None if there is no verified message available
+    pub fn try_recv(&self) -> Result<Option<StoredMessage<Verified<M>>>> {
+        match self.rx.try_recv() {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::ChannelEmpty) => Ok(None),
+            Err(_) => Err(Error::simple_with_msg(ErrorKind::Communication, "Verification pipeline is disconnected"))
+        }
+    }
+
+    /// Returns None if no message was verified until the timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<StoredMessage<Verified<M>>>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::Timeout) => Ok(None),
+            Err(_) => Err(Error::simple_with_msg(ErrorKind::Communication, "Verification pipeline is disconnected"))
+        }
+    }
+}
+
+impl VerificationCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            verified: HashSet::with_capacity(capacity),
+            insertion_order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn contains(&self, header: &Header) -> bool {
+        self.verified.contains(&(header.unique_digest(), header.from()))
+    }
+
+    fn insert_verified(&mut self, header: &Header) {
+        let key = (header.unique_digest(), header.from());
+
+        if self.capacity == 0 || !self.verified.insert(key.clone()) {
+            return;
+        }
+
+        self.insertion_order.push_back(key);
+
+        while self.insertion_order.len() > self.capacity {
+            if let Some(oldest) = self.insertion_order.pop_front() {
+                self.verified.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn run_verifier<NI, M>(job_rx: ChannelSyncRx<VerificationJob<M>>, network_info: Arc<NI>, verify: VerifyFn<NI, M>,
+                       cache: Arc<Mutex<VerificationCache>>) {
+    while let Ok(VerificationJob { message, result_tx }) = job_rx.recv() {
+        let start = Instant::now();
+
+        let (header, message) = message.into_inner();
+
+        let verified = match verify(&network_info, &header, message) {
+            Ok(verified) => verified,
+            Err(err) => {
+                warn!("Failed to verify message from {:?}: {:?}", header.from(), err);
+
+                None
+            }
+        };
+
+        metric_duration(MSG_VERIFICATION_TIME_ID, start.elapsed());
+
+        if verified.is_some() {
+            cache.lock().unwrap().insert_verified(&header);
+        }
+
+        let _ = result_tx.send((header.from(), verified.map(|verified| StoredMessage::new(header, verified))));
+    }
+}
+
+/// Hand off the verified messages in the order they were submitted
+fn run_hand_off<M>(order_rx: ChannelSyncRx<OneShotRx<VerificationResult<M>>>, verified_tx: ChannelSyncTx<StoredMessage<Verified<M>>>) {
+    while let Ok(result_rx) = order_rx.recv() {
+        let (from, verified) = match result_rx.recv() {
+            Ok(result) => result,
+            Err(_) => {
+                error!("Message verification result was dropped");
+
+                continue;
+            }
+        };
+
+        let message = match verified {
+            Some(message) => message,
+            None => {
+                warn!("Dropping message from {:?} which failed verification", from);
+
+                metric_increment(MSG_VERIFICATION_REJECTED_ID, Some(1));
+
+                continue;
+            }
+        };
+
+        if let Err(_) = verified_tx.send(message) {
+            error!("Verified message receiver is disconnected, stopping the hand off");
+
+            break;
+        }
+    }
+}
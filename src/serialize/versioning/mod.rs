@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+#[cfg(feature = "serialize_serde")]
+use std::marker::PhantomData;
+
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(feature = "serialize_serde")]
+use serde::de::{DeserializeSeed, Error as DeserializeError};
+
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_communication::message::StoredMessage;
+use atlas_execution::serialize::ApplicationData;
+
+use crate::messages::signature_ver::Verified;
+use crate::messages::SystemMessage;
+
+/// The version of the wire format used by this build
+pub const CURRENT_WIRE_VERSION: WireVersion = WireVersion(1);
+
+/// The oldest wire version we are still able to decode.
+/// When the version is bumped, this stays at the previous version (N-1) for a release, so clusters
+/// can be upgraded one replica at a time. There is no previous version yet
+pub const MIN_SUPPORTED_WIRE_VERSION: WireVersion = CURRENT_WIRE_VERSION;
+
+/// The version of the wire format of the messages exchanged by atlas-core and the protocols on top of it.
+///
+/// Whenever the encoding of any of the protocol messages changes, the version must be bumped
+/// and the protocol must be able to decode the messages of the previous version.
+/// The version of a message is read before its payload, so the payload is decoded directly with
+/// the decoder of the version it was encoded with (see `VersionedDeserialize` with serde and
+/// `OrderingProtocolMessage::deserialize_previous_capnp` with capnp).
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct WireVersion(u16);
+
+/// The range of wire versions a node is able to speak
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WireCapabilities {
+    min: WireVersion,
+    max: WireVersion,
+}
+
+/// The capability handshake exchanged by the replicas when they connect,
+/// in order to check that they are able to understand each other.
+///
+/// It is always signed (see [NodeWrap::send_wire_handshake](crate::smr::networking::NodeWrap::send_wire_handshake)),
+/// so a peer's advertised capabilities can't be forged.
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct WireHandshakeMessage {
+    capabilities: WireCapabilities,
+}
+
+/// The wire versions negotiated with each of the peers.
+///
+/// Messages are always sent with the [CURRENT_WIRE_VERSION], since there is no way to encode a message
+/// with an older version; the peers one version ahead are the ones which decode them with the previous version's decoder.
+/// The negotiation only establishes whether we are able to talk with a peer at all.
+pub struct WireVersionTable {
+    local: WireCapabilities,
+    peers: BTreeMap<NodeId, WireVersion>,
+}
+
+impl WireVersion {
+    pub const fn new(version: u16) -> Self {
+        Self(version)
+    }
+
+    pub fn version(&self) -> u16 {
+        self.0
+    }
+
+    /// Are we able to decode messages with this version
+    pub fn is_supported(&self) -> bool {
+        *self >= MIN_SUPPORTED_WIRE_VERSION && *self <= CURRENT_WIRE_VERSION
+    }
+
+    /// Is this the version of the previous release, whose messages are decoded by the previous version's decoder
+    pub fn is_previous(&self) -> bool {
+        *self < CURRENT_WIRE_VERSION && self.is_supported()
+    }
+}
+
+impl Default for WireVersion {
+    fn default() -> Self {
+        CURRENT_WIRE_VERSION
+    }
+}
+
+impl Display for WireVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl WireCapabilities {
+    pub fn new(min: WireVersion, max: WireVersion) -> Self {
+        Self { min, max }
+    }
+
+    /// The capabilities of this build
+    pub fn local() -> Self {
+        Self::new(MIN_SUPPORTED_WIRE_VERSION, CURRENT_WIRE_VERSION)
+    }
+
+    pub fn min(&self) -> WireVersion {
+        self.min
+    }
+
+    pub fn max(&self) -> WireVersion {
+        self.max
+    }
+
+    /// The highest version supported by both sides, if there is one.
+    /// Both sides reach the same result, so no further round trip is needed
+    pub fn negotiate(&self, other: &WireCapabilities) -> Option<WireVersion> {
+        let highest = self.max.min(other.max);
+        let lowest = self.min.max(other.min);
+
+        if highest >= lowest {
+            Some(highest)
+        } else {
+            None
+        }
+    }
+}
+
+impl WireHandshakeMessage {
+    pub fn new(capabilities: WireCapabilities) -> Self {
+        Self { capabilities }
+    }
+
+    pub fn capabilities(&self) -> &WireCapabilities {
+        &self.capabilities
+    }
+}
+
+impl WireVersionTable {
+    pub fn new() -> Self {
+        Self {
+            local: WireCapabilities::local(),
+            peers: Default::default(),
+        }
+    }
+
+    /// The handshake we should send to the peers we connect to
+    pub fn handshake_message(&self) -> WireHandshakeMessage {
+        WireHandshakeMessage::new(self.local)
+    }
+
+    /// Process a (verified) message received from a peer, if it is a wire handshake.
+    /// Returns the version negotiated with the peer, or None if the message is not a handshake.
+    /// Fails if there is no version supported by both of us, in which case the connection to the peer
+    /// should be dropped
+    pub fn handle_handshake<D, P, S, L>(&mut self, message: &StoredMessage<Verified<SystemMessage<D, P, S, L>>>) -> Result<Option<WireVersion>>
+        where D: ApplicationData {
+        let handshake = match message.message().message() {
+            SystemMessage::WireHandshake(handshake) => handshake,
+            _ => return Ok(None),
+        };
+
+        let from = message.header().from();
+
+        let version = match self.local.negotiate(handshake.capabilities()) {
+            Some(version) => version,
+            None => {
+                self.peers.remove(&from);
+
+                return Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "No wire version is supported by both nodes"));
+            }
+        };
+
+        self.peers.insert(from, version);
+
+        Ok(Some(version))
+    }
+
+    /// The version negotiated with the given peer, if we have received its handshake
+    pub fn negotiated_version(&self, peer: NodeId) -> Option<WireVersion> {
+        self.peers.get(&peer).copied()
+    }
+
+    /// Forget the version negotiated with a peer (for example, when it disconnects,
+    /// since it might come back running another version)
+    pub fn forget_peer(&mut self, peer: NodeId) {
+        self.peers.remove(&peer);
+    }
+}
+
+/// The messages whose serde encoding is versioned.
+///
+/// Messages of the current version are decoded with their [Deserialize] implementation, while the ones
+/// encoded by a peer still running the previous wire version are handed, still undecoded, to
+/// [VersionedDeserialize::deserialize_previous].
+/// By default, no previous version is supported
+#[cfg(feature = "serialize_serde")]
+pub trait VersionedDeserialize: for<'a> Deserialize<'a> {
+    fn deserialize_previous<'de, DE>(version: WireVersion, deserializer: DE) -> std::result::Result<Self, DE::Error>
+        where DE: Deserializer<'de> {
+        let _ = deserializer;
+
+        Err(DE::Error::custom(format!("No decoder for the messages of wire version {}", version)))
+    }
+}
+
+#[cfg(feature = "serialize_serde")]
+impl VersionedDeserialize for () {}
+
+/// Decodes a payload with the decoder of the wire version it was encoded with,
+/// which must have been read before the payload
+#[cfg(feature = "serialize_serde")]
+pub(crate) struct VersionedPayload<P> {
+    version: WireVersion,
+    payload: PhantomData<fn() -> P>,
+}
+
+#[cfg(feature = "serialize_serde")]
+impl<P> VersionedPayload<P> {
+    pub(crate) fn new(version: WireVersion) -> Self {
+        Self { version, payload: PhantomData }
+    }
+}
+
+#[cfg(feature = "serialize_serde")]
+impl<'de, P> DeserializeSeed<'de> for VersionedPayload<P> where P: VersionedDeserialize {
+    type Value = P;
+
+    fn deserialize<DE>(self, deserializer: DE) -> std::result::Result<Self::Value, DE::Error> where DE: Deserializer<'de> {
+        if self.version == CURRENT_WIRE_VERSION {
+            P::deserialize(deserializer)
+        } else if self.version.is_supported() {
+            P::deserialize_previous(self.version, deserializer)
+        } else {
+            Err(DE::Error::custom(format!("Unsupported wire version {}", self.version)))
+        }
+    }
+}
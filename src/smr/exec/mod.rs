@@ -12,6 +12,9 @@ use crate::serialize::Service;
 use crate::smr::networking::NodeWrap;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 
+pub mod reply_cache;
+pub mod unordered;
+
 pub enum ReplyType {
     Ordered,
     Unordered,
@@ -0,0 +1,203 @@
+use std::sync::{Arc, Mutex};
+
+use intmap::IntMap;
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Serialize};
+
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::{Orderable, SeqNo};
+use atlas_execution::serialize::ApplicationData;
+
+use crate::messages::ReplyMessage;
+use crate::request_pre_processing::operation_key_raw;
+use crate::smr::exec::{ReplyNode, ReplyType};
+
+/// The result of looking up a request in the reply cache
+pub enum CachedReplyLookup<R> {
+    /// The request has not yet been executed (as far as we know)
+    NotExecuted,
+    /// The request was the last one executed for its session, and this was its reply
+    Cached(ReplyMessage<R>),
+    /// The request has been executed, but it is older than the last executed
+    /// operation of its session, so its reply is no longer available
+    Outdated,
+}
+
+/// A cache of the last reply sent to each client session.
+///
+/// When a client retransmits a request that has already been executed, we resend
+/// the cached reply instead of silently discarding the request, which gives clients
+/// exactly once semantics even if the original reply was lost.
+/// This cache is shared between the reply node (which fills it, see [CachingReplyNode])
+/// and the request pre processor (which uses it to answer retransmissions).
+pub struct ReplyCache<D> where D: ApplicationData {
+    replies: Mutex<IntMap<(NodeId, ReplyMessage<D::Reply>)>>,
+}
+
+/// A snapshot of the reply cache, with the replies in their serialized form so
+/// it can be stored along with the checkpoints.
+///
+/// The replies are sorted by their session, so the same cache always produces the same snapshot
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReplyCacheSnapshot {
+    replies: Vec<SerializedCachedReply>,
+}
+
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SerializedCachedReply {
+    client: NodeId,
+    session: SeqNo,
+    operation: SeqNo,
+    reply: Vec<u8>,
+}
+
+impl<D> ReplyCache<D> where D: ApplicationData {
+    pub fn new() -> Self {
+        Self {
+            replies: Mutex::new(IntMap::new()),
+        }
+    }
+
+    /// Cache the reply sent to a given client. We only keep the reply with the
+    /// highest operation id for each session
+    pub fn cache_reply(&self, client: NodeId, reply: &ReplyMessage<D::Reply>) {
+        let key = operation_key_raw(client, reply.session_id());
+
+        let mut replies = self.replies.lock().unwrap();
+
+        match replies.get(key) {
+            Some((_, cached)) if cached.sequence_number() >= reply.sequence_number() => {}
+            _ => {
+                replies.insert(key, (client, reply.clone()));
+            }
+        }
+    }
+
+    /// Look up the reply for a given client request
+    pub fn get_reply(&self, client: NodeId, session: SeqNo, operation: SeqNo) -> CachedReplyLookup<D::Reply> {
+        let key = operation_key_raw(client, session);
+
+        let replies = self.replies.lock().unwrap();
+
+        match replies.get(key) {
+            Some((_, cached)) if cached.sequence_number() == operation => {
+                CachedReplyLookup::Cached(cached.clone())
+            }
+            Some((_, cached)) if cached.sequence_number() > operation => {
+                CachedReplyLookup::Outdated
+            }
+            _ => CachedReplyLookup::NotExecuted
+        }
+    }
+
+    /// Snapshot the cache, to be stored in a checkpoint
+    pub fn snapshot(&self) -> Result<ReplyCacheSnapshot> {
+        let replies = self.replies.lock().unwrap();
+
+        let mut serialized = Vec::with_capacity(replies.len());
+
+        for (client, reply) in replies.values() {
+            let mut reply_bytes = Vec::new();
+
+            D::serialize_reply(&mut reply_bytes, reply.payload())?;
+
+            serialized.push(SerializedCachedReply {
+                client: *client,
+                session: reply.session_id(),
+                operation: reply.sequence_number(),
+                reply: reply_bytes,
+            });
+        }
+
+        serialized.sort_unstable_by_key(SerializedCachedReply::operation_key);
+
+        Ok(ReplyCacheSnapshot { replies: serialized })
+    }
+
+    /// Install the replies contained in a snapshot (received in a checkpoint),
+    /// so we can still answer retransmissions of requests executed before it
+    pub fn install(&self, snapshot: ReplyCacheSnapshot) -> Result<()> {
+        for cached in snapshot.replies {
+            let payload = D::deserialize_reply(&cached.reply[..])?;
+
+            self.cache_reply(cached.client, &ReplyMessage::new(cached.session, cached.operation, payload));
+        }
+
+        Ok(())
+    }
+}
+
+impl SerializedCachedReply {
+    fn operation_key(&self) -> u64 {
+        operation_key_raw(self.client, self.session)
+    }
+}
+
+impl ReplyCacheSnapshot {
+    pub fn len(&self) -> usize {
+        self.replies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty()
+    }
+}
+
+/// A reply node which caches every ordered reply it sends in a [ReplyCache]
+pub struct CachingReplyNode<D, RN> where D: ApplicationData {
+    inner: Arc<RN>,
+    cache: Arc<ReplyCache<D>>,
+}
+
+impl<D, RN> CachingReplyNode<D, RN> where D: ApplicationData {
+    pub fn new(inner: Arc<RN>, cache: Arc<ReplyCache<D>>) -> Self {
+        Self { inner, cache }
+    }
+
+    pub fn cache(&self) -> &Arc<ReplyCache<D>> {
+        &self.cache
+    }
+}
+
+impl<D, RN> ReplyNode<D> for CachingReplyNode<D, RN>
+    where D: ApplicationData + 'static,
+          RN: ReplyNode<D> {
+    fn send(&self, reply_type: ReplyType, reply: ReplyMessage<D::Reply>, target: NodeId, flush: bool) -> Result<()> {
+        if let ReplyType::Ordered = reply_type {
+            self.cache.cache_reply(target, &reply);
+        }
+
+        self.inner.send(reply_type, reply, target, flush)
+    }
+
+    fn send_signed(&self, reply_type: ReplyType, reply: ReplyMessage<D::Reply>, target: NodeId, flush: bool) -> Result<()> {
+        if let ReplyType::Ordered = reply_type {
+            self.cache.cache_reply(target, &reply);
+        }
+
+        self.inner.send_signed(reply_type, reply, target, flush)
+    }
+
+    fn broadcast(&self, reply_type: ReplyType, reply: ReplyMessage<D::Reply>, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>> {
+        let targets: Vec<NodeId> = targets.collect();
+
+        if let ReplyType::Ordered = reply_type {
+            targets.iter().for_each(|target| self.cache.cache_reply(*target, &reply));
+        }
+
+        self.inner.broadcast(reply_type, reply, targets.into_iter())
+    }
+
+    fn broadcast_signed(&self, reply_type: ReplyType, reply: ReplyMessage<D::Reply>, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>> {
+        let targets: Vec<NodeId> = targets.collect();
+
+        if let ReplyType::Ordered = reply_type {
+            targets.iter().for_each(|target| self.cache.cache_reply(*target, &reply));
+        }
+
+        self.inner.broadcast_signed(reply_type, reply, targets.into_iter())
+    }
+}
@@ -0,0 +1,187 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crossbeam::channel::{Receiver, Sender};
+use log::{error, warn};
+
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::{Orderable, SeqNo};
+use atlas_execution::serialize::ApplicationData;
+
+use crate::messages::{ReplyMessage, StoredRequestMessage};
+use crate::smr::exec::{ReplyNode, ReplyType};
+
+const READ_ONLY_QUEUE_SIZE: usize = 1024;
+const READ_ONLY_THREAD_NAME: &str = "READ-ONLY-EXECUTION";
+
+/// The application's executor for read only (unordered) requests.
+///
+/// These requests are executed over the current state of the replica, without going through
+/// the ordering protocol, so they must not modify the state.
+pub trait ReadOnlyExecutor<D>: Send + Sync where D: ApplicationData {
+    /// Execute a read only request, returning the reply and the sequence number of
+    /// the last decision that was applied to the state the request was executed over
+    fn execute_read_only(&self, client: NodeId, request: &D::Request) -> (SeqNo, D::Reply);
+}
+
+/// Where the request pre processor delivers the unordered requests.
+/// When there is none, they are delivered to the proposer along with the ordered requests
+pub trait UnorderedRequestSink<O>: Send + Sync {
+    /// Submit requests for execution. Returns the requests back if they could not be accepted
+    fn submit(&self, requests: Vec<StoredRequestMessage<O>>) -> std::result::Result<(), Vec<StoredRequestMessage<O>>>;
+}
+
+/// Executes the unordered requests on a dedicated thread, with the application's
+/// [ReadOnlyExecutor], and replies directly to the clients
+pub struct ReadOnlyExecution<O> {
+    tx: Sender<Vec<StoredRequestMessage<O>>>,
+}
+
+impl<O> ReadOnlyExecution<O> where O: Send + 'static {
+    pub fn spawn<D, EX, RN>(executor: Arc<EX>, reply_node: Arc<RN>) -> Arc<Self>
+        where D: ApplicationData<Request=O> + 'static,
+              EX: ReadOnlyExecutor<D> + 'static,
+              RN: ReplyNode<D> + 'static {
+        let (tx, rx) = crossbeam::channel::bounded(READ_ONLY_QUEUE_SIZE);
+
+        std::thread::Builder::new()
+            .name(READ_ONLY_THREAD_NAME.to_string())
+            .spawn(move || {
+                run_read_only_execution::<D, EX, RN>(rx, executor, reply_node);
+            }).expect("Failed to launch read only execution thread");
+
+        Arc::new(Self { tx })
+    }
+}
+
+impl<O> UnorderedRequestSink<O> for ReadOnlyExecution<O> where O: Send {
+    fn submit(&self, requests: Vec<StoredRequestMessage<O>>) -> std::result::Result<(), Vec<StoredRequestMessage<O>>> {
+        if let Err(err) = self.tx.try_send(requests) {
+            warn!("Failed to deliver unordered requests to the read only execution, the queue is full or disconnected");
+
+            return Err(err.into_inner());
+        }
+
+        Ok(())
+    }
+}
+
+fn run_read_only_execution<D, EX, RN>(rx: Receiver<Vec<StoredRequestMessage<D::Request>>>, executor: Arc<EX>, reply_node: Arc<RN>)
+    where D: ApplicationData,
+          EX: ReadOnlyExecutor<D>,
+          RN: ReplyNode<D> {
+    while let Ok(requests) = rx.recv() {
+        for request in requests {
+            let (header, message) = request.into_inner();
+
+            let operation = match message.try_operation() {
+                Ok(operation) => operation,
+                Err(err) => {
+                    error!("Failed to decode the unordered request from {:?}: {:?}", header.from(), err);
+
+                    continue;
+                }
+            };
+
+            let (executed_seq, reply) = executor.execute_read_only(header.from(), operation);
+
+            let reply = ReplyMessage::new_unordered(message.session_id(), message.sequence_number(), executed_seq, reply);
+
+            if let Err(err) = reply_node.send(ReplyType::Unordered, reply, header.from(), true) {
+                error!("Failed to send unordered reply to {:?}: {:?}", header.from(), err);
+            }
+        }
+    }
+}
+
+/// The outcome of collecting the replies to an unordered request
+pub enum UnorderedQuorumOutcome<R> {
+    /// We still need more replies to reach a decision
+    Pending,
+    /// A quorum of replicas executed the request over the same state and replied the same
+    Accepted(ReplyMessage<R>),
+    /// The replicas diverged (they executed over different states, or replied differently),
+    /// so no quorum can be formed. The request should be resubmitted as an ordered request
+    FallbackToOrdered,
+}
+
+/// Collects the replies to an unordered request on the client side.
+///
+/// Replies match when they were executed over the state of the same decision and have the same
+/// (serialized) payload. A reply is only accepted once `quorum` replicas agree on it.
+pub struct UnorderedReplyQuorum<D> where D: ApplicationData {
+    quorum: usize,
+    replica_count: usize,
+    responded: Vec<NodeId>,
+    votes: Vec<ReplyVote<D::Reply>>,
+    _phantom: PhantomData<fn() -> D>,
+}
+
+struct ReplyVote<R> {
+    executed_seq: Option<SeqNo>,
+    payload: Vec<u8>,
+    count: usize,
+    reply: ReplyMessage<R>,
+}
+
+impl<D> UnorderedReplyQuorum<D> where D: ApplicationData {
+    pub fn new(quorum: usize, replica_count: usize) -> Self {
+        Self {
+            quorum,
+            replica_count,
+            responded: Vec::with_capacity(replica_count),
+            votes: Vec::new(),
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Register the reply of a given replica
+    pub fn register_reply(&mut self, from: NodeId, reply: ReplyMessage<D::Reply>) -> Result<UnorderedQuorumOutcome<D::Reply>> {
+        if self.responded.contains(&from) {
+            return Ok(self.outcome());
+        }
+
+        self.responded.push(from);
+
+        let mut payload = Vec::new();
+
+        D::serialize_reply(&mut payload, reply.payload())?;
+
+        let executed_seq = reply.executed_seq();
+
+        match self.votes.iter_mut().find(|vote| vote.executed_seq == executed_seq && vote.payload == payload) {
+            Some(vote) => vote.count += 1,
+            None => self.votes.push(ReplyVote { executed_seq, payload, count: 1, reply }),
+        }
+
+        Ok(self.outcome())
+    }
+
+    /// Require the given amount of matching replies from a quorum of `replica_count` replicas,
+    /// since the members of the quorum changed while the request was pending
+    pub fn update_quorum(&mut self, quorum: usize, replica_count: usize) -> UnorderedQuorumOutcome<D::Reply> {
+        self.quorum = quorum;
+        self.replica_count = replica_count;
+
+        self.outcome()
+    }
+
+    fn outcome(&self) -> UnorderedQuorumOutcome<D::Reply> {
+        // Replies without the executed sequence number can never be part of a quorum
+        let viable_votes = || self.votes.iter().filter(|vote| vote.executed_seq.is_some());
+
+        if let Some(vote) = viable_votes().find(|vote| vote.count >= self.quorum) {
+            return UnorderedQuorumOutcome::Accepted(vote.reply.clone());
+        }
+
+        let best = viable_votes().map(|vote| vote.count).max().unwrap_or(0);
+        let missing = self.replica_count.saturating_sub(self.responded.len());
+
+        if best + missing < self.quorum {
+            UnorderedQuorumOutcome::FallbackToOrdered
+        } else {
+            UnorderedQuorumOutcome::Pending
+        }
+    }
+}
@@ -16,7 +16,9 @@ use crate::log_transfer::networking::LogTransferSendNode;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
 use crate::ordering_protocol::networking::OrderProtocolSendNode;
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
+use crate::messages::SystemMessage;
 use crate::serialize::{Service, ServiceMessage};
+use crate::serialize::versioning::WireVersionTable;
 use crate::smr::exec::ReplyNode;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 use crate::state_transfer::networking::StateTransferSendNode;
@@ -53,6 +55,13 @@ impl<NT, D, P, S, L, NI, RM> NodeWrap<NT, D, P, S, L, NI, RM>
     pub fn from_node(node: NT) -> Self {
         NodeWrap(node, Default::default())
     }
+
+    /// Send our (signed) wire handshake to the given peers.
+    /// This must be done as soon as we connect to them, and their handshakes must be passed to
+    /// [WireVersionTable::handle_handshake] once verified
+    pub fn send_wire_handshake(&self, versions: &WireVersionTable, targets: impl Iterator<Item=NodeId>) -> Result<(), Vec<NodeId>> {
+        self.0.broadcast_signed(SystemMessage::WireHandshake(versions.handshake_message()), targets)
+    }
 }
 
 impl<NT, D, P, S, L, NI, RM> Deref for NodeWrap<NT, D, P, S, L, NI, RM>
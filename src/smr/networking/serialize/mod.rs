@@ -22,11 +22,21 @@ pub trait DecisionLogMessage<D, OPM> {
 
     #[cfg(feature = "serialize_serde")]
     type DecLog: OrderProtocolLog + for<'a> Deserialize<'a> + Serialize + Send + Clone;
-    
-    fn verify_decision_log<NI, OPVH>(network_info: &Arc<NI>, dec_log: Self::DecLog)
-                                     -> Result<(bool, Self::DecLog)>
+
+    /// Verify a decision log received from another replica.
+    /// Logs whose decisions are backed by [QuorumCertificate](crate::ordering_protocol::networking::certificate::QuorumCertificate)s
+    /// can be verified with [OrderProtocolSignatureVerificationHelper::verify_quorum_certificate],
+    /// a single check per decision
+    fn verify_decision_log<NI, OPVH>(network_info: &Arc<NI>, dec_log: &Self::DecLog)
+                                     -> Result<bool>
         where NI: NetworkInformationProvider,
               D: ApplicationData,
               OPM: OrderingProtocolMessage<D>,
               OPVH: OrderProtocolSignatureVerificationHelper<D, OPM, NI>,;
-}
\ No newline at end of file
+
+    #[cfg(feature = "serialize_capnp")]
+    fn serialize_declog_capnp(builder: capnp::any_pointer::Builder, msg: &Self::DecLog) -> Result<()>;
+
+    #[cfg(feature = "serialize_capnp")]
+    fn deserialize_declog_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::DecLog>;
+}
@@ -1,6 +1,7 @@
 use atlas_smr_application::serialize::ApplicationData;
 use atlas_common::error::*;
 use atlas_common::ordering::SeqNo;
+use crate::messages::StoredRequestMessage;
 use crate::ordering_protocol::{OrderingProtocol, SerProof};
 use crate::persistent_log::PersistentDecisionLog;
 use crate::smr::networking::serialize::DecisionLogMessage;
@@ -20,7 +21,9 @@ pub trait DecisionLog<D, OP, NT, PL> where D: ApplicationData, OP: OrderingProto
 
     /// Install a log received from other replicas in the system
     /// list of all requests that should then be executed by the application.
-    fn install_log(&mut self, order_protocol: &OP, dec_log: DecLog<D, OP::Serialization, Self::LogSerialization>) -> Result<Vec<D::Request>>
+    /// The requests are returned as they were ordered, so the request pre processor can also be
+    /// told they have been decided (see [crate::log_transfer::LTResult::finished])
+    fn install_log(&mut self, order_protocol: &OP, dec_log: DecLog<D, OP::Serialization, Self::LogSerialization>) -> Result<Vec<StoredRequestMessage<D::Request>>>
         where PL: PersistentDecisionLog<D, OP, Self::LogSerialization>;
 
     /// Take a snapshot of our current decision log.
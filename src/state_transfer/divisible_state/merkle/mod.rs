@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Serialize};
+
+use atlas_common::crypto::hash::{Context, Digest};
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+
+/// Domain separation between the leaves and the inner nodes of the tree, so a leaf can never
+/// be passed off as an inner node (or the other way around)
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// The commitment to all of the parts of a divisible state: the root of the Merkle tree built over
+/// the digests of the parts, in order, along with the number of parts.
+///
+/// This should be included in the state descriptor (see [MerkleCommittedDescriptor]), so that once
+/// the replicas agree on the descriptor, each part can be verified on its own with its [MerkleProof]
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MerkleCommitment {
+    root: Digest,
+    leaf_count: u64,
+}
+
+/// The proof that a given part is included in a [MerkleCommitment]: the digests of the siblings
+/// on the path from the part's leaf up to the root
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct MerkleProof {
+    index: u64,
+    siblings: Vec<Digest>,
+}
+
+/// A Merkle tree over the digests of the parts of a divisible state.
+///
+/// When a level has an odd number of nodes, the last one is promoted to the next level as is
+/// (instead of being paired with a copy of itself), so no two different sets of parts have the same root
+pub struct MerkleTree {
+    /// The levels of the tree, from the leaves up to the root
+    levels: Vec<Vec<Digest>>,
+}
+
+/// A state descriptor which carries the Merkle commitment to its parts
+pub trait MerkleCommittedDescriptor {
+    fn merkle_commitment(&self) -> &MerkleCommitment;
+}
+
+/// The verdict on a state part received from a peer
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartVerdict {
+    /// The part is included in the commitment
+    Valid,
+    /// The part is not included in the commitment. The peer which served it has been penalized
+    Invalid,
+    /// The peer has served too many invalid parts, so its parts are no longer considered
+    PeerBanned,
+}
+
+/// Verifies the state parts received from the peers against the agreed commitment,
+/// keeping track of the peers which served invalid parts
+pub struct PartVerifier {
+    commitment: MerkleCommitment,
+    max_invalid_parts: usize,
+    invalid_parts: BTreeMap<NodeId, usize>,
+}
+
+fn leaf_digest(index: u64, part_digest: &Digest) -> Digest {
+    let mut context = Context::new();
+
+    context.update(&[LEAF_PREFIX]);
+    context.update(&index.to_le_bytes());
+    context.update(part_digest.as_ref());
+
+    context.finish()
+}
+
+fn node_digest(left: &Digest, right: &Digest) -> Digest {
+    let mut context = Context::new();
+
+    context.update(&[NODE_PREFIX]);
+    context.update(left.as_ref());
+    context.update(right.as_ref());
+
+    context.finish()
+}
+
+impl MerkleCommitment {
+    pub fn root(&self) -> &Digest {
+        &self.root
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    /// Verify that the part with the given digest is the part at the given index
+    pub fn verify(&self, part_digest: &Digest, proof: &MerkleProof) -> bool {
+        if proof.index >= self.leaf_count {
+            return false;
+        }
+
+        let mut siblings = proof.siblings.iter();
+
+        let mut current = leaf_digest(proof.index, part_digest);
+        let mut position = proof.index;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            // The last node of an odd level is promoted without a sibling
+            let promoted = position == width - 1 && width % 2 == 1;
+
+            if !promoted {
+                let sibling = match siblings.next() {
+                    Some(sibling) => sibling,
+                    None => return false,
+                };
+
+                current = if position % 2 == 0 {
+                    node_digest(&current, sibling)
+                } else {
+                    node_digest(sibling, &current)
+                };
+            }
+
+            position /= 2;
+            width = (width + 1) / 2;
+        }
+
+        // A proof with more siblings than the path to the root is not valid
+        siblings.next().is_none() && current == self.root
+    }
+}
+
+impl MerkleProof {
+    /// The index of the part this proof refers to
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn siblings(&self) -> &Vec<Digest> {
+        &self.siblings
+    }
+}
+
+impl MerkleTree {
+    /// Build the tree over the digests of the parts of a state, in order
+    pub fn from_part_digests(part_digests: &[Digest]) -> Result<Self> {
+        if part_digests.is_empty() {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "Can't build a Merkle tree without any parts"));
+        }
+
+        let leaves: Vec<Digest> = part_digests.iter()
+            .enumerate()
+            .map(|(index, digest)| leaf_digest(index as u64, digest))
+            .collect();
+
+        let mut levels = vec![leaves];
+
+        while levels.last().unwrap().len() > 1 {
+            let level = levels.last().unwrap();
+
+            let next = level.chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_digest(left, right),
+                    [promoted] => promoted.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+
+            levels.push(next);
+        }
+
+        Ok(Self { levels })
+    }
+
+    /// Build the tree over the given parts, with the given function to digest each of them
+    pub fn from_parts<P>(parts: &[P], digest: fn(&P) -> Digest) -> Result<Self> {
+        let part_digests: Vec<Digest> = parts.iter().map(digest).collect();
+
+        Self::from_part_digests(&part_digests)
+    }
+
+    pub fn commitment(&self) -> MerkleCommitment {
+        MerkleCommitment {
+            root: self.levels.last().unwrap()[0].clone(),
+            leaf_count: self.levels[0].len() as u64,
+        }
+    }
+
+    /// The inclusion proof of the part at the given index, to be sent along with the part
+    pub fn proof(&self, index: u64) -> Option<MerkleProof> {
+        if index >= self.levels[0].len() as u64 {
+            return None;
+        }
+
+        let mut siblings = Vec::with_capacity(self.levels.len());
+        let mut position = index as usize;
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = position ^ 1;
+
+            // Promoted nodes have no sibling
+            if sibling < level.len() {
+                siblings.push(level[sibling].clone());
+            }
+
+            position /= 2;
+        }
+
+        Some(MerkleProof { index, siblings })
+    }
+}
+
+impl PartVerifier {
+    /// Verify the parts against the given commitment (which should come from the agreed descriptor).
+    /// Peers which serve more than `max_invalid_parts` invalid parts are banned
+    pub fn new(commitment: MerkleCommitment, max_invalid_parts: usize) -> Self {
+        Self {
+            commitment,
+            max_invalid_parts,
+            invalid_parts: Default::default(),
+        }
+    }
+
+    pub fn commitment(&self) -> &MerkleCommitment {
+        &self.commitment
+    }
+
+    /// Has the given peer served too many invalid parts
+    pub fn is_banned(&self, peer: &NodeId) -> bool {
+        self.invalid_parts.get(peer).copied().unwrap_or(0) > self.max_invalid_parts
+    }
+
+    /// Verify a part (identified by its digest) served by the given peer
+    pub fn verify_part(&mut self, from: NodeId, part_digest: &Digest, proof: &MerkleProof) -> PartVerdict {
+        if self.is_banned(&from) {
+            return PartVerdict::PeerBanned;
+        }
+
+        if self.commitment.verify(part_digest, proof) {
+            return PartVerdict::Valid;
+        }
+
+        warn!("Peer {:?} served state part {} which is not included in the agreed commitment", from, proof.index);
+
+        *self.invalid_parts.entry(from).or_insert(0) += 1;
+
+        PartVerdict::Invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atlas_common::crypto::hash::{Context, Digest};
+    use atlas_common::node_id::NodeId;
+
+    use super::{MerkleProof, MerkleTree, PartVerdict, PartVerifier};
+
+    fn part_digest(part: u8) -> Digest {
+        let mut context = Context::new();
+
+        context.update(&[part]);
+
+        context.finish()
+    }
+
+    fn part_digests(count: u8) -> Vec<Digest> {
+        (0..count).map(part_digest).collect()
+    }
+
+    #[test]
+    fn every_part_is_proven() {
+        // Covers the odd levels, whose last node is promoted
+        for count in 1..=9 {
+            let digests = part_digests(count);
+
+            let tree = MerkleTree::from_part_digests(&digests).unwrap();
+            let commitment = tree.commitment();
+
+            assert_eq!(commitment.leaf_count(), count as u64);
+
+            for (index, digest) in digests.iter().enumerate() {
+                let proof = tree.proof(index as u64).unwrap();
+
+                assert!(commitment.verify(digest, &proof), "Part {} of {} does not verify", index, count);
+            }
+
+            assert!(tree.proof(count as u64).is_none());
+        }
+    }
+
+    #[test]
+    fn single_part() {
+        let tree = MerkleTree::from_part_digests(&part_digests(1)).unwrap();
+
+        let proof = tree.proof(0).unwrap();
+
+        assert!(proof.siblings().is_empty());
+        assert!(tree.commitment().verify(&part_digest(0), &proof));
+        assert!(!tree.commitment().verify(&part_digest(1), &proof));
+    }
+
+    #[test]
+    fn no_parts() {
+        assert!(MerkleTree::from_part_digests(&[]).is_err());
+    }
+
+    #[test]
+    fn wrong_part_or_position_is_rejected() {
+        let digests = part_digests(5);
+
+        let tree = MerkleTree::from_part_digests(&digests).unwrap();
+        let commitment = tree.commitment();
+
+        // Another part with this part's proof
+        assert!(!commitment.verify(&digests[1], &tree.proof(0).unwrap()));
+
+        // The right part, claimed to be at another position
+        let moved = MerkleProof { index: 1, siblings: tree.proof(0).unwrap().siblings().clone() };
+
+        assert!(!commitment.verify(&digests[0], &moved));
+
+        // A position outside of the commitment
+        let outside = MerkleProof { index: 5, siblings: tree.proof(4).unwrap().siblings().clone() };
+
+        assert!(!commitment.verify(&digests[4], &outside));
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let digests = part_digests(6);
+
+        let tree = MerkleTree::from_part_digests(&digests).unwrap();
+        let commitment = tree.commitment();
+
+        let proof = tree.proof(2).unwrap();
+
+        assert!(commitment.verify(&digests[2], &proof));
+
+        let mut tampered = proof.clone();
+        tampered.siblings[1] = part_digest(0xFF);
+
+        assert!(!commitment.verify(&digests[2], &tampered));
+
+        let mut truncated = proof.clone();
+        truncated.siblings.pop();
+
+        assert!(!commitment.verify(&digests[2], &truncated));
+
+        let mut extended = proof.clone();
+        extended.siblings.push(part_digest(0xFF));
+
+        assert!(!commitment.verify(&digests[2], &extended));
+    }
+
+    #[test]
+    fn commitment_binds_the_number_of_parts() {
+        let three = MerkleTree::from_part_digests(&part_digests(3)).unwrap();
+        let two = MerkleTree::from_part_digests(&part_digests(2)).unwrap();
+
+        assert_ne!(three.commitment().root(), two.commitment().root());
+
+        // A proof from a tree over a prefix of the parts does not verify against the full tree
+        assert!(!three.commitment().verify(&part_digest(0), &two.proof(0).unwrap()));
+    }
+
+    #[test]
+    fn peers_serving_invalid_parts_are_banned() {
+        let digests = part_digests(4);
+
+        let tree = MerkleTree::from_part_digests(&digests).unwrap();
+
+        let mut verifier = PartVerifier::new(tree.commitment(), 1);
+
+        let honest = NodeId::from(1);
+        let faulty = NodeId::from(2);
+
+        assert_eq!(verifier.verify_part(honest, &digests[0], &tree.proof(0).unwrap()), PartVerdict::Valid);
+
+        assert_eq!(verifier.verify_part(faulty, &digests[0], &tree.proof(1).unwrap()), PartVerdict::Invalid);
+        assert!(!verifier.is_banned(&faulty));
+
+        assert_eq!(verifier.verify_part(faulty, &digests[2], &tree.proof(3).unwrap()), PartVerdict::Invalid);
+        assert!(verifier.is_banned(&faulty));
+
+        // Once banned, even its valid parts are no longer considered
+        assert_eq!(verifier.verify_part(faulty, &digests[3], &tree.proof(3).unwrap()), PartVerdict::PeerBanned);
+
+        assert!(!verifier.is_banned(&honest));
+    }
+}
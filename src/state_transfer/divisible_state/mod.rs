@@ -9,6 +9,8 @@ use crate::persistent_log::DivisibleStateLog;
 use crate::state_transfer::StateTransferProtocol;
 use crate::timeouts::Timeouts;
 
+pub mod merkle;
+
 pub trait DivisibleStateTransfer<S, NT, PL>: StateTransferProtocol<S, NT, PL>
     where S: DivisibleState + 'static,
           PL: DivisibleStateLog<S> {
@@ -23,6 +25,9 @@ pub trait DivisibleStateTransfer<S, NT, PL>: StateTransferProtocol<S, NT, PL>
     /// Handle having received a state from the application
     /// you should also notify the ordering protocol that the state has been received
     /// and processed, so he is now safe to delete the state (Maybe this should be handled by the replica?)
+    ///
+    /// The parts can be committed to with a [merkle::MerkleTree], whose commitment is carried in the descriptor,
+    /// so the receivers can verify each part as it arrives (see [merkle::PartVerifier])
     fn handle_state_received_from_app<V>(&mut self, view: V,
                                          descriptor: S::StateDescriptor,
                                          state: Vec<S::StatePart>) -> Result<()>
@@ -16,6 +16,9 @@ use crate::messages::StateTransfer;
 use crate::ordering_protocol::{ExecutionResult, OrderingProtocol};
 use crate::ordering_protocol::networking::serialize::NetworkView;
 use crate::persistent_log::StatefulOrderingProtocolLog;
+use crate::request_pre_processing::RequestPreProcessor;
+use crate::request_pre_processing::latest_ops::LatestOpsSnapshot;
+use crate::smr::exec::reply_cache::{ReplyCache, ReplyCacheSnapshot};
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 use crate::timeouts::RqTimeout;
 
@@ -27,12 +30,60 @@ pub mod networking;
 ///
 /// Contains the last application state, as well as the sequence number
 /// which decided the last batch of requests executed before the checkpoint.
+/// It can also carry the latest operation decided for each client session and the
+/// last reply sent to each of them, so the request deduplication and the answers to
+/// retransmitted requests survive restarts and state transfers.
+///
+/// The session state is not covered by the digest of the checkpoint: each replica snapshots it
+/// asynchronously from the checkpoint, so correct replicas can carry different session state for the
+/// same checkpoint. It is installed as received (see [monolithic_state::install_checkpoint]), which is
+/// safe for the replies since clients only accept a reply once a quorum of replicas agrees on it.
 #[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
 #[derive(Clone)]
 pub struct Checkpoint<S> {
     seq: SeqNo,
     app_state: S,
     digest: Digest,
+    #[cfg_attr(feature = "serialize_serde", serde(default))]
+    latest_ops: LatestOpsSnapshot,
+    #[cfg_attr(feature = "serialize_serde", serde(default))]
+    replies: ReplyCacheSnapshot,
+}
+
+/// Installs the client session state carried by a checkpoint, when the checkpoint is installed
+/// after a state transfer (see [monolithic_state::install_checkpoint])
+pub trait SessionStateInstaller: Send + Sync {
+    fn install_latest_ops(&self, latest_ops: LatestOpsSnapshot) -> Result<()>;
+
+    fn install_replies(&self, replies: ReplyCacheSnapshot) -> Result<()>;
+}
+
+/// The session state of a replica: the latest operations known to the request pre processor
+/// and, if the replica caches its replies, the reply cache
+pub struct SessionState<D> where D: ApplicationData {
+    pre_processor: RequestPreProcessor<D::Request>,
+    reply_cache: Option<Arc<ReplyCache<D>>>,
+}
+
+impl<D> SessionState<D> where D: ApplicationData {
+    pub fn new(pre_processor: RequestPreProcessor<D::Request>, reply_cache: Option<Arc<ReplyCache<D>>>) -> Self {
+        Self { pre_processor, reply_cache }
+    }
+}
+
+impl<D> SessionStateInstaller for SessionState<D> where D: ApplicationData + 'static {
+    fn install_latest_ops(&self, latest_ops: LatestOpsSnapshot) -> Result<()> {
+        self.pre_processor.install_latest_ops(latest_ops);
+
+        Ok(())
+    }
+
+    fn install_replies(&self, replies: ReplyCacheSnapshot) -> Result<()> {
+        match &self.reply_cache {
+            Some(reply_cache) => reply_cache.install(replies),
+            None => Ok(())
+        }
+    }
 }
 
 impl<S> Orderable for Checkpoint<S> {
@@ -45,10 +96,20 @@ impl<S> Orderable for Checkpoint<S> {
 
 impl<S> Checkpoint<S> {
     pub fn new(seq: SeqNo, app_state: S, digest: Digest) -> Arc<ReadOnly<Self>> {
+        Self::new_with_sessions(seq, app_state, digest, Default::default(), Default::default())
+    }
+
+    /// Create a checkpoint that also stores the state of the client sessions: the latest decided operations,
+    /// as given by [crate::request_pre_processing::RequestPreProcessor::snapshot_latest_ops] and the cached replies,
+    /// as given by [crate::smr::exec::reply_cache::ReplyCache::snapshot].
+    pub fn new_with_sessions(seq: SeqNo, app_state: S, digest: Digest,
+                             latest_ops: LatestOpsSnapshot, replies: ReplyCacheSnapshot) -> Arc<ReadOnly<Self>> {
         Arc::new(ReadOnly::new(Self {
             seq,
             app_state,
             digest,
+            latest_ops,
+            replies,
         }))
     }
 
@@ -57,6 +118,8 @@ impl<S> Checkpoint<S> {
             seq,
             app_state,
             digest,
+            latest_ops: Default::default(),
+            replies: Default::default(),
         }
     }
 
@@ -73,6 +136,16 @@ impl<S> Checkpoint<S> {
 
     pub fn digest(&self) -> &Digest { &self.digest }
 
+    /// The latest decided operations of the client sessions at the time of this checkpoint.
+    /// These are installed in the request pre processor along with the checkpoint
+    /// (see [monolithic_state::install_checkpoint])
+    pub fn latest_ops(&self) -> &LatestOpsSnapshot { &self.latest_ops }
+
+    /// The replies cached at the time of this checkpoint.
+    /// These are installed in the reply cache along with the checkpoint
+    /// (see [monolithic_state::install_checkpoint])
+    pub fn replies(&self) -> &ReplyCacheSnapshot { &self.replies }
+
     /// Returns the inner values within this local checkpoint.
     pub fn into_inner(self) -> (SeqNo, S, Digest) {
         (self.seq, self.app_state, self.digest)
@@ -0,0 +1,734 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+#[cfg(feature = "serialize_serde")]
+use serde::{Deserialize, Serialize};
+
+use atlas_common::crypto::hash::{Context, Digest};
+use atlas_common::error::*;
+use atlas_common::node_id::NodeId;
+use atlas_common::ordering::{Orderable, SeqNo};
+
+/// The default size of each chunk of a checkpoint
+pub const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// The configuration of a chunked checkpoint transfer
+#[derive(Clone, Debug)]
+pub struct ChunkedTransferConfig {
+    /// The size of each chunk (the last chunk might be smaller)
+    pub chunk_size: usize,
+    /// How many chunks we can be waiting on from each peer
+    pub max_in_flight_per_peer: usize,
+    /// How long we wait for a chunk before requesting it from another peer
+    pub chunk_timeout: Duration,
+    /// After how many timed out or invalid chunks a peer is no longer asked for chunks
+    pub max_peer_failures: usize,
+}
+
+impl Default for ChunkedTransferConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_in_flight_per_peer: 4,
+            chunk_timeout: Duration::from_secs(10),
+            max_peer_failures: 3,
+        }
+    }
+}
+
+/// The manifest of a serialized checkpoint, split into chunks.
+///
+/// Describes the checkpoint (its sequence number and digest) along with the digest of each of its
+/// chunks, so every chunk can be verified on its own as soon as it is received.
+///
+/// Replicas agree on the checkpoint digest (which binds the application state and the session state),
+/// not on how they serialize the checkpoint, so the chunk digests of the same checkpoint can differ from
+/// one replica to the other. The manifest must be trusted before the transfer starts, by at least f + 1
+/// replicas sending the very same manifest (see [ManifestCollector]), and the reassembled checkpoint is
+/// checked against it, re-hashing its application state (see [ChunkedTransfer::into_checkpoint])
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct CheckpointManifest {
+    seq: SeqNo,
+    checkpoint_digest: Digest,
+    total_length: u64,
+    chunk_size: u32,
+    chunk_digests: Vec<Digest>,
+}
+
+/// A chunk of a serialized checkpoint
+#[cfg_attr(feature = "serialize_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct CheckpointChunk {
+    seq: SeqNo,
+    index: u32,
+    data: Vec<u8>,
+}
+
+/// A checkpoint that is being served in chunks to the other replicas
+pub struct ChunkedCheckpoint {
+    manifest: CheckpointManifest,
+    serialized: Arc<[u8]>,
+}
+
+/// Collects the manifests sent by the other replicas, accepting a manifest once enough of them sent the very same one
+pub struct ManifestCollector {
+    seq: SeqNo,
+    quorum: usize,
+    /// The peers which sent each manifest (by the digest of the manifest)
+    votes: HashMap<Digest, BTreeSet<NodeId>>,
+    /// The manifest sent by each peer
+    manifests: BTreeMap<NodeId, CheckpointManifest>,
+}
+
+/// The outcome of receiving a chunk
+#[derive(Debug, Eq, PartialEq)]
+pub enum ChunkOutcome {
+    /// The chunk was valid and stored
+    Accepted,
+    /// We already had this chunk
+    Duplicate,
+    /// The chunk does not match the manifest, so the peer which sent it is no longer asked for chunks
+    Invalid,
+}
+
+/// A chunk which was requested from a peer and not yet received
+struct InFlightChunk {
+    peer: NodeId,
+    requested_at: Instant,
+}
+
+/// The receiving side of a chunked checkpoint transfer.
+///
+/// The chunks are fetched from several peers in parallel. The received chunks are kept when a
+/// request times out (or when the transfer is resumed with the same manifest), so only the
+/// missing chunks are requested again instead of restarting the transfer from zero
+pub struct ChunkedTransfer {
+    config: ChunkedTransferConfig,
+    manifest: CheckpointManifest,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    in_flight: BTreeMap<u32, InFlightChunk>,
+    peer_failures: BTreeMap<NodeId, usize>,
+}
+
+impl CheckpointManifest {
+    pub fn seq(&self) -> SeqNo {
+        self.seq
+    }
+
+    /// The digest of the checkpoint
+    pub fn checkpoint_digest(&self) -> &Digest {
+        &self.checkpoint_digest
+    }
+
+    pub fn total_length(&self) -> u64 {
+        self.total_length
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_digests.len()
+    }
+
+    pub fn chunk_digests(&self) -> &Vec<Digest> {
+        &self.chunk_digests
+    }
+
+    /// The digest of the manifest itself. Peers which sent the same manifest serialized
+    /// the checkpoint in the same way, so they can all serve its chunks
+    pub fn digest(&self) -> Digest {
+        let mut context = Context::new();
+
+        context.update(&u32::from(self.seq).to_le_bytes());
+        context.update(self.checkpoint_digest.as_ref());
+        context.update(&self.total_length.to_le_bytes());
+        context.update(&self.chunk_size.to_le_bytes());
+
+        for digest in &self.chunk_digests {
+            context.update(digest.as_ref());
+        }
+
+        context.finish()
+    }
+
+    /// The length the chunk with the given index must have
+    fn expected_chunk_length(&self, index: u32) -> u64 {
+        let start = index as u64 * self.chunk_size as u64;
+
+        (self.total_length - start).min(self.chunk_size as u64)
+    }
+
+    /// Check that the manifest is consistent (the number of chunks matches the total length)
+    fn is_well_formed(&self) -> bool {
+        if self.chunk_size == 0 {
+            return false;
+        }
+
+        let expected_chunks = self.total_length.div_ceil(self.chunk_size as u64);
+
+        expected_chunks == self.chunk_digests.len() as u64
+    }
+}
+
+impl Orderable for CheckpointManifest {
+    fn sequence_number(&self) -> SeqNo {
+        self.seq
+    }
+}
+
+impl CheckpointChunk {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Orderable for CheckpointChunk {
+    fn sequence_number(&self) -> SeqNo {
+        self.seq
+    }
+}
+
+fn chunk_digest(index: u32, data: &[u8]) -> Digest {
+    let mut context = Context::new();
+
+    // The index is part of the digest, so chunks can't be served in the wrong place
+    context.update(&index.to_le_bytes());
+    context.update(data);
+
+    context.finish()
+}
+
+impl ChunkedCheckpoint {
+    /// Split a serialized checkpoint into chunks of the given size
+    pub fn new(seq: SeqNo, checkpoint_digest: Digest, serialized: Arc<[u8]>, chunk_size: usize) -> Result<Self> {
+        if chunk_size == 0 || chunk_size > u32::MAX as usize {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "Invalid checkpoint chunk size"));
+        }
+
+        let chunk_digests = serialized.chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| chunk_digest(index as u32, chunk))
+            .collect();
+
+        let manifest = CheckpointManifest {
+            seq,
+            checkpoint_digest,
+            total_length: serialized.len() as u64,
+            chunk_size: chunk_size as u32,
+            chunk_digests,
+        };
+
+        Ok(Self { manifest, serialized })
+    }
+
+    /// Serialize a checkpoint (with the postcard backend) and split it into chunks
+    #[cfg(feature = "serialize_postcard")]
+    pub fn from_checkpoint<S>(checkpoint: &crate::state_transfer::Checkpoint<S>, chunk_size: usize) -> Result<Self> where S: Serialize {
+        let mut serialized = Vec::new();
+
+        crate::serialize::postcard::serialize_checkpoint(&mut serialized, checkpoint)?;
+
+        Self::new(checkpoint.sequence_number(), checkpoint.digest().clone(), Arc::from(serialized), chunk_size)
+    }
+
+    pub fn manifest(&self) -> &CheckpointManifest {
+        &self.manifest
+    }
+
+    /// The chunk with the given index, to be sent to a peer which requested it
+    pub fn chunk(&self, index: u32) -> Option<CheckpointChunk> {
+        let chunk_size = self.manifest.chunk_size as usize;
+
+        self.serialized.chunks(chunk_size)
+            .nth(index as usize)
+            .map(|data| CheckpointChunk {
+                seq: self.manifest.seq,
+                index,
+                data: data.to_vec(),
+            })
+    }
+}
+
+impl ManifestCollector {
+    /// Collect the manifests of the checkpoint with the given sequence number.
+    /// The quorum should be at least f + 1, so at least one correct replica vouches for the manifest
+    pub fn new(seq: SeqNo, quorum: usize) -> Self {
+        Self {
+            seq,
+            quorum,
+            votes: Default::default(),
+            manifests: Default::default(),
+        }
+    }
+
+    /// Register the manifest sent by a given peer, returning the manifest to transfer the checkpoint with
+    /// once a quorum of peers sent the very same manifest.
+    ///
+    /// The whole manifest is voted on (and not only the checkpoint digest), so a single faulty peer can't
+    /// pick the chunk digests the checkpoint is transferred with. Since the chunk digests depend on how each
+    /// replica serialized the checkpoint, this needs a quorum of replicas which serialized it in the same way.
+    /// Every peer which sent the returned manifest can serve its chunks (see [Self::peers_serving])
+    pub fn register(&mut self, from: NodeId, manifest: CheckpointManifest) -> Option<CheckpointManifest> {
+        if manifest.seq != self.seq || !manifest.is_well_formed() {
+            warn!("Received an invalid checkpoint manifest from {:?}", from);
+
+            return None;
+        }
+
+        // Each peer only gets to vouch for a single manifest
+        if self.manifests.contains_key(&from) {
+            return None;
+        }
+
+        let manifest_digest = manifest.digest();
+
+        self.manifests.insert(from, manifest);
+
+        let voters = self.votes.entry(manifest_digest).or_default();
+
+        voters.insert(from);
+
+        if voters.len() < self.quorum {
+            return None;
+        }
+
+        self.manifests.get(&from).cloned()
+    }
+
+    /// The peers which sent the given manifest, and are therefore able to serve its chunks
+    pub fn peers_serving(&self, manifest: &CheckpointManifest) -> Vec<NodeId> {
+        let digest = manifest.digest();
+
+        self.manifests.iter()
+            .filter(|(_, sent)| sent.digest() == digest)
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+}
+
+impl ChunkedTransfer {
+    /// Start the transfer of the checkpoint described by the given manifest, which is rejected if it is malformed
+    pub fn new(config: ChunkedTransferConfig, manifest: CheckpointManifest) -> Result<Self> {
+        if !manifest.is_well_formed() {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "Malformed checkpoint manifest"));
+        }
+
+        let chunk_count = manifest.chunk_count();
+
+        Ok(Self {
+            config,
+            manifest,
+            chunks: vec![None; chunk_count],
+            received: 0,
+            in_flight: Default::default(),
+            peer_failures: Default::default(),
+        })
+    }
+
+    /// Resume a transfer with a (possibly) new manifest, keeping the chunks we have already received
+    /// which are still valid under it. When the manifest is the same, nothing is lost
+    pub fn resume(self, manifest: CheckpointManifest) -> Result<Self> {
+        let mut resumed = Self::new(self.config, manifest)?;
+
+        if resumed.manifest.chunk_size != self.manifest.chunk_size {
+            return Ok(resumed);
+        }
+
+        for (index, chunk) in self.chunks.into_iter().enumerate() {
+            let chunk = match chunk {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+
+            let still_valid = resumed.manifest.chunk_digests.get(index)
+                .map(|digest| *digest == self.manifest.chunk_digests[index])
+                .unwrap_or(false);
+
+            if still_valid {
+                resumed.chunks[index] = Some(chunk);
+                resumed.received += 1;
+            }
+        }
+
+        debug!("Resuming the transfer of checkpoint {:?} with {} of {} chunks", resumed.manifest.seq,
+            resumed.received, resumed.chunks.len());
+
+        Ok(resumed)
+    }
+
+    pub fn manifest(&self) -> &CheckpointManifest {
+        &self.manifest
+    }
+
+    /// The number of chunks received and the total number of chunks
+    pub fn progress(&self) -> (usize, usize) {
+        (self.received, self.chunks.len())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received == self.chunks.len()
+    }
+
+    /// Can the given peer still be asked for chunks
+    fn is_usable(&self, peer: &NodeId) -> bool {
+        self.peer_failures.get(peer).copied().unwrap_or(0) < self.config.max_peer_failures
+    }
+
+    fn fail_peer(&mut self, peer: NodeId) {
+        *self.peer_failures.entry(peer).or_insert(0) += 1;
+    }
+
+    /// Assign the missing chunks which are not yet being fetched to the given peers,
+    /// spreading them so each peer has at most the configured amount of chunks in flight.
+    /// Returns the chunks that should be requested from each peer
+    pub fn next_requests(&mut self, peers: &[NodeId]) -> BTreeMap<NodeId, Vec<u32>> {
+        let mut requests: BTreeMap<NodeId, Vec<u32>> = BTreeMap::new();
+
+        let mut load: BTreeMap<NodeId, usize> = BTreeMap::new();
+
+        for in_flight in self.in_flight.values() {
+            *load.entry(in_flight.peer).or_insert(0) += 1;
+        }
+
+        let peers: Vec<NodeId> = peers.iter().copied().filter(|peer| self.is_usable(peer)).collect();
+
+        if peers.is_empty() {
+            return requests;
+        }
+
+        let now = Instant::now();
+
+        let missing: Vec<u32> = (0..self.chunks.len() as u32)
+            .filter(|index| self.chunks[*index as usize].is_none() && !self.in_flight.contains_key(index))
+            .collect();
+
+        let mut next_peer = 0;
+
+        for index in missing {
+            // Find the next peer (round robin) which still has room for another chunk
+            let peer = (0..peers.len())
+                .map(|offset| peers[(next_peer + offset) % peers.len()])
+                .find(|peer| load.get(peer).copied().unwrap_or(0) < self.config.max_in_flight_per_peer);
+
+            let peer = match peer {
+                Some(peer) => peer,
+                None => break,
+            };
+
+            next_peer = (peers.iter().position(|p| *p == peer).unwrap() + 1) % peers.len();
+
+            *load.entry(peer).or_insert(0) += 1;
+
+            self.in_flight.insert(index, InFlightChunk { peer, requested_at: now });
+
+            requests.entry(peer).or_insert_with(Vec::new).push(index);
+        }
+
+        requests
+    }
+
+    /// Receive a chunk from a given peer, verifying it against the manifest
+    pub fn receive_chunk(&mut self, from: NodeId, chunk: CheckpointChunk) -> ChunkOutcome {
+        let index = chunk.index;
+
+        let expected_digest = match self.manifest.chunk_digests.get(index as usize) {
+            Some(digest) if chunk.seq == self.manifest.seq => digest,
+            _ => {
+                warn!("Received a chunk from {:?} which is not part of checkpoint {:?}", from, self.manifest.seq);
+
+                self.fail_peer(from);
+
+                return ChunkOutcome::Invalid;
+            }
+        };
+
+        if self.chunks[index as usize].is_some() {
+            return ChunkOutcome::Duplicate;
+        }
+
+        if chunk.data.len() as u64 != self.manifest.expected_chunk_length(index)
+            || chunk_digest(index, &chunk.data) != *expected_digest {
+            warn!("Received an invalid chunk {} of checkpoint {:?} from {:?}", index, self.manifest.seq, from);
+
+            self.fail_peer(from);
+
+            // Let the chunk be requested from another peer
+            if self.in_flight.get(&index).map(|in_flight| in_flight.peer == from).unwrap_or(false) {
+                self.in_flight.remove(&index);
+            }
+
+            return ChunkOutcome::Invalid;
+        }
+
+        self.in_flight.remove(&index);
+
+        self.chunks[index as usize] = Some(chunk.data);
+        self.received += 1;
+
+        ChunkOutcome::Accepted
+    }
+
+    /// Release the chunks whose requests have timed out, so they can be requested from other peers.
+    /// The chunks we have already received are kept. Returns the peers which did not deliver in time
+    pub fn handle_timeout(&mut self) -> Vec<NodeId> {
+        let timeout = self.config.chunk_timeout;
+
+        let timed_out: Vec<(u32, NodeId)> = self.in_flight.iter()
+            .filter(|(_, in_flight)| in_flight.requested_at.elapsed() >= timeout)
+            .map(|(index, in_flight)| (*index, in_flight.peer))
+            .collect();
+
+        let mut peers = BTreeSet::new();
+
+        for (index, peer) in timed_out {
+            self.in_flight.remove(&index);
+
+            if peers.insert(peer) {
+                self.fail_peer(peer);
+            }
+        }
+
+        peers.into_iter().collect()
+    }
+
+    /// Reassemble the serialized checkpoint, once all of the chunks have been received
+    pub fn assemble(self) -> Result<Vec<u8>> {
+        if !self.is_complete() {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "Not all of the checkpoint chunks have been received"));
+        }
+
+        let mut serialized = Vec::with_capacity(self.manifest.total_length as usize);
+
+        for chunk in self.chunks.into_iter() {
+            serialized.extend_from_slice(&chunk.unwrap());
+        }
+
+        Ok(serialized)
+    }
+
+    /// Reassemble and deserialize the checkpoint (serialized with the postcard backend),
+    /// checking that it is the checkpoint described by the manifest.
+    ///
+    /// The digest carried by the checkpoint is only a claim of whoever serialized it, so the application
+    /// state is hashed again with the given (application supplied) digest function, and the checkpoint
+    /// is only accepted if that matches the checkpoint digest of the manifest
+    #[cfg(feature = "serialize_postcard")]
+    pub fn into_checkpoint<S, F>(self, digest_state: F) -> Result<crate::state_transfer::Checkpoint<S>>
+        where S: for<'a> Deserialize<'a>, F: FnOnce(&S) -> Result<Digest> {
+        let seq = self.manifest.seq;
+        let digest = self.manifest.checkpoint_digest.clone();
+
+        let checkpoint: crate::state_transfer::Checkpoint<S> = crate::serialize::postcard::deserialize_checkpoint(&self.assemble()?)?;
+
+        if checkpoint.sequence_number() != seq || *checkpoint.digest() != digest {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "The received checkpoint does not match its manifest"));
+        }
+
+        if digest_state(checkpoint.state())? != digest {
+            return Err(Error::simple_with_msg(ErrorKind::Communication, "The state of the received checkpoint does not match its digest"));
+        }
+
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use atlas_common::crypto::hash::{Context, Digest};
+    use atlas_common::node_id::NodeId;
+    use atlas_common::ordering::SeqNo;
+
+    use super::{CheckpointChunk, ChunkedCheckpoint, ChunkedTransfer, ChunkedTransferConfig, ChunkOutcome, ManifestCollector};
+
+    const CHUNK_SIZE: usize = 4;
+
+    fn digest_of(bytes: &[u8]) -> Digest {
+        let mut context = Context::new();
+
+        context.update(bytes);
+
+        context.finish()
+    }
+
+    /// A checkpoint of 10 bytes, split into 3 chunks (the last one of 2 bytes)
+    fn checkpoint(fill: u8) -> ChunkedCheckpoint {
+        let serialized: Vec<u8> = (0..10).map(|byte| byte + fill).collect();
+
+        ChunkedCheckpoint::new(SeqNo::from(5), digest_of(&[0x11]), Arc::from(serialized), CHUNK_SIZE).unwrap()
+    }
+
+    fn config(chunk_timeout: Duration) -> ChunkedTransferConfig {
+        ChunkedTransferConfig {
+            chunk_size: CHUNK_SIZE,
+            max_in_flight_per_peer: 2,
+            chunk_timeout,
+            max_peer_failures: 1,
+        }
+    }
+
+    #[test]
+    fn manifest_needs_a_quorum_of_identical_manifests() {
+        let served = checkpoint(0);
+        // The same checkpoint digest, with chunks a faulty peer made up
+        let forged = checkpoint(0x80);
+
+        let mut collector = ManifestCollector::new(SeqNo::from(5), 2);
+
+        assert!(collector.register(NodeId::from(0), forged.manifest().clone()).is_none());
+
+        // Two peers vouch for the checkpoint digest, but not with the same manifest
+        assert!(collector.register(NodeId::from(1), served.manifest().clone()).is_none());
+
+        // A peer can't vouch twice
+        assert!(collector.register(NodeId::from(0), served.manifest().clone()).is_none());
+
+        let manifest = collector.register(NodeId::from(2), served.manifest().clone())
+            .expect("Two peers sent the same manifest");
+
+        assert_eq!(manifest.digest(), served.manifest().digest());
+        assert_eq!(collector.peers_serving(&manifest), vec![NodeId::from(1), NodeId::from(2)]);
+    }
+
+    #[test]
+    fn chunks_are_spread_and_reassembled() {
+        let served = checkpoint(0);
+
+        let mut transfer = ChunkedTransfer::new(config(Duration::from_secs(60)), served.manifest().clone()).unwrap();
+
+        let requests = transfer.next_requests(&[NodeId::from(1), NodeId::from(2)]);
+
+        assert_eq!(requests[&NodeId::from(1)], vec![0, 2]);
+        assert_eq!(requests[&NodeId::from(2)], vec![1]);
+
+        // Every chunk is already in flight
+        assert!(transfer.next_requests(&[NodeId::from(1), NodeId::from(2)]).is_empty());
+
+        for (peer, indexes) in requests {
+            for index in indexes {
+                assert_eq!(transfer.receive_chunk(peer, served.chunk(index).unwrap()), ChunkOutcome::Accepted);
+            }
+        }
+
+        assert_eq!(transfer.receive_chunk(NodeId::from(1), served.chunk(0).unwrap()), ChunkOutcome::Duplicate);
+
+        assert!(transfer.is_complete());
+        assert_eq!(transfer.assemble().unwrap(), (0..10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn timed_out_chunks_are_reassigned() {
+        let served = checkpoint(0);
+
+        let mut transfer = ChunkedTransfer::new(config(Duration::ZERO), served.manifest().clone()).unwrap();
+
+        let requests = transfer.next_requests(&[NodeId::from(1)]);
+
+        assert_eq!(requests[&NodeId::from(1)], vec![0, 1]);
+
+        assert_eq!(transfer.receive_chunk(NodeId::from(1), served.chunk(0).unwrap()), ChunkOutcome::Accepted);
+
+        // Chunk 1 was not delivered in time, so it is released and the peer is no longer asked for chunks
+        assert_eq!(transfer.handle_timeout(), vec![NodeId::from(1)]);
+
+        let requests = transfer.next_requests(&[NodeId::from(1), NodeId::from(2)]);
+
+        assert!(!requests.contains_key(&NodeId::from(1)));
+        assert_eq!(requests[&NodeId::from(2)], vec![1, 2]);
+
+        // The chunk received before the timeout was kept
+        assert_eq!(transfer.progress(), (1, 3));
+    }
+
+    #[test]
+    fn invalid_chunks_are_rejected_and_requested_again() {
+        let served = checkpoint(0);
+
+        let mut transfer = ChunkedTransfer::new(config(Duration::from_secs(60)), served.manifest().clone()).unwrap();
+
+        let requests = transfer.next_requests(&[NodeId::from(1)]);
+
+        assert_eq!(requests[&NodeId::from(1)], vec![0, 1]);
+
+        // The data of chunk 0 served as chunk 1
+        let mut misplaced = served.chunk(0).unwrap();
+        misplaced.index = 1;
+
+        assert_eq!(transfer.receive_chunk(NodeId::from(1), misplaced), ChunkOutcome::Invalid);
+
+        // A chunk which is not part of the checkpoint
+        let unknown = CheckpointChunk { seq: SeqNo::from(5), index: 7, data: vec![0; CHUNK_SIZE] };
+
+        assert_eq!(transfer.receive_chunk(NodeId::from(2), unknown), ChunkOutcome::Invalid);
+
+        // Both peers failed, so only a new peer is asked for the rejected chunk (and the one never requested)
+        let requests = transfer.next_requests(&[NodeId::from(1), NodeId::from(2), NodeId::from(3)]);
+
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[&NodeId::from(3)], vec![1, 2]);
+
+        assert_eq!(transfer.progress(), (0, 3));
+    }
+
+    #[test]
+    fn resume_keeps_the_chunks_which_are_still_valid() {
+        let served = checkpoint(0);
+
+        let mut transfer = ChunkedTransfer::new(config(Duration::from_secs(60)), served.manifest().clone()).unwrap();
+
+        transfer.next_requests(&[NodeId::from(1)]);
+
+        assert_eq!(transfer.receive_chunk(NodeId::from(1), served.chunk(0).unwrap()), ChunkOutcome::Accepted);
+        assert_eq!(transfer.receive_chunk(NodeId::from(1), served.chunk(1).unwrap()), ChunkOutcome::Accepted);
+
+        // Resuming with the same manifest loses nothing, and the chunks in flight can be requested again
+        let mut transfer = transfer.resume(served.manifest().clone()).unwrap();
+
+        assert_eq!(transfer.progress(), (2, 3));
+        assert_eq!(transfer.next_requests(&[NodeId::from(2)])[&NodeId::from(2)], vec![2]);
+
+        // A manifest whose first chunk differs only keeps the second one
+        let mut changed: Vec<u8> = (0..10).collect();
+        changed[0] = 0xFF;
+
+        let reserialized = ChunkedCheckpoint::new(SeqNo::from(5), digest_of(&[0x11]), Arc::from(changed), CHUNK_SIZE).unwrap();
+
+        let transfer = transfer.resume(reserialized.manifest().clone()).unwrap();
+
+        assert_eq!(transfer.progress(), (1, 3));
+    }
+
+    #[test]
+    #[cfg(feature = "serialize_postcard")]
+    fn checkpoint_state_is_hashed_again() {
+        use crate::state_transfer::Checkpoint;
+
+        let state = vec![0x42u8; 9];
+
+        let honest = Checkpoint::new(SeqNo::from(5), state.clone(), digest_of(&state));
+        // A checkpoint claiming the honest digest for another state
+        let forged = Checkpoint::new(SeqNo::from(5), vec![0x66u8; 9], digest_of(&state));
+
+        for (checkpoint, accepted) in [(honest, true), (forged, false)] {
+            let served = ChunkedCheckpoint::from_checkpoint(&**checkpoint, CHUNK_SIZE).unwrap();
+
+            let mut transfer = ChunkedTransfer::new(config(Duration::from_secs(60)), served.manifest().clone()).unwrap();
+
+            for index in 0..served.manifest().chunk_count() as u32 {
+                assert_eq!(transfer.receive_chunk(NodeId::from(1), served.chunk(index).unwrap()), ChunkOutcome::Accepted);
+            }
+
+            let result = transfer.into_checkpoint(|state: &Vec<u8>| Ok(digest_of(state)));
+
+            assert_eq!(result.is_ok(), accepted);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use log::error;
+
 use atlas_common::channel::ChannelSyncTx;
 use atlas_common::error::*;
 use atlas_common::globals::ReadOnly;
@@ -7,9 +9,11 @@ use atlas_execution::state::monolithic_state::{InstallStateMessage, MonolithicSt
 use crate::ordering_protocol::networking::serialize::NetworkView;
 
 use crate::persistent_log::MonolithicStateLog;
-use crate::state_transfer::{Checkpoint, StateTransferProtocol};
+use crate::state_transfer::{Checkpoint, SessionStateInstaller, StateTransferProtocol};
 use crate::timeouts::Timeouts;
 
+pub mod chunked;
+
 pub trait MonolithicStateTransfer<S, NT, PL>: StateTransferProtocol<S, NT, PL>
     where S: MonolithicState + 'static,
           PL: MonolithicStateLog<S> {
@@ -25,6 +29,30 @@ pub trait MonolithicStateTransfer<S, NT, PL>: StateTransferProtocol<S, NT, PL>
     /// Handle having received a state from the application
     /// you should also notify the ordering protocol that the state has been received
     /// and processed, so he is now safe to delete the state (Maybe this should be handled by the replica?)
+    ///
+    /// Large states should not be sent as a single message. They can instead be served in chunks
+    /// (see [chunked::ChunkedCheckpoint]) and fetched from several replicas with [chunked::ChunkedTransfer]
     fn handle_state_received_from_app<V>(&mut self, view: V, state: Arc<ReadOnly<Checkpoint<S>>>) -> Result<()>
         where V: NetworkView;
-}
\ No newline at end of file
+}
+
+/// Install a checkpoint received from other replicas (whose digest has already been checked against the
+/// one the replicas agreed on), through the executor handle the protocol was initialized with.
+/// The session state it carries is installed with the given installer (usually part of the protocol's configuration),
+/// after which the application state is handed to the executor
+pub fn install_checkpoint<S>(checkpoint: Checkpoint<S>, sessions: &dyn SessionStateInstaller,
+                             executor_handle: &ChannelSyncTx<InstallStateMessage<S>>) -> Result<()>
+    where S: MonolithicState + 'static {
+    sessions.install_latest_ops(checkpoint.latest_ops().clone())?;
+    sessions.install_replies(checkpoint.replies().clone())?;
+
+    let (_, state, _) = checkpoint.into_inner();
+
+    if let Err(err) = executor_handle.send(InstallStateMessage::new(state)) {
+        error!("Failed to deliver the installed checkpoint to the executor: {:?}", err);
+
+        return Err(Error::simple_with_msg(ErrorKind::Communication, "Failed to deliver the installed checkpoint to the executor"));
+    }
+
+    Ok(())
+}
@@ -3,6 +3,8 @@ pub mod serialize;
 
 use std::collections::BTreeMap;
 
+use log::error;
+
 use atlas_common::crypto::hash::Digest;
 use atlas_common::error::*;
 use atlas_common::node_id::NodeId;
@@ -10,13 +12,15 @@ use atlas_communication::FullNetworkNode;
 use atlas_communication::message::{SerializedMessage, StoredMessage, StoredSerializedProtocolMessage};
 use atlas_communication::protocol_node::ProtocolNetworkNode;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
-use atlas_communication::serialize::Serializable;
+use atlas_communication::serialize::{Buf, Serializable};
 use atlas_execution::serialize::ApplicationData;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
 
-use crate::messages::SystemMessage;
+use crate::messages::authenticator::{authenticator_for, MessageClass};
+use crate::messages::{StateTransfer, SystemMessage};
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
-use crate::serialize::Service;
+use crate::serialize::compression::CompressionRequest;
+use crate::serialize::{compress_message, Service};
 use crate::smr::networking::NodeWrap;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 
@@ -47,10 +51,32 @@ pub trait StateTransferSendNode<STM> where STM: StateTransferMessage {
     /// on the success of the message dispatch
     fn broadcast_signed(&self, message: STM::StateTransferMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>>;
 
+    /// Sends a message to a given target, authenticated as dictated by our [AuthenticationPolicy](crate::messages::authenticator::AuthenticationPolicy):
+    /// either signed or carrying an [Authenticator](crate::messages::authenticator::Authenticator) with the MAC for the target.
+    /// Does not block on the message sent. Returns a result that is
+    /// Ok if there is a current connection to the target or err if not. No other checks are made
+    /// on the success of the message dispatch
+    fn send_authenticated(&self, message: STM::StateTransferMessage, target: NodeId, flush: bool) -> Result<()>;
+
+    /// Broadcast a message to all of the given targets, authenticated as dictated by our policy
+    /// (a single authenticator carries the MACs for all of the targets).
+    /// Does not block on the message sent. Returns a result that is
+    /// Ok if there is a current connection to the targets or err if not. No other checks are made
+    /// on the success of the message dispatch
+    fn broadcast_authenticated(&self, message: STM::StateTransferMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>>;
+
     /// Serialize a message to a given target.
     /// Creates the serialized byte buffer along with the header, so we can send it later.
     fn serialize_digest_message(&self, message: STM::StateTransferMessage) -> Result<(SerializedMessage<STM::StateTransferMessage>, Digest)>;
 
+    /// Serialize a message to a given target, compressing its encoding with the given compression
+    /// (usually the one negotiated with the target, see [PeerCompressionTable](crate::serialize::compression::PeerCompressionTable))
+    /// if it is large enough. Meant for the large messages, such as checkpoints.
+    /// The returned digest (which the header must be signed over) is the digest of the bytes which are sent,
+    /// so it's the digest of the compressed bytes if the message was compressed, since that is what the receiver's
+    /// network layer checks the payload against
+    fn serialize_digest_compressed_message(&self, message: STM::StateTransferMessage, compression: Option<CompressionRequest>) -> Result<(SerializedMessage<STM::StateTransferMessage>, Digest)>;
+
     /// Broadcast the serialized messages provided.
     /// Does not block on the message sent. Returns a result that is
     /// Ok if there is a current connection to the targets or err if not. No other checks are made
@@ -91,6 +117,46 @@ impl<NT, D, P, S, L, NI, RM> StateTransferSendNode<S> for NodeWrap<NT, D, P, S,
         self.0.broadcast_signed(SystemMessage::from_state_transfer_message(message), targets)
     }
 
+    fn send_authenticated(&self, message: S::StateTransferMessage, target: NodeId, flush: bool) -> Result<()> {
+        let authentication = match authenticator_for(MessageClass::StateTransfer) {
+            Some(authentication) => authentication,
+            None => return self.0.send_signed(SystemMessage::from_state_transfer_message(message), target, flush),
+        };
+
+        let message = StateTransfer::new(message);
+
+        let digest = Service::<D, P, S, L>::state_authentication_digest(self.0.id(), message.wire_version(), message.payload())?;
+
+        let authenticator = authentication.authenticate(&digest, std::iter::once(target))?;
+
+        self.0.send(SystemMessage::StateTransferMessage(message.with_authenticator(Some(authenticator))), target, flush)
+    }
+
+    fn broadcast_authenticated(&self, message: S::StateTransferMessage, targets: impl Iterator<Item=NodeId>) -> std::result::Result<(), Vec<NodeId>> {
+        let authentication = match authenticator_for(MessageClass::StateTransfer) {
+            Some(authentication) => authentication,
+            None => return self.0.broadcast_signed(SystemMessage::from_state_transfer_message(message), targets),
+        };
+
+        let targets: Vec<NodeId> = targets.collect();
+
+        let message = StateTransfer::new(message);
+
+        let authenticator = Service::<D, P, S, L>::state_authentication_digest(self.0.id(), message.wire_version(), message.payload())
+            .and_then(|digest| authentication.authenticate(&digest, targets.iter().copied()));
+
+        match authenticator {
+            Ok(authenticator) => {
+                self.0.broadcast(SystemMessage::StateTransferMessage(message.with_authenticator(Some(authenticator))), targets.into_iter())
+            }
+            Err(err) => {
+                error!("Failed to authenticate state transfer message: {:?}", err);
+
+                Err(targets)
+            }
+        }
+    }
+
     #[inline(always)]
     fn serialize_digest_message(&self, message: S::StateTransferMessage) -> Result<(SerializedMessage<S::StateTransferMessage>, Digest)> {
         let (message, digest) = self.0.serialize_digest_message(SystemMessage::from_state_transfer_message(message))?;
@@ -102,6 +168,25 @@ impl<NT, D, P, S, L, NI, RM> StateTransferSendNode<S> for NodeWrap<NT, D, P, S,
         Ok((SerializedMessage::new(message, bytes), digest))
     }
 
+    #[inline(always)]
+    fn serialize_digest_compressed_message(&self, message: S::StateTransferMessage, compression: Option<CompressionRequest>) -> Result<(SerializedMessage<S::StateTransferMessage>, Digest)> {
+        let (message, digest) = self.0.serialize_digest_message(SystemMessage::from_state_transfer_message(message))?;
+
+        let (message, bytes) = message.into_inner();
+
+        let compressed = match compression {
+            Some(compression) => compress_message(&bytes[..], &compression)?,
+            None => None,
+        };
+
+        let (bytes, digest) = match compressed {
+            Some((compressed, compressed_digest)) => (Buf::from(compressed), compressed_digest),
+            None => (bytes, digest),
+        };
+
+        Ok((SerializedMessage::new(message.into_state_tranfer_message(), bytes), digest))
+    }
+
     #[inline(always)]
     fn broadcast_serialized(&self, messages: BTreeMap<NodeId, StoredSerializedProtocolMessage<S::StateTransferMessage>>) -> std::result::Result<(), Vec<NodeId>> {
         let mut map = BTreeMap::new();
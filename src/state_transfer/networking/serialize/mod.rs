@@ -1,8 +1,12 @@
 use std::sync::Arc;
 use atlas_common::error::*;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use atlas_communication::message::Header;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
+#[cfg(feature = "serialize_capnp")]
+use crate::serialize::versioning::WireVersion;
+#[cfg(feature = "serialize_serde")]
+use crate::serialize::versioning::VersionedDeserialize;
 use crate::state_transfer::networking::signature_ver::StateTransferVerificationHelper;
 
 /// The abstraction for state transfer protocol messages.
@@ -12,17 +16,24 @@ pub trait StateTransferMessage: Send + Sync  {
     type StateTransferMessage: Send + Clone;
 
     #[cfg(feature = "serialize_serde")]
-    type StateTransferMessage: for<'a> Deserialize<'a> + Serialize + Send + Clone;
+    type StateTransferMessage: VersionedDeserialize + Serialize + Send + Clone;
 
-    /// Verify the message and return the message if it is valid
+    /// Verify the message, without taking ownership of it
     fn verify_state_message<NI, SVH>(network_info: &Arc<NI>,
                                           header: &Header,
-                                          message: Self::StateTransferMessage) -> Result<(bool, Self::StateTransferMessage)>
+                                          message: &Self::StateTransferMessage) -> Result<bool>
         where NI: NetworkInformationProvider, SVH: StateTransferVerificationHelper;
 
     #[cfg(feature = "serialize_capnp")]
-    fn serialize_capnp(builder: febft_capnp::cst_messages_capnp::cst_message::Builder, msg: &Self::StateTransferMessage) -> Result<()>;
+    fn serialize_capnp(builder: capnp::any_pointer::Builder, msg: &Self::StateTransferMessage) -> Result<()>;
 
     #[cfg(feature = "serialize_capnp")]
-    fn deserialize_capnp(reader: febft_capnp::cst_messages_capnp::cst_message::Reader) -> Result<Self::StateTransferMessage>;
+    fn deserialize_capnp(reader: capnp::any_pointer::Reader) -> Result<Self::StateTransferMessage>;
+
+    /// Decode a message encoded by a peer which is still running the previous wire version.
+    /// See [OrderingProtocolMessage::deserialize_previous_capnp](crate::ordering_protocol::networking::serialize::OrderingProtocolMessage::deserialize_previous_capnp)
+    #[cfg(feature = "serialize_capnp")]
+    fn deserialize_previous_capnp(_version: WireVersion, _reader: capnp::any_pointer::Reader) -> Result<Self::StateTransferMessage> {
+        Err(Error::simple_with_msg(ErrorKind::CommunicationSerialize, "No decoder for the state transfer messages of the previous wire version"))
+    }
 }
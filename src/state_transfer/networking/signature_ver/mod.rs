@@ -1,15 +1,27 @@
+use atlas_common::crypto::hash::Digest;
+use atlas_common::error::*;
+use atlas_communication::message::Header;
 use atlas_communication::message_signing::NetworkMessageSignatureVerifier;
 use atlas_communication::reconfiguration_node::NetworkInformationProvider;
 use atlas_execution::serialize::ApplicationData;
 use crate::log_transfer::networking::serialize::LogTransferMessage;
+use crate::messages::authenticator::{Authenticator, MacKeyProvider};
 use crate::messages::signature_ver::SigVerifier;
 use crate::ordering_protocol::networking::serialize::OrderingProtocolMessage;
 use crate::serialize::Service;
 use crate::state_transfer::networking::serialize::StateTransferMessage;
 
 /// State transfer messages don't really need internal verifications, since the entire message is signed
-/// and the signature is verified by the network layer (by verifying the entire image)
-pub trait StateTransferVerificationHelper {}
+/// and the signature is verified by the network layer (by verifying the entire image).
+/// The exception are the messages which are authenticated with an [Authenticator] instead
+pub trait StateTransferVerificationHelper {
+    /// helper to verify messages sent in the [AuthenticationMode::Authenticator](crate::messages::authenticator::AuthenticationMode::Authenticator) mode,
+    /// which carry a vector of MACs (over the given authentication digest) instead of a signature
+    fn verify_authenticator<K>(keys: &K, header: &Header, digest: &Digest, authenticator: &Authenticator) -> Result<bool>
+        where K: MacKeyProvider + ?Sized {
+        Ok(authenticator.verify(keys, header.from(), digest))
+    }
+}
 
 impl<SV, NI, D, OP, LT, ST> StateTransferVerificationHelper for SigVerifier<SV, NI, D, OP, ST, LT>
     where D: ApplicationData + 'static,
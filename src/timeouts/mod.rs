@@ -105,7 +105,7 @@ impl Timeouts {
     pub fn new<D: ApplicationData + 'static>(node_id: NodeId, iteration_delay: Duration,
                                              default_timeout: Duration,
                                              loopback_channel: ChannelSyncTx<Message>) -> Self {
-        launch_orchestrator_thread::<WDRoundRobin, D>(2, node_id, default_timeout, loopback_channel)
+        launch_orchestrator_thread::<WDRoundRobin, D>(WDRoundRobin, 2, node_id, default_timeout, loopback_channel)
     }
 
     /// Start a timeout request on the list of digests that have been provided
@@ -225,16 +225,19 @@ struct TimeoutOrchestrator<WP, D> {
 
     worker_channel: Vec<ChannelSyncTx<TimeoutWorkerMessage>>,
 
-    work_partition: PhantomData<(WP, D)>,
+    work_partition: WP,
+
+    _phantom: PhantomData<D>,
 }
 
 impl<WP, D> TimeoutOrchestrator<WP, D> {
-    fn new(worker_count: u32, work_rx: ChannelSyncRx<TimeoutMessage>, workers: Vec<ChannelSyncTx<TimeoutWorkerMessage>>) -> Self {
+    fn new(work_partition: WP, worker_count: u32, work_rx: ChannelSyncRx<TimeoutMessage>, workers: Vec<ChannelSyncTx<TimeoutWorkerMessage>>) -> Self {
         Self {
             worker_count,
             work_rx,
             worker_channel: workers,
-            work_partition: Default::default(),
+            work_partition,
+            _phantom: Default::default(),
         }
     }
 
@@ -284,7 +287,7 @@ impl<WP, D> TimeoutOrchestrator<WP, D> {
         for timeout in timeout_info {
             match &timeout {
                 TimeoutKind::ClientRequestTimeout(client_rq) => {
-                    let worker = WP::get_worker_for_processed(client_rq, self.worker_count as usize);
+                    let worker = self.work_partition.get_worker_for_processed(client_rq, self.worker_count as usize);
 
                     separated_vecs[worker].push(timeout);
                 }
@@ -311,7 +314,7 @@ impl<WP, D> TimeoutOrchestrator<WP, D> {
                 let mut separated_vecs: Vec<Vec<ClientRqInfo>> = self.init_worker_separated_vec(|| Vec::with_capacity(messages.len()));
 
                 for recvd_rq in messages {
-                    let worker = WP::get_worker_for_processed(&recvd_rq, self.worker_count as usize);
+                    let worker = self.work_partition.get_worker_for_processed(&recvd_rq, self.worker_count as usize);
 
                     separated_vecs[worker].push(recvd_rq);
                 }
@@ -351,7 +354,7 @@ impl<WP, D> TimeoutOrchestrator<WP, D> {
 
         if let Some(timeouts) = clear_timeouts {
             for rq in timeouts {
-                let worker = WP::get_worker_for_processed(&rq, self.worker_count as usize);
+                let worker = self.work_partition.get_worker_for_processed(&rq, self.worker_count as usize);
 
                 separated_vecs[worker].as_mut().unwrap().push(rq);
             }
@@ -417,7 +420,7 @@ impl TimeoutPhase {
     }
 }
 
-fn launch_orchestrator_thread<WP, D>(worker_count: u32, node_id: NodeId, timeout_dur: Duration, loopback: ChannelSyncTx<Message>) -> Timeouts
+fn launch_orchestrator_thread<WP, D>(work_partition: WP, worker_count: u32, node_id: NodeId, timeout_dur: Duration, loopback: ChannelSyncTx<Message>) -> Timeouts
     where D: ApplicationData + 'static,
           WP: WorkPartitioner<D::Request> + 'static {
     let (tx, rx) = channel::new_bounded_sync(CHANNEL_SIZE);
@@ -430,7 +433,7 @@ fn launch_orchestrator_thread<WP, D>(worker_count: u32, node_id: NodeId, timeout
         workers.push(worker);
     }
 
-    let orchestrator: TimeoutOrchestrator<WP, D> = TimeoutOrchestrator::new(worker_count, rx, workers);
+    let orchestrator: TimeoutOrchestrator<WP, D> = TimeoutOrchestrator::new(work_partition, worker_count, rx, workers);
 
     std::thread::Builder::new()
         .name(format!("Timeout-Orchestrator"))